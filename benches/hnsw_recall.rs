@@ -0,0 +1,116 @@
+//! Recall and latency of the HNSW index against exact search.
+//!
+//! Run with `cargo bench --bench hnsw_recall`. The corpus size defaults to
+//! 20k vectors and can be changed with `HNSW_BENCH_VECTORS`.
+
+use buildli::config::HnswConfig;
+use buildli::vector::hnsw::Hnsw;
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use std::time::Instant;
+
+const DIMENSIONS: usize = 128;
+const QUERIES: usize = 200;
+const TOP_K: usize = 10;
+
+/// Deterministic xorshift so runs are comparable.
+struct Rng(u64);
+
+impl Rng {
+    fn next_f32(&mut self) -> f32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 40) as f32 / (1u64 << 24) as f32 - 0.5
+    }
+
+    fn next_index(&mut self, len: usize) -> usize {
+        ((self.next_f32() + 0.5) * len as f32) as usize % len
+    }
+}
+
+fn random_vectors(count: usize, rng: &mut Rng) -> Vec<Vec<f32>> {
+    (0..count)
+        .map(|_| (0..DIMENSIONS).map(|_| rng.next_f32()).collect())
+        .collect()
+}
+
+/// Points scattered around `centers`, which is closer to real embeddings
+/// than uniform noise.
+fn clustered(centers: &[Vec<f32>], count: usize, rng: &mut Rng) -> Vec<Vec<f32>> {
+    (0..count)
+        .map(|_| {
+            let center = &centers[rng.next_index(centers.len())];
+            center.iter().map(|x| x + rng.next_f32() * 0.5).collect()
+        })
+        .collect()
+}
+
+fn cosine(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm_a: f32 = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norm_b: f32 = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+    dot / (norm_a * norm_b)
+}
+
+fn exact_top_k(vectors: &[Vec<f32>], query: &[f32]) -> Vec<u32> {
+    let mut scored: Vec<(f32, u32)> = vectors
+        .iter()
+        .enumerate()
+        .map(|(id, vector)| (cosine(query, vector), id as u32))
+        .collect();
+    scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+    scored.into_iter().take(TOP_K).map(|(_, id)| id).collect()
+}
+
+fn bench_hnsw(c: &mut Criterion) {
+    let count: usize = std::env::var("HNSW_BENCH_VECTORS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(20_000);
+
+    let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+    let centers = random_vectors(256, &mut rng);
+    let vectors = clustered(&centers, count, &mut rng);
+    let queries = clustered(&centers, QUERIES, &mut rng);
+    let config = HnswConfig::default();
+
+    let started = Instant::now();
+    let mut graph = Hnsw::new(&config);
+    for id in 0..count as u32 {
+        graph.insert(id, vectors.as_slice()).unwrap();
+    }
+    println!("built HNSW over {} vectors in {:.2?}", count, started.elapsed());
+
+    for ef in [16, 32, 64, 128, 256] {
+        let mut hits = 0;
+        for query in &queries {
+            let expected = exact_top_k(&vectors, query);
+            let found = graph.search(query, TOP_K, ef, vectors.as_slice(), |_| true);
+            hits += found.iter().filter(|(id, _)| expected.contains(id)).count();
+        }
+        println!("ef={:<4} recall@{} = {:.3}", ef, TOP_K, hits as f64 / (QUERIES * TOP_K) as f64);
+    }
+
+    let mut group = c.benchmark_group(format!("search_{}", count));
+    let mut next = 0;
+    group.bench_function("hnsw", |b| {
+        b.iter(|| {
+            next = (next + 1) % QUERIES;
+            black_box(graph.search(&queries[next], TOP_K, config.ef_search, vectors.as_slice(), |_| true))
+        })
+    });
+    group.bench_function("exact", |b| {
+        b.iter(|| {
+            next = (next + 1) % QUERIES;
+            black_box(exact_top_k(&vectors, &queries[next]))
+        })
+    });
+    group.finish();
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(20);
+    targets = bench_hnsw
+}
+criterion_main!(benches);
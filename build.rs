@@ -27,7 +27,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     
     tonic_build::configure()
         .build_server(true)
-        .build_client(false)
+        .build_client(true)
         .out_dir(out_dir)
         .compile_protos(&["proto/buildli.proto"], &["proto"])?;
         
@@ -1,4 +1,8 @@
-use tonic::Request;
+use std::io::Write;
+use tonic::{
+    metadata::{Ascii, MetadataValue},
+    Request,
+};
 
 // Include the generated proto code
 pub mod buildli {
@@ -13,14 +17,20 @@ use buildli::{
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Connect to the gRPC server
-    let mut client = BuildliServiceClient::connect("http://localhost:9091").await?;
+    let mut client = BuildliServiceClient::connect("http://localhost:8081").await?;
     println!("✓ Connected to buildli gRPC server");
 
+    // Send the token from BUILDLI_TOKEN, if the server requires one
+    let authorization: Option<MetadataValue<Ascii>> = match std::env::var("BUILDLI_TOKEN") {
+        Ok(token) => Some(format!("Bearer {}", token).parse()?),
+        Err(_) => None,
+    };
+
     // Test index status
     println!("\n1. Testing index status...");
-    let request = Request::new(IndexStatusRequest {
+    let request = authorized(IndexStatusRequest {
         paths: vec![],
-    });
+    }, &authorization);
     
     let response = client.index_status(request).await?;
     let status = response.into_inner();
@@ -33,29 +43,31 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     // Test query (requires API key to be configured)
     println!("\n2. Testing query...");
-    let request = Request::new(QueryRequest {
+    let request = authorized(QueryRequest {
         question: "How does the CLI parsing work?".to_string(),
         top_k: 5,
         repos: vec![],
         languages: vec![],
-    });
+    }, &authorization);
     
     match client.query(request).await {
         Ok(response) => {
             let mut stream = response.into_inner();
             
-            println!("Query results:");
+            // The first message carries the references, the rest the answer
+            // one token at a time.
             while let Some(result) = stream.message().await? {
-                if !result.chunk.is_empty() {
-                    println!("Answer: {}", result.chunk);
-                }
                 if !result.references.is_empty() {
-                    println!("\nReferences:");
+                    println!("References:");
                     for reference in result.references {
                         println!("  - {}:{}", reference.file_path, reference.line_start);
                     }
+                    print!("\nAnswer: ");
                 }
+                print!("{}", result.chunk);
+                std::io::stdout().flush()?;
             }
+            println!();
         }
         Err(e) => {
             println!("Query error (expected if no API key configured): {}", e);
@@ -65,14 +77,15 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+fn authorized<T>(message: T, authorization: &Option<MetadataValue<Ascii>>) -> Request<T> {
+    let mut request = Request::new(message);
+    if let Some(value) = authorization {
+        request.metadata_mut().insert("authorization", value.clone());
+    }
+    request
+}
+
 // To run this example:
-// 1. Add to Cargo.toml:
-//    [[example]]
-//    name = "grpc_client"
-//    required-features = []
-//
-// 2. Add these dependencies to Cargo.toml:
-//    [dependencies]
-//    tonic = "0.12"
-//
-// 3. Run: cargo run --example grpc_client
\ No newline at end of file
+// 1. Start the server: buildli serve (gRPC listens on port + 1, default 8081)
+// 2. Run: BUILDLI_TOKEN=<token> cargo run --example grpc_client
+//    (BUILDLI_TOKEN can be left out if the server has no tokens configured)
@@ -0,0 +1,232 @@
+use crate::{
+    embeddings::EmbeddingProvider,
+    patch::{self, Patch},
+    query::{CodeReference, QueryEngine},
+    vector::{SearchFilter, SearchResult, VectorStore},
+    BuildliError, Result,
+};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tracing::debug;
+
+const SYSTEM_PROMPT: &str = "You are an expert software engineer who diagnoses bugs and writes minimal, correct fixes.";
+
+/// Number of extra lines read from disk around every retrieved chunk so the
+/// model sees enough surrounding code to produce matching diff context.
+const CONTEXT_PADDING: usize = 3;
+
+pub struct BugSolver<E: EmbeddingProvider, V: VectorStore> {
+    engine: Arc<QueryEngine<E, V>>,
+    workspace_root: PathBuf,
+    top_k: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BugSolution {
+    pub explanation: String,
+    pub patch: Option<String>,
+    pub affected_files: Vec<String>,
+    pub references: Vec<CodeReference>,
+    /// Set when the model produced a diff that does not apply to the files on disk.
+    pub validation_error: Option<String>,
+}
+
+impl<E: EmbeddingProvider, V: VectorStore> BugSolver<E, V> {
+    pub fn new(engine: Arc<QueryEngine<E, V>>, workspace_root: PathBuf) -> Self {
+        Self {
+            engine,
+            workspace_root,
+            top_k: 8,
+        }
+    }
+
+    pub fn with_top_k(mut self, top_k: usize) -> Self {
+        self.top_k = top_k;
+        self
+    }
+
+    pub fn workspace_root(&self) -> &Path {
+        &self.workspace_root
+    }
+
+    pub async fn solve(&self, description: &str) -> Result<BugSolution> {
+        let (results, prompt) = self.prepare(description).await?;
+        let answer = self.engine.llm_client().chat(SYSTEM_PROMPT, &prompt).await?;
+        self.finish(&results, &prompt, answer).await
+    }
+
+    /// Like [`solve`](Self::solve), but forwards the model's explanation to
+    /// `on_delta` as it is generated.
+    pub async fn solve_streaming<F>(&self, description: &str, on_delta: F) -> Result<BugSolution>
+    where
+        F: FnMut(&str) + Send,
+    {
+        let (results, prompt) = self.prepare(description).await?;
+        let answer = self
+            .engine
+            .llm_client()
+            .stream_chat(SYSTEM_PROMPT, &prompt, on_delta)
+            .await?;
+        self.finish(&results, &prompt, answer).await
+    }
+
+    async fn prepare(&self, description: &str) -> Result<(Vec<SearchResult>, String)> {
+        debug!("Solving bug: {}", description);
+
+        let results = self.engine.retrieve(description, self.top_k, &SearchFilter::default()).await?;
+        if results.is_empty() {
+            return Err(BuildliError::Query(
+                "No relevant code found for this bug. Has the codebase been indexed?".to_string(),
+            ));
+        }
+
+        let prompt = format!(
+            "A user reported the following bug:\n\
+            \n\
+            {}\n\
+            \n\
+            Relevant code from the repository (each line is prefixed with its line number and '| ', \
+            which is NOT part of the file):\n{}\n\
+            \n\
+            First explain the root cause in a few sentences. Then provide the fix as a single unified diff \
+            inside a ```diff fenced block. Use '--- a/<path>' and '+++ b/<path>' headers with the file paths \
+            exactly as shown above, correct '@@' line numbers, and at least 3 lines of unchanged context \
+            around every change. Only modify what is necessary to fix the bug.",
+            description,
+            self.build_context(&results)
+        );
+
+        Ok((results, prompt))
+    }
+
+    async fn finish(&self, results: &[SearchResult], prompt: &str, answer: String) -> Result<BugSolution> {
+        let references = self.engine.extract_references(results);
+        let explanation = match answer.find("```diff").or_else(|| answer.find("```patch")) {
+            Some(index) => answer[..index].trim_end().to_string(),
+            None => answer.trim_end().to_string(),
+        };
+
+        let Some(diff) = patch::extract_diff_block(&answer) else {
+            return Ok(BugSolution {
+                explanation,
+                patch: None,
+                affected_files: vec![],
+                references,
+                validation_error: Some("The model did not produce a diff".to_string()),
+            });
+        };
+
+        let (diff, validation) = match self.validate(&diff) {
+            Ok(patch) => (diff, Ok(patch)),
+            Err(e) => {
+                // LLM diffs are often slightly off; give the model one chance to repair it.
+                debug!("Generated patch failed validation: {}", e);
+                let repaired = self.repair(prompt, &diff, &e).await?;
+                match repaired {
+                    Some(repaired) => {
+                        let validation = self.validate(&repaired);
+                        (repaired, validation)
+                    }
+                    None => (diff, Err(e)),
+                }
+            }
+        };
+
+        let (affected_files, validation_error) = match validation {
+            Ok(patch) => (patch.affected_files(), None),
+            Err(e) => (
+                Patch::parse(&diff).map(|p| p.affected_files()).unwrap_or_default(),
+                Some(e.to_string()),
+            ),
+        };
+
+        Ok(BugSolution {
+            explanation,
+            patch: Some(diff),
+            affected_files,
+            references,
+            validation_error,
+        })
+    }
+
+    fn validate(&self, diff: &str) -> Result<Patch> {
+        let patch = Patch::parse(diff)?;
+        patch.check(&self.workspace_root)?;
+        Ok(patch)
+    }
+
+    async fn repair(&self, prompt: &str, diff: &str, error: &BuildliError) -> Result<Option<String>> {
+        let repair_prompt = format!(
+            "{}\n\
+            \n\
+            You previously proposed this diff:\n\
+            ```diff\n{}```\n\
+            \n\
+            It does not apply to the files on disk: {}\n\
+            \n\
+            Reply with only a corrected unified diff inside a ```diff fenced block. Context and removed \
+            lines must match the file contents exactly.",
+            prompt, diff, error
+        );
+
+        let answer = self.engine.llm_client().chat(SYSTEM_PROMPT, &repair_prompt).await?;
+        Ok(patch::extract_diff_block(&answer))
+    }
+
+    fn build_context(&self, results: &[SearchResult]) -> String {
+        let mut context = String::new();
+
+        for result in results {
+            let Some(file_path) = result.metadata.get("file_path").and_then(|v| v.as_str()) else {
+                continue;
+            };
+            let line_start = result.metadata.get("line_start").and_then(|v| v.as_u64()).unwrap_or(1) as usize;
+            let line_end = result.metadata.get("line_end").and_then(|v| v.as_u64()).unwrap_or(line_start as u64) as usize;
+
+            context.push_str(&format!("\nFile: {}\n```\n", self.display_path(file_path)));
+
+            // Prefer the live file so line numbers match what the diff will be applied to.
+            match std::fs::read_to_string(self.resolve_path(file_path)) {
+                Ok(content) => {
+                    let lines: Vec<&str> = content.lines().collect();
+                    let start = line_start.saturating_sub(CONTEXT_PADDING + 1);
+                    let end = (line_end + CONTEXT_PADDING).min(lines.len());
+                    for (offset, line) in lines[start.min(end)..end].iter().enumerate() {
+                        context.push_str(&format!("{:>5}| {}\n", start + offset + 1, line));
+                    }
+                }
+                Err(_) => {
+                    if let Some(content) = result.metadata.get("content").and_then(|v| v.as_str()) {
+                        context.push_str(content);
+                        context.push('\n');
+                    }
+                }
+            }
+
+            context.push_str("```\n");
+        }
+
+        context
+    }
+
+    fn resolve_path(&self, file_path: &str) -> PathBuf {
+        let path = Path::new(file_path);
+        if path.is_absolute() {
+            path.to_path_buf()
+        } else {
+            self.workspace_root.join(path)
+        }
+    }
+
+    /// Path as the model should write it in diff headers: relative to the
+    /// workspace root and without a leading `./`.
+    fn display_path(&self, file_path: &str) -> String {
+        let path = Path::new(file_path);
+        let relative = path.strip_prefix(&self.workspace_root).unwrap_or(path);
+        relative
+            .to_string_lossy()
+            .trim_start_matches("./")
+            .to_string()
+    }
+}
@@ -1,4 +1,4 @@
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 use std::path::PathBuf;
 
 #[derive(Parser)]
@@ -26,11 +26,14 @@ pub enum Commands {
         #[arg(short, long, help = "Watch for changes and auto-reindex")]
         watch: bool,
 
-        #[arg(short, long, help = "Index from specific commit")]
+        #[arg(short, long, conflicts_with = "watch", help = "Index the tree of a specific commit without checking it out")]
         commit: Option<String>,
 
         #[arg(long, help = "Ignore test files")]
         ignore_tests: bool,
+
+        #[arg(long, value_enum, default_value = "bars", help = "How to report progress")]
+        progress: ProgressFormat,
     },
 
     #[command(about = "Query the indexed codebase with natural language")]
@@ -44,17 +47,17 @@ pub enum Commands {
         #[arg(long, help = "Output format (json for machine-readable)")]
         json: bool,
 
-        #[arg(short, long, help = "Filter by repository")]
+        #[arg(short, long, value_delimiter = ',', help = "Filter by repository")]
         repo: Option<Vec<String>>,
 
-        #[arg(short, long, help = "Filter by language")]
+        #[arg(short, long, value_delimiter = ',', help = "Filter by language (e.g. rust, python, ts)")]
         lang: Option<Vec<String>>,
     },
 
     #[command(about = "Analyze and solve bugs based on description")]
     Bug {
-        #[arg(short, long, help = "Bug description")]
-        desc: String,
+        #[arg(short, long, required_unless_present = "undo", help = "Bug description")]
+        desc: Option<String>,
 
         #[arg(long, help = "Apply the suggested patch")]
         apply: bool,
@@ -64,6 +67,9 @@ pub enum Commands {
 
         #[arg(long, help = "Disable streaming output")]
         no_stream: bool,
+
+        #[arg(long, conflicts_with_all = ["apply", "patch_file"], help = "Undo the most recently applied patch")]
+        undo: bool,
     },
 
     #[command(about = "Start gRPC/REST/SSE server")]
@@ -84,9 +90,46 @@ pub enum Commands {
         print: bool,
     },
 
+    #[command(about = "Re-index from scratch, or move the index to the configured embedding model")]
+    Reindex {
+        #[arg(help = "Paths to re-index (directories or files)", conflicts_with = "migrate_embeddings")]
+        paths: Vec<PathBuf>,
+
+        #[arg(long, help = "Re-embed the stored chunks with the configured embedding model instead of re-reading files")]
+        migrate_embeddings: bool,
+
+        #[arg(long, help = "Ignore test files")]
+        ignore_tests: bool,
+
+        #[arg(long, value_enum, default_value = "bars", help = "How to report progress")]
+        progress: ProgressFormat,
+    },
+
+    #[command(about = "Convert the local vector store to another quantization in place")]
+    ConvertStore {
+        #[arg(long, help = "Quantization method: none, int8 or pq")]
+        quantization: String,
+
+        #[arg(long, help = "Subvectors per vector for pq (default: one per 8 dimensions)")]
+        pq_subvectors: Option<usize>,
+
+        #[arg(long, help = "Drop full-precision vectors instead of keeping them to re-rank results")]
+        no_rerank: bool,
+    },
+
     #[command(about = "Update buildli to the latest version")]
     Update {
         #[arg(long, default_value = "stable", help = "Release channel")]
         channel: String,
     },
-}
\ No newline at end of file
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ProgressFormat {
+    /// Progress bars on the terminal
+    Bars,
+    /// A JSON progress snapshot per line, for CI logs
+    Json,
+    /// No progress output
+    None,
+}
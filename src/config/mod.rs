@@ -17,6 +17,15 @@ pub struct Config {
     
     #[serde(default)]
     pub embedding: EmbeddingConfig,
+    
+    #[serde(default)]
+    pub http: HttpConfig,
+    
+    #[serde(default)]
+    pub indexing: IndexingConfig,
+    
+    #[serde(default)]
+    pub server: ServerConfig,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -49,6 +58,44 @@ pub struct VectorConfig {
     
     #[serde(default = "default_collection_name")]
     pub collection_name: String,
+    
+    /// Approximate nearest-neighbor index of the local backend.
+    #[serde(default)]
+    pub hnsw: HnswConfig,
+    
+    /// Compression of vectors in the local backend.
+    #[serde(default)]
+    pub quantization: QuantizationConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuantizationConfig {
+    /// `none`, `int8` or `pq`.
+    #[serde(default = "default_quantization_method")]
+    pub method: String,
+    
+    /// Subvectors per vector for `pq`; 0 picks one per 8 dimensions.
+    #[serde(default)]
+    pub pq_subvectors: usize,
+    
+    /// Keep full-precision vectors on disk to re-rank the best quantized matches exactly.
+    #[serde(default = "default_true")]
+    pub rerank: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HnswConfig {
+    /// Links per node; higher improves recall at the cost of memory and build time.
+    #[serde(default = "default_hnsw_m")]
+    pub m: usize,
+    
+    /// Candidate list size while inserting.
+    #[serde(default = "default_hnsw_ef_construction")]
+    pub ef_construction: usize,
+    
+    /// Candidate list size while searching.
+    #[serde(default = "default_hnsw_ef_search")]
+    pub ef_search: usize,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -63,6 +110,57 @@ pub struct EmbeddingConfig {
     pub batch_size: usize,
 }
 
+/// Parallelism of the indexing pipeline.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexingConfig {
+    /// Threads parsing files; 0 uses one per CPU.
+    #[serde(default)]
+    pub parse_workers: usize,
+    
+    /// Embedding requests in flight at once.
+    #[serde(default = "default_embedding_requests")]
+    pub embedding_requests: usize,
+}
+
+/// Retries and client-side rate limits for calls to the OpenAI API.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HttpConfig {
+    /// Retries of rate-limited (429), failed (5xx) or unsent requests.
+    #[serde(default = "default_max_retries")]
+    pub max_retries: u32,
+    
+    /// Requests sent per minute by each client; 0 means no limit.
+    #[serde(default)]
+    pub requests_per_minute: u32,
+    
+    /// Tokens sent per minute by each client; 0 means no limit.
+    #[serde(default)]
+    pub tokens_per_minute: u32,
+}
+
+/// Settings of `buildli serve`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ServerConfig {
+    /// Tokens accepted as `Authorization: Bearer <token>`. With none
+    /// configured and no `--token`, the API is open.
+    #[serde(default)]
+    pub tokens: Vec<ApiToken>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiToken {
+    /// Name logged for requests made with the token.
+    pub name: String,
+    
+    /// The token itself, or `env:VAR` to read it from the environment when
+    /// the server starts. The reference is kept as is in the config.
+    pub token: String,
+    
+    /// `read` to query and read index status, `admin` to also apply patches.
+    #[serde(default = "default_token_scope")]
+    pub scope: String,
+}
+
 impl Default for Config {
     fn default() -> Self {
         Self {
@@ -70,6 +168,9 @@ impl Default for Config {
             llm: LlmConfig::default(),
             vector: VectorConfig::default(),
             embedding: EmbeddingConfig::default(),
+            http: HttpConfig::default(),
+            indexing: IndexingConfig::default(),
+            server: ServerConfig::default(),
         }
     }
 }
@@ -99,10 +200,40 @@ impl Default for VectorConfig {
             backend: default_vector_backend(),
             url: default_vector_url(),
             collection_name: default_collection_name(),
+            hnsw: HnswConfig::default(),
+            quantization: QuantizationConfig::default(),
+        }
+    }
+}
+
+impl Default for QuantizationConfig {
+    fn default() -> Self {
+        Self {
+            method: default_quantization_method(),
+            pq_subvectors: 0,
+            rerank: true,
         }
     }
 }
 
+impl Default for HnswConfig {
+    fn default() -> Self {
+        Self {
+            m: default_hnsw_m(),
+            ef_construction: default_hnsw_ef_construction(),
+            ef_search: default_hnsw_ef_search(),
+        }
+    }
+}
+
+impl VectorConfig {
+    /// Key under which per-collection index state (manifests, lexical index)
+    /// is stored, so each backend and collection keeps its own.
+    pub fn index_key(&self) -> String {
+        format!("{}/{}", self.backend, self.collection_name)
+    }
+}
+
 impl Default for EmbeddingConfig {
     fn default() -> Self {
         Self {
@@ -113,6 +244,34 @@ impl Default for EmbeddingConfig {
     }
 }
 
+impl Default for IndexingConfig {
+    fn default() -> Self {
+        Self {
+            parse_workers: 0,
+            embedding_requests: default_embedding_requests(),
+        }
+    }
+}
+
+impl IndexingConfig {
+    pub fn parse_workers(&self) -> usize {
+        match self.parse_workers {
+            0 => std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+            workers => workers,
+        }
+    }
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            max_retries: default_max_retries(),
+            requests_per_minute: 0,
+            tokens_per_minute: 0,
+        }
+    }
+}
+
 fn default_index_root() -> Vec<PathBuf> {
     vec![PathBuf::from(".")]
 }
@@ -141,6 +300,26 @@ fn default_collection_name() -> String {
     "buildli".to_string()
 }
 
+fn default_quantization_method() -> String {
+    "none".to_string()
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_hnsw_m() -> usize {
+    16
+}
+
+fn default_hnsw_ef_construction() -> usize {
+    100
+}
+
+fn default_hnsw_ef_search() -> usize {
+    64
+}
+
 fn default_embedding_provider() -> String {
     "openai".to_string()
 }
@@ -153,6 +332,26 @@ fn default_batch_size() -> usize {
     100
 }
 
+fn default_embedding_requests() -> usize {
+    4
+}
+
+fn default_token_scope() -> String {
+    "read".to_string()
+}
+
+fn default_max_retries() -> u32 {
+    5
+}
+
+/// Directory where buildli keeps its local state (vector store, patch journal, ...).
+pub fn data_dir() -> Result<PathBuf> {
+    let project_dirs = ProjectDirs::from("", "", "buildli")
+        .context("Failed to determine project directories")?;
+    
+    Ok(project_dirs.data_dir().to_path_buf())
+}
+
 #[derive(Clone)]
 pub struct ConfigManager {
     config_path: PathBuf,
@@ -169,6 +368,15 @@ impl ConfigManager {
         Ok(Self { config_path })
     }
 
+    /// Manages the config file at `config_path` instead of the default location.
+    pub fn at(config_path: PathBuf) -> Self {
+        Self { config_path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.config_path
+    }
+
     pub async fn load(&self) -> Result<Config> {
         if !self.config_path.exists() {
             return Ok(Config::default());
@@ -212,9 +420,20 @@ impl ConfigManager {
             "vector.backend" => config.vector.backend = value.to_string(),
             "vector.url" => config.vector.url = value.to_string(),
             "vector.collection_name" => config.vector.collection_name = value.to_string(),
+            "vector.hnsw.m" => config.vector.hnsw.m = value.parse()?,
+            "vector.hnsw.ef_construction" => config.vector.hnsw.ef_construction = value.parse()?,
+            "vector.hnsw.ef_search" => config.vector.hnsw.ef_search = value.parse()?,
+            "vector.quantization.method" => config.vector.quantization.method = value.to_string(),
+            "vector.quantization.pq_subvectors" => config.vector.quantization.pq_subvectors = value.parse()?,
+            "vector.quantization.rerank" => config.vector.quantization.rerank = value.parse()?,
             "embedding.provider" => config.embedding.provider = value.to_string(),
             "embedding.model" => config.embedding.model = value.to_string(),
             "embedding.batch_size" => config.embedding.batch_size = value.parse()?,
+            "indexing.parse_workers" => config.indexing.parse_workers = value.parse()?,
+            "indexing.embedding_requests" => config.indexing.embedding_requests = value.parse()?,
+            "http.max_retries" => config.http.max_retries = value.parse()?,
+            "http.requests_per_minute" => config.http.requests_per_minute = value.parse()?,
+            "http.tokens_per_minute" => config.http.tokens_per_minute = value.parse()?,
             _ => anyhow::bail!("Unknown configuration key: {}", key),
         }
         
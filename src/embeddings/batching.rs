@@ -0,0 +1,166 @@
+//! Splits embedding inputs into requests that fit a provider's limits on
+//! tokens per input, inputs per request and tokens per request.
+
+use tiktoken_rs::{cl100k_base_singleton, CoreBPE};
+
+/// Part of an input text small enough to embed on its own.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Piece {
+    /// Index of the input text this piece was cut from.
+    pub input: usize,
+    pub text: String,
+    pub tokens: usize,
+}
+
+/// BPE encoding used by OpenAI's embedding models.
+fn tokenizer() -> &'static CoreBPE {
+    cl100k_base_singleton()
+}
+
+pub fn count_tokens(text: &str) -> usize {
+    tokenizer().encode_ordinary(text).len()
+}
+
+/// Cuts every text longer than `max_tokens` into consecutive pieces of at
+/// most that many tokens. Pieces are returned in input order.
+pub fn split(texts: &[String], max_tokens: usize) -> Vec<Piece> {
+    let bpe = tokenizer();
+    let max_tokens = max_tokens.max(1);
+    let mut pieces = Vec::with_capacity(texts.len());
+
+    for (input, text) in texts.iter().enumerate() {
+        let tokens = bpe.encode_ordinary(text);
+        if tokens.len() <= max_tokens {
+            pieces.push(Piece {
+                input,
+                text: text.clone(),
+                tokens: tokens.len(),
+            });
+            continue;
+        }
+
+        // Token bytes concatenate to the text, so decoding every run of
+        // `max_tokens` tokens cuts it into pieces. A run that ends inside a
+        // character does not decode and is shortened to the last boundary,
+        // or lengthened past it when the character alone is longer.
+        let mut start = 0;
+        while start < tokens.len() {
+            let mut end = (start + max_tokens).min(tokens.len());
+            let mut decoded = bpe.decode(tokens[start..end].to_vec());
+            while decoded.is_err() && end - start > 1 {
+                end -= 1;
+                decoded = bpe.decode(tokens[start..end].to_vec());
+            }
+            while decoded.is_err() && end < tokens.len() {
+                end += 1;
+                decoded = bpe.decode(tokens[start..end].to_vec());
+            }
+            if let Ok(text) = decoded {
+                pieces.push(Piece {
+                    input,
+                    text,
+                    tokens: end - start,
+                });
+            }
+            start = end;
+        }
+    }
+
+    pieces
+}
+
+/// Groups consecutive pieces into requests of at most `max_items` pieces and
+/// `max_tokens` tokens, returned as ranges into `pieces`.
+pub fn batches(pieces: &[Piece], max_items: usize, max_tokens: usize) -> Vec<std::ops::Range<usize>> {
+    let max_items = max_items.max(1);
+    let mut batches = Vec::new();
+    let (mut start, mut tokens) = (0, 0);
+
+    for (i, piece) in pieces.iter().enumerate() {
+        let full = i - start >= max_items || tokens + piece.tokens > max_tokens;
+        if full && i > start {
+            batches.push(start..i);
+            start = i;
+            tokens = 0;
+        }
+        tokens += piece.tokens;
+    }
+    if start < pieces.len() {
+        batches.push(start..pieces.len());
+    }
+
+    batches
+}
+
+/// Recombines piece embeddings into one embedding per input. Inputs that were
+/// split get the token-weighted mean of their pieces, rescaled to unit length.
+pub fn combine(inputs: usize, pieces: &[Piece], embeddings: Vec<Vec<f32>>) -> Vec<Vec<f32>> {
+    let mut parts: Vec<Vec<(usize, Vec<f32>)>> = vec![Vec::new(); inputs];
+    for (piece, embedding) in pieces.iter().zip(embeddings) {
+        parts[piece.input].push((piece.tokens, embedding));
+    }
+
+    parts
+        .into_iter()
+        .map(|mut parts| {
+            if parts.len() <= 1 {
+                return parts.pop().map(|(_, embedding)| embedding).unwrap_or_default();
+            }
+
+            let mut mean = vec![0.0; parts[0].1.len()];
+            for (tokens, embedding) in &parts {
+                for (sum, x) in mean.iter_mut().zip(embedding) {
+                    *sum += x * *tokens as f32;
+                }
+            }
+            let norm = mean.iter().map(|x| x * x).sum::<f32>().sqrt();
+            if norm > 0.0 {
+                mean.iter_mut().for_each(|x| *x /= norm);
+            }
+            mean
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_splits_batches_and_recombines_in_order() {
+        let long = "fn generated() { let value = 42; }\n".repeat(200);
+        let texts = vec!["short".to_string(), long.clone(), "ünïcödé".repeat(50)];
+
+        let pieces = split(&texts, 100);
+        assert_eq!(pieces[0].text, "short");
+        assert!(pieces.iter().all(|piece| piece.tokens <= 100));
+        let rejoined: String = pieces.iter().filter(|p| p.input == 1).map(|p| p.text.as_str()).collect();
+        assert_eq!(rejoined, long);
+        let rejoined: String = pieces.iter().filter(|p| p.input == 2).map(|p| p.text.as_str()).collect();
+        assert_eq!(rejoined, texts[2]);
+
+        // A character spread over several tokens is never cut apart.
+        let crabs = vec!["🦀".repeat(3)];
+        let crab_pieces = split(&crabs, 1);
+        assert_eq!(crab_pieces.len(), 3);
+        assert!(crab_pieces.iter().all(|piece| piece.text == "🦀"));
+
+        let batches = batches(&pieces, 4, 250);
+        assert_eq!(batches.first().unwrap().start, 0);
+        assert_eq!(batches.last().unwrap().end, pieces.len());
+        for batch in &batches {
+            assert!(batch.len() <= 4);
+            assert!(pieces[batch.clone()].iter().map(|p| p.tokens).sum::<usize>() <= 250);
+        }
+
+        // Each piece "embeds" to a one-hot vector of its input.
+        let embeddings = pieces
+            .iter()
+            .map(|piece| (0..3).map(|i| if i == piece.input { 2.0 } else { 0.0 }).collect())
+            .collect();
+        let combined = combine(texts.len(), &pieces, embeddings);
+        assert_eq!(combined[0], vec![2.0, 0.0, 0.0]);
+        assert_eq!(combined[1], vec![0.0, 1.0, 0.0]);
+        assert_eq!(combined[2], vec![0.0, 0.0, 1.0]);
+    }
+}
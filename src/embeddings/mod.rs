@@ -1,20 +1,41 @@
+pub mod batching;
+
 use anyhow::Result;
 use async_trait::async_trait;
-use reqwest::Client;
+use crate::config::HttpConfig;
+use crate::http::HttpClient;
 use serde::{Deserialize, Serialize};
-use sha2::{Digest, Sha256};
+use std::collections::HashMap;
 use std::time::Duration;
 
 #[async_trait]
 pub trait EmbeddingProvider: Send + Sync {
     async fn embed(&self, text: &str) -> Result<Vec<f32>>;
     async fn embed_batch(&self, texts: &[String]) -> Result<Vec<Vec<f32>>>;
+
+    /// Name of the model, recorded with a collection so that vectors from
+    /// different models are never mixed.
+    fn model_name(&self) -> String;
+
+    /// Length of the vectors this provider returns. The default embeds a
+    /// short probe text.
+    async fn dimension(&self) -> Result<usize> {
+        Ok(self.embed("dimension probe").await?.len())
+    }
 }
 
+/// OpenAI accepts at most 8191 tokens per input; pieces are cut a little
+/// shorter since a cut can land inside a token.
+const OPENAI_MAX_INPUT_TOKENS: usize = 8000;
+const OPENAI_MAX_REQUEST_INPUTS: usize = 2048;
+const OPENAI_MAX_REQUEST_TOKENS: usize = 300_000;
+const EMBEDDING_TIMEOUT: Duration = Duration::from_secs(30);
+
 pub struct OpenAIEmbeddings {
-    client: Client,
+    client: HttpClient,
     api_key: String,
     model: String,
+    batch_size: usize,
 }
 
 #[derive(Serialize)]
@@ -30,89 +51,223 @@ struct EmbeddingResponse {
 
 #[derive(Deserialize)]
 struct EmbeddingData {
+    index: usize,
     embedding: Vec<f32>,
 }
 
 impl OpenAIEmbeddings {
     pub fn new(api_key: String, model: String) -> Self {
-        let client = Client::builder()
-            .timeout(Duration::from_secs(30))
-            .build()
-            .unwrap();
-        
         Self {
-            client,
+            client: HttpClient::new(EMBEDDING_TIMEOUT, &HttpConfig::default()),
             api_key,
             model,
+            batch_size: OPENAI_MAX_REQUEST_INPUTS,
         }
     }
-}
 
-#[async_trait]
-impl EmbeddingProvider for OpenAIEmbeddings {
-    async fn embed(&self, text: &str) -> Result<Vec<f32>> {
-        let embeddings = self.embed_batch(&[text.to_string()]).await?;
-        Ok(embeddings.into_iter().next().unwrap())
+    pub fn with_http_config(mut self, config: &HttpConfig) -> Self {
+        self.client = HttpClient::new(EMBEDDING_TIMEOUT, config);
+        self
     }
 
-    async fn embed_batch(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+    /// Maximum number of inputs sent in one request.
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.clamp(1, OPENAI_MAX_REQUEST_INPUTS);
+        self
+    }
+
+    async fn request(&self, input: Vec<String>, tokens: usize) -> Result<Vec<Vec<f32>>> {
+        let count = input.len();
         let request = EmbeddingRequest {
-            input: texts.to_vec(),
+            input,
             model: self.model.clone(),
         };
         
         let response = self
             .client
-            .post("https://api.openai.com/v1/embeddings")
-            .header("Authorization", format!("Bearer {}", self.api_key))
-            .json(&request)
-            .send()
+            .send(tokens, |client| {
+                client
+                    .post("https://api.openai.com/v1/embeddings")
+                    .header("Authorization", format!("Bearer {}", self.api_key))
+                    .json(&request)
+            })
             .await?;
         
-        if !response.status().is_success() {
-            anyhow::bail!("OpenAI API error: {}", response.status());
+        let mut data = response.json::<EmbeddingResponse>().await?.data;
+        if data.len() != count {
+            anyhow::bail!("OpenAI API returned {} embeddings for {} inputs", data.len(), count);
         }
+        data.sort_by_key(|data| data.index);
+        
+        Ok(data.into_iter().map(|data| data.embedding).collect())
+    }
+}
+
+#[async_trait]
+impl EmbeddingProvider for OpenAIEmbeddings {
+    fn model_name(&self) -> String {
+        format!("openai/{}", self.model)
+    }
+
+    async fn embed(&self, text: &str) -> Result<Vec<f32>> {
+        let embeddings = self.embed_batch(&[text.to_string()]).await?;
+        Ok(embeddings.into_iter().next().unwrap())
+    }
+
+    /// Splits `texts` into requests within OpenAI's limits; see [`batching`].
+    async fn embed_batch(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        let pieces = batching::split(texts, OPENAI_MAX_INPUT_TOKENS);
         
-        let embedding_response: EmbeddingResponse = response.json().await?;
-        let embeddings = embedding_response
-            .data
-            .into_iter()
-            .map(|data| data.embedding)
-            .collect();
+        let mut embeddings = Vec::with_capacity(pieces.len());
+        for batch in batching::batches(&pieces, self.batch_size, OPENAI_MAX_REQUEST_TOKENS) {
+            let batch = &pieces[batch];
+            let tokens = batch.iter().map(|piece| piece.tokens).sum();
+            let input = batch.iter().map(|piece| piece.text.clone()).collect();
+            embeddings.extend(self.request(input, tokens).await?);
+        }
         
-        Ok(embeddings)
+        Ok(batching::combine(texts.len(), &pieces, embeddings))
     }
 }
 
+/// Dimension of [`LocalEmbeddings`] vectors.
+pub const LOCAL_EMBEDDING_DIMENSIONS: usize = 384;
+
+/// Language keywords and English filler words. They carry little meaning in
+/// code search, so they get a fixed low weight in place of a corpus IDF.
+const COMMON_TERMS: &[&str] = &[
+    "a", "an", "and", "as", "async", "await", "bool", "break", "case", "catch", "class", "const", "continue",
+    "def", "default", "else", "enum", "err", "export", "false", "fn", "for", "from", "func", "function", "if",
+    "impl", "import", "in", "int", "is", "let", "match", "mod", "mut", "new", "nil", "none", "not", "null",
+    "of", "ok", "or", "pub", "return", "self", "some", "static", "str", "string", "struct", "the", "this",
+    "to", "true", "try", "type", "use", "var", "void", "while", "with",
+];
+const COMMON_TERM_WEIGHT: f32 = 0.2;
+const BIGRAM_WEIGHT: f32 = 0.5;
+const TRIGRAM_WEIGHT: f32 = 0.15;
+
+/// Offline embedding model for air-gapped setups: a hashed TF-IDF style
+/// projection of identifier-aware tokens into a dense vector.
+///
+/// Every term, adjacent term pair and character trigram is hashed into one of
+/// `dimensions` buckets with a pseudo-random sign, weighted by `1 + ln(tf)`,
+/// and the result is L2-normalized, so cosine similarity approximates lexical
+/// overlap while tolerating partial identifier matches.
 pub struct LocalEmbeddings {
+    dimensions: usize,
 }
 
 impl LocalEmbeddings {
     pub fn new() -> Self {
-        Self {}
+        Self {
+            dimensions: LOCAL_EMBEDDING_DIMENSIONS,
+        }
+    }
+
+    pub fn with_dimensions(mut self, dimensions: usize) -> Self {
+        self.dimensions = dimensions.max(1);
+        self
+    }
+
+    fn embed_text(&self, text: &str) -> Vec<f32> {
+        let terms = crate::utils::tokenizer::tokenize(text);
+
+        // feature -> (term frequency, weight)
+        let mut features: HashMap<String, (u32, f32)> = HashMap::new();
+        let mut add = |feature: String, weight: f32| {
+            let entry = features.entry(feature).or_insert((0, weight));
+            entry.0 += 1;
+        };
+
+        for term in &terms {
+            let weight = if COMMON_TERMS.contains(&term.as_str()) {
+                COMMON_TERM_WEIGHT
+            } else {
+                1.0
+            };
+            add(format!("t:{}", term), weight);
+
+            if term.chars().count() > 3 {
+                let padded: Vec<char> = format!("^{}$", term).chars().collect();
+                for window in padded.windows(3) {
+                    add(format!("c:{}", window.iter().collect::<String>()), TRIGRAM_WEIGHT * weight);
+                }
+            }
+        }
+        for pair in terms.windows(2) {
+            add(format!("b:{} {}", pair[0], pair[1]), BIGRAM_WEIGHT);
+        }
+
+        let mut embedding = vec![0.0; self.dimensions];
+        for (feature, (tf, weight)) in features {
+            let hash = fnv1a(feature.as_bytes());
+            let bucket = (hash % self.dimensions as u64) as usize;
+            let sign = if hash >> 63 == 0 { 1.0 } else { -1.0 };
+            // Sublinear term frequency so repeated tokens do not dominate.
+            embedding[bucket] += sign * weight * (1.0 + (tf as f32).ln());
+        }
+
+        let norm = embedding.iter().map(|x| x * x).sum::<f32>().sqrt();
+        if norm > 0.0 {
+            for value in &mut embedding {
+                *value /= norm;
+            }
+        }
+
+        embedding
+    }
+}
+
+impl Default for LocalEmbeddings {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
+/// 64-bit FNV-1a. Used instead of `DefaultHasher`, whose output may change
+/// between Rust releases and would silently invalidate stored vectors.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for &byte in bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
 #[async_trait]
 impl EmbeddingProvider for LocalEmbeddings {
     async fn embed(&self, text: &str) -> Result<Vec<f32>> {
-        let hash = Sha256::digest(text.as_bytes());
-        let mut embedding = vec![0.0; 384];
-        
-        for (i, &byte) in hash.iter().enumerate() {
-            if i < embedding.len() {
-                embedding[i] = (byte as f32) / 255.0;
-            }
-        }
-        
-        Ok(embedding)
+        Ok(self.embed_text(text))
     }
 
     async fn embed_batch(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
-        let mut embeddings = Vec::new();
-        for text in texts {
-            embeddings.push(self.embed(text).await?);
-        }
-        Ok(embeddings)
+        Ok(texts.iter().map(|text| self.embed_text(text)).collect())
+    }
+
+    fn model_name(&self) -> String {
+        "local/hashed-tfidf".to_string()
+    }
+
+    async fn dimension(&self) -> Result<usize> {
+        Ok(self.dimensions)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vector::cosine_similarity;
+
+    #[test]
+    fn test_local_embeddings_rank_related_code_higher() {
+        let embeddings = LocalEmbeddings::new();
+        let query = embeddings.embed_text("where is the config file loaded");
+        let related = embeddings.embed_text("pub fn load_config_file(path: &Path) -> Result<Config> { ... }");
+        let unrelated = embeddings.embed_text("fn render_progress_bar(total: u64) { bar.tick(); }");
+
+        assert_eq!(query.len(), LOCAL_EMBEDDING_DIMENSIONS);
+        assert!(cosine_similarity(&query, &related) > cosine_similarity(&query, &unrelated));
+        assert_eq!(embeddings.embed_text("same input"), embeddings.embed_text("same input"));
+    }
+}
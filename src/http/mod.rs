@@ -0,0 +1,190 @@
+//! HTTP layer for calls to the OpenAI API. Requests are paced by a
+//! client-side [`RateLimiter`], and rate-limited (429) or failed (5xx)
+//! responses and connection errors are retried with exponential backoff,
+//! honoring `Retry-After`.
+
+pub mod rate_limit;
+
+use crate::{config::HttpConfig, BuildliError, Result};
+use rate_limit::RateLimiter;
+use reqwest::{header::HeaderMap, Client, RequestBuilder, Response, StatusCode};
+use std::time::{Duration, SystemTime};
+use tracing::warn;
+
+const BASE_RETRY_DELAY: Duration = Duration::from_millis(500);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+pub struct HttpClient {
+    client: Client,
+    limiter: RateLimiter,
+    max_retries: u32,
+}
+
+impl HttpClient {
+    /// Client whose requests fail after `timeout` in total.
+    pub fn new(timeout: Duration, config: &HttpConfig) -> Self {
+        Self::build(Client::builder().timeout(timeout), config)
+    }
+
+    /// Client for streamed responses, which may run for any time as long as
+    /// connecting takes at most `connect_timeout` and no read waits longer
+    /// than `idle_timeout`.
+    pub fn streaming(connect_timeout: Duration, idle_timeout: Duration, config: &HttpConfig) -> Self {
+        Self::build(
+            Client::builder().connect_timeout(connect_timeout).read_timeout(idle_timeout),
+            config,
+        )
+    }
+
+    fn build(builder: reqwest::ClientBuilder, config: &HttpConfig) -> Self {
+        Self {
+            client: builder.build().unwrap(),
+            limiter: RateLimiter::new(config.requests_per_minute, config.tokens_per_minute),
+            max_retries: config.max_retries,
+        }
+    }
+
+    /// Sends the request made by `build`, retrying as described above.
+    /// `tokens` is the request's size for the rate limiter. Errors carry the
+    /// API's error message.
+    pub async fn send<F>(&self, tokens: usize, build: F) -> Result<Response>
+    where
+        F: Fn(&Client) -> RequestBuilder,
+    {
+        let mut attempt = 0;
+        loop {
+            self.limiter.acquire(tokens).await;
+
+            let (reason, retry_after) = match build(&self.client).send().await {
+                Ok(response) if response.status().is_success() => return Ok(response),
+                Ok(response) => {
+                    let status = response.status();
+                    let retryable = status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error();
+                    if !retryable || attempt >= self.max_retries {
+                        let body = response.text().await.unwrap_or_default();
+                        return Err(BuildliError::Network(api_error(status, &body)));
+                    }
+                    (status.to_string(), retry_after(response.headers()))
+                }
+                Err(e) if attempt < self.max_retries && (e.is_connect() || e.is_timeout()) => (e.to_string(), None),
+                Err(e) => {
+                    return Err(BuildliError::Network(format!("Failed to send request to OpenAI: {}", e)));
+                }
+            };
+
+            let delay = retry_after.unwrap_or_else(|| backoff(attempt));
+            if retry_after.is_some() {
+                self.limiter.pause(delay);
+            }
+            attempt += 1;
+            warn!(
+                "OpenAI request failed ({}), retrying in {:.1}s ({}/{})",
+                reason,
+                delay.as_secs_f32(),
+                attempt,
+                self.max_retries
+            );
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
+/// Exponential backoff with jitter: between half and all of
+/// `BASE_RETRY_DELAY * 2^attempt`, capped at `MAX_RETRY_DELAY`.
+fn backoff(attempt: u32) -> Duration {
+    let delay = BASE_RETRY_DELAY.saturating_mul(1 << attempt.min(16)).min(MAX_RETRY_DELAY);
+    delay.mul_f64(0.5 + fastrand::f64() * 0.5)
+}
+
+/// Delay requested by `Retry-After` (seconds or an HTTP date) or OpenAI's
+/// `retry-after-ms`.
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+
+    if let Some(ms) = header("retry-after-ms").and_then(|value| value.trim().parse::<f64>().ok()) {
+        return Some(Duration::from_secs_f64(ms.max(0.0) / 1000.0));
+    }
+    let value = header("retry-after")?.trim();
+    if let Ok(seconds) = value.parse::<f64>() {
+        return Some(Duration::from_secs_f64(seconds.max(0.0)));
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(date.duration_since(SystemTime::now()).unwrap_or_default())
+}
+
+/// Error message for a failed response, including the message from the
+/// API's `{"error": {"message": ...}}` body when there is one.
+fn api_error(status: StatusCode, body: &str) -> String {
+    let message = serde_json::from_str::<serde_json::Value>(body)
+        .ok()
+        .and_then(|json| json["error"]["message"].as_str().map(str::to_string))
+        .unwrap_or_else(|| body.trim().chars().take(500).collect());
+
+    if message.is_empty() {
+        format!("OpenAI API error: {}", status)
+    } else {
+        format!("OpenAI API error: {}: {}", status, message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Serves the given raw HTTP responses, one per connection.
+    async fn serve(responses: Vec<&'static str>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            for response in responses {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut buffer = [0; 4096];
+                let _ = socket.read(&mut buffer).await;
+                socket.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        url
+    }
+
+    #[tokio::test]
+    async fn test_retries_rate_limits_and_reports_api_errors() {
+        let url = serve(vec![
+            "HTTP/1.1 429 Too Many Requests\r\nretry-after-ms: 10\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+            "HTTP/1.1 200 OK\r\ncontent-length: 2\r\nconnection: close\r\n\r\nok",
+            "HTTP/1.1 400 Bad Request\r\ncontent-length: 40\r\nconnection: close\r\n\r\n{\"error\": {\"message\": \"Input too long\"}}",
+        ])
+        .await;
+        let client = HttpClient::new(Duration::from_secs(5), &HttpConfig::default());
+
+        let response = client.send(1, |client| client.get(&url)).await.unwrap();
+        assert_eq!(response.text().await.unwrap(), "ok");
+
+        let err = client.send(1, |client| client.get(&url)).await.unwrap_err();
+        assert_eq!(err.to_string(), "Network error: OpenAI API error: 400 Bad Request: Input too long");
+    }
+
+    #[tokio::test]
+    async fn test_streaming_client_outlasts_its_idle_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buffer = [0; 4096];
+            let _ = socket.read(&mut buffer).await;
+            socket
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 5\r\nconnection: close\r\n\r\n")
+                .await
+                .unwrap();
+            for byte in b"slow!" {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                socket.write_all(&[*byte]).await.unwrap();
+            }
+        });
+        let client = HttpClient::streaming(Duration::from_secs(1), Duration::from_millis(300), &HttpConfig::default());
+
+        let response = client.send(1, |client| client.get(&url)).await.unwrap();
+        assert_eq!(response.text().await.unwrap(), "slow!");
+    }
+}
@@ -0,0 +1,116 @@
+//! Client-side token-bucket rate limiting, so bulk indexing paces itself
+//! instead of running into the API's limits.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Limits requests and tokens per minute. Each bucket holds up to one
+/// minute's allowance and refills continuously; a limit of zero disables it.
+pub struct RateLimiter {
+    requests_per_minute: f64,
+    tokens_per_minute: f64,
+    state: Mutex<State>,
+}
+
+struct State {
+    requests: f64,
+    tokens: f64,
+    updated: Instant,
+    /// Set after the server asked to back off; nothing is sent before then.
+    paused_until: Option<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(requests_per_minute: u32, tokens_per_minute: u32) -> Self {
+        Self {
+            requests_per_minute: requests_per_minute as f64,
+            tokens_per_minute: tokens_per_minute as f64,
+            state: Mutex::new(State {
+                requests: requests_per_minute as f64,
+                tokens: tokens_per_minute as f64,
+                updated: Instant::now(),
+                paused_until: None,
+            }),
+        }
+    }
+
+    /// Waits until a request of `tokens` tokens may be sent and takes it from
+    /// the buckets. Requests larger than a minute's allowance wait for a full
+    /// bucket.
+    pub async fn acquire(&self, tokens: usize) {
+        loop {
+            match self.try_acquire(tokens, Instant::now()) {
+                None => return,
+                Some(wait) => tokio::time::sleep(wait).await,
+            }
+        }
+    }
+
+    /// Holds back every request for `delay`, e.g. after a 429 response.
+    pub fn pause(&self, delay: Duration) {
+        let until = Instant::now() + delay;
+        let mut state = self.state.lock().unwrap();
+        state.paused_until = Some(state.paused_until.map_or(until, |paused| paused.max(until)));
+    }
+
+    /// Takes a request from the buckets, or returns how long to wait first.
+    fn try_acquire(&self, tokens: usize, now: Instant) -> Option<Duration> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(paused_until) = state.paused_until {
+            if paused_until > now {
+                return Some(paused_until - now);
+            }
+            state.paused_until = None;
+        }
+
+        let minutes = now.duration_since(state.updated).as_secs_f64() / 60.0;
+        state.updated = now;
+        state.requests = (state.requests + minutes * self.requests_per_minute).min(self.requests_per_minute);
+        state.tokens = (state.tokens + minutes * self.tokens_per_minute).min(self.tokens_per_minute);
+
+        let tokens = (tokens as f64).min(self.tokens_per_minute);
+        let wait = wait_for(state.requests, 1.0, self.requests_per_minute)
+            .max(wait_for(state.tokens, tokens, self.tokens_per_minute));
+        if wait > Duration::ZERO {
+            return Some(wait);
+        }
+
+        if self.requests_per_minute > 0.0 {
+            state.requests -= 1.0;
+        }
+        if self.tokens_per_minute > 0.0 {
+            state.tokens -= tokens;
+        }
+        None
+    }
+}
+
+/// Time until a bucket refilling at `per_minute` holds `needed`.
+fn wait_for(available: f64, needed: f64, per_minute: f64) -> Duration {
+    if per_minute <= 0.0 || available >= needed {
+        return Duration::ZERO;
+    }
+    Duration::from_secs_f64((needed - available) / per_minute * 60.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_refills_over_time() {
+        let limiter = RateLimiter::new(60, 1000);
+        let start = Instant::now();
+
+        assert_eq!(limiter.try_acquire(900, start), None);
+        // 100 tokens left; 200 more take 12 seconds at 1000 per minute.
+        let wait = limiter.try_acquire(300, start).unwrap();
+        assert!((wait.as_secs_f64() - 12.0).abs() < 0.01);
+        assert_eq!(limiter.try_acquire(300, start + wait + Duration::from_millis(1)), None);
+
+        let unlimited = RateLimiter::new(0, 0);
+        for _ in 0..1000 {
+            assert_eq!(unlimited.try_acquire(1_000_000, start), None);
+        }
+    }
+}
@@ -1,7 +1,8 @@
 use crate::{
+    config::Config,
     embeddings::{EmbeddingProvider, LocalEmbeddings, OpenAIEmbeddings},
     indexer::Indexer,
-    vector::{PersistentLocalVectorStore, QdrantStore, VectorStore},
+    vector::{CollectionSchema, PersistentLocalVectorStore, QdrantStore, VectorStore},
 };
 use async_trait::async_trait;
 
@@ -11,8 +12,56 @@ pub enum EmbeddingProviderType {
 }
 
 pub enum VectorStoreType {
-    Qdrant(QdrantStore),
-    Local(PersistentLocalVectorStore),
+    Qdrant(Box<QdrantStore>),
+    Local(Box<PersistentLocalVectorStore>),
+}
+
+impl EmbeddingProviderType {
+    pub fn from_config(config: &Config) -> Self {
+        match (config.embedding.provider.as_str(), &config.llm.api_key) {
+            ("openai", Some(api_key)) => EmbeddingProviderType::OpenAI(
+                OpenAIEmbeddings::new(api_key.clone(), config.embedding.model.clone())
+                    .with_batch_size(config.embedding.batch_size)
+                    .with_http_config(&config.http),
+            ),
+            _ => EmbeddingProviderType::Local(LocalEmbeddings::new()),
+        }
+    }
+}
+
+impl VectorStoreType {
+    pub async fn from_config(config: &Config) -> anyhow::Result<Self> {
+        match config.vector.backend.as_str() {
+            "qdrant" => Ok(VectorStoreType::Qdrant(Box::new(
+                QdrantStore::new(&config.vector.url, &config.vector.collection_name).await?,
+            ))),
+            _ => Ok(VectorStoreType::Local(Box::new(PersistentLocalVectorStore::new(&config.vector).await?))),
+        }
+    }
+
+    /// Like [`from_config`](Self::from_config), but a local store is opened
+    /// without the writer lock, so indexing can run while it is open.
+    pub async fn from_config_read_only(config: &Config) -> anyhow::Result<Self> {
+        match config.vector.backend.as_str() {
+            "qdrant" => Self::from_config(config).await,
+            _ => Ok(VectorStoreType::Local(Box::new(
+                PersistentLocalVectorStore::new_read_only(&config.vector).await?,
+            ))),
+        }
+    }
+
+    /// Makes the configured collection name refer to collection `target`,
+    /// deleting the collection it referred to before. Takes the store by
+    /// value since a local store must be closed first; `target` must be too.
+    pub async fn promote(self, config: &Config, target: &str) -> anyhow::Result<()> {
+        match self {
+            VectorStoreType::Qdrant(store) => store.promote(target).await,
+            VectorStoreType::Local(store) => {
+                drop(store);
+                PersistentLocalVectorStore::promote(&config.vector.collection_name, target)
+            }
+        }
+    }
 }
 
 #[async_trait]
@@ -30,14 +79,28 @@ impl EmbeddingProvider for EmbeddingProviderType {
             EmbeddingProviderType::Local(provider) => provider.embed_batch(texts).await,
         }
     }
+
+    fn model_name(&self) -> String {
+        match self {
+            EmbeddingProviderType::OpenAI(provider) => provider.model_name(),
+            EmbeddingProviderType::Local(provider) => provider.model_name(),
+        }
+    }
+
+    async fn dimension(&self) -> anyhow::Result<usize> {
+        match self {
+            EmbeddingProviderType::OpenAI(provider) => provider.dimension().await,
+            EmbeddingProviderType::Local(provider) => provider.dimension().await,
+        }
+    }
 }
 
 #[async_trait]
 impl VectorStore for VectorStoreType {
-    async fn initialize(&self, collection_name: &str, vector_size: usize) -> anyhow::Result<()> {
+    async fn initialize(&self, schema: &CollectionSchema) -> anyhow::Result<()> {
         match self {
-            VectorStoreType::Qdrant(store) => store.initialize(collection_name, vector_size).await,
-            VectorStoreType::Local(store) => store.initialize(collection_name, vector_size).await,
+            VectorStoreType::Qdrant(store) => store.initialize(schema).await,
+            VectorStoreType::Local(store) => store.initialize(schema).await,
         }
     }
 
@@ -48,10 +111,10 @@ impl VectorStore for VectorStoreType {
         }
     }
 
-    async fn search(&self, query_vector: Vec<f32>, top_k: usize) -> anyhow::Result<Vec<crate::vector::SearchResult>> {
+    async fn search(&self, query_vector: Vec<f32>, top_k: usize, filter: &crate::vector::SearchFilter) -> anyhow::Result<Vec<crate::vector::SearchResult>> {
         match self {
-            VectorStoreType::Qdrant(store) => store.search(query_vector, top_k).await,
-            VectorStoreType::Local(store) => store.search(query_vector, top_k).await,
+            VectorStoreType::Qdrant(store) => store.search(query_vector, top_k, filter).await,
+            VectorStoreType::Local(store) => store.search(query_vector, top_k, filter).await,
         }
     }
 
@@ -62,6 +125,34 @@ impl VectorStore for VectorStoreType {
         }
     }
 
+    async fn delete_documents(&self, ids: Vec<String>) -> anyhow::Result<()> {
+        match self {
+            VectorStoreType::Qdrant(store) => store.delete_documents(ids).await,
+            VectorStoreType::Local(store) => store.delete_documents(ids).await,
+        }
+    }
+
+    async fn get_documents(&self, ids: &[String]) -> anyhow::Result<Vec<crate::vector::SearchResult>> {
+        match self {
+            VectorStoreType::Qdrant(store) => store.get_documents(ids).await,
+            VectorStoreType::Local(store) => store.get_documents(ids).await,
+        }
+    }
+
+    async fn scroll(&self, offset: Option<String>, limit: usize) -> anyhow::Result<(Vec<crate::vector::SearchResult>, Option<String>)> {
+        match self {
+            VectorStoreType::Qdrant(store) => store.scroll(offset, limit).await,
+            VectorStoreType::Local(store) => store.scroll(offset, limit).await,
+        }
+    }
+
+    async fn schema(&self) -> anyhow::Result<Option<CollectionSchema>> {
+        match self {
+            VectorStoreType::Qdrant(store) => store.schema().await,
+            VectorStoreType::Local(store) => store.schema().await,
+        }
+    }
+
     fn create_document(&self, chunk: crate::indexer::parser::CodeChunk, embedding: Vec<f32>) -> crate::vector::Document {
         match self {
             VectorStoreType::Qdrant(store) => store.create_document(chunk, embedding),
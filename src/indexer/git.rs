@@ -0,0 +1,224 @@
+use anyhow::{bail, Context, Result};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{Child, ChildStdin, ChildStdout, Command};
+
+/// A commit's tree read straight from the git object database, so a revision
+/// can be indexed without checking it out.
+pub struct GitTree {
+    repo_root: PathBuf,
+    commit: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct TreeEntry {
+    /// Path relative to the repository root.
+    pub path: PathBuf,
+    pub oid: String,
+}
+
+impl GitTree {
+    /// Resolves `rev` (a SHA, tag or branch) in the repository containing `path`.
+    pub async fn open(path: &Path, rev: &str) -> Result<Self> {
+        let dir = if path.is_file() {
+            path.parent().unwrap_or(Path::new("."))
+        } else {
+            path
+        };
+
+        let repo_root = git_output(dir, &["rev-parse", "--show-toplevel"])
+            .await
+            .with_context(|| format!("{} is not inside a git repository", path.display()))?;
+        let commit = git_output(dir, &["rev-parse", "--verify", &format!("{}^{{commit}}", rev)])
+            .await
+            .with_context(|| format!("Unknown commit: {}", rev))?;
+
+        Ok(Self {
+            repo_root: PathBuf::from(repo_root),
+            commit,
+        })
+    }
+
+    pub fn commit(&self) -> &str {
+        &self.commit
+    }
+
+    pub fn repo_root(&self) -> &Path {
+        &self.repo_root
+    }
+
+    /// Lists the regular files in the commit's tree. Symlinks and submodules
+    /// are skipped since they have no content of their own to index.
+    pub async fn files(&self) -> Result<Vec<TreeEntry>> {
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(&self.repo_root)
+            .args(["ls-tree", "-r", "-z", "--full-tree", &self.commit])
+            .output()
+            .await
+            .context("Failed to run git ls-tree")?;
+
+        if !output.status.success() {
+            bail!("git ls-tree failed: {}", String::from_utf8_lossy(&output.stderr).trim());
+        }
+
+        let mut entries = Vec::new();
+        for record in output.stdout.split(|&b| b == 0).filter(|r| !r.is_empty()) {
+            // "<mode> <type> <oid>\t<path>"
+            let record = String::from_utf8_lossy(record);
+            let Some((info, path)) = record.split_once('\t') else {
+                continue;
+            };
+            let mut fields = info.split(' ');
+            let (Some(mode), Some(kind), Some(oid)) = (fields.next(), fields.next(), fields.next()) else {
+                continue;
+            };
+
+            if kind == "blob" && mode != "120000" {
+                entries.push(TreeEntry {
+                    path: PathBuf::from(path),
+                    oid: oid.to_string(),
+                });
+            }
+        }
+
+        Ok(entries)
+    }
+
+    pub async fn blob_reader(&self) -> Result<BlobReader> {
+        BlobReader::spawn(&self.repo_root).await
+    }
+}
+
+/// Long-running `git cat-file --batch` process used to read many blobs
+/// without spawning a process per file.
+pub struct BlobReader {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+}
+
+impl BlobReader {
+    async fn spawn(repo_root: &Path) -> Result<Self> {
+        let mut child = Command::new("git")
+            .arg("-C")
+            .arg(repo_root)
+            .args(["cat-file", "--batch"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .context("Failed to run git cat-file")?;
+
+        let stdin = child.stdin.take().context("Failed to open git cat-file stdin")?;
+        let stdout = BufReader::new(child.stdout.take().context("Failed to open git cat-file stdout")?);
+
+        Ok(Self { child, stdin, stdout })
+    }
+
+    pub async fn read(&mut self, oid: &str) -> Result<Vec<u8>> {
+        self.stdin.write_all(format!("{}\n", oid).as_bytes()).await?;
+        self.stdin.flush().await?;
+
+        // "<oid> <type> <size>\n<content>\n", or "<oid> missing\n"
+        let mut header = String::new();
+        self.stdout.read_line(&mut header).await?;
+        let mut fields = header.split_whitespace();
+        let (Some(_), Some(kind), Some(size)) = (fields.next(), fields.next(), fields.next()) else {
+            bail!("git object {} not found", oid);
+        };
+        if kind != "blob" {
+            bail!("git object {} is a {}, not a blob", oid, kind);
+        }
+
+        let size: usize = size.parse().context("Invalid object size from git cat-file")?;
+        let mut content = vec![0; size + 1];
+        self.stdout.read_exact(&mut content).await?;
+        content.truncate(size);
+
+        Ok(content)
+    }
+
+    pub async fn close(mut self) -> Result<()> {
+        drop(self.stdin);
+        self.child.wait().await?;
+        Ok(())
+    }
+}
+
+async fn git_output(dir: &Path, args: &[&str]) -> Result<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .await
+        .context("Failed to run git")?;
+
+    if !output.status.success() {
+        bail!("{}", String::from_utf8_lossy(&output.stderr).trim());
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::embeddings::LocalEmbeddings;
+    use crate::indexer::{IndexStats, Indexer};
+    use crate::vector::{LocalVectorStore, VectorStore};
+
+    fn git(dir: &Path, args: &[&str]) -> String {
+        let output = std::process::Command::new("git")
+            .arg("-C")
+            .arg(dir)
+            .args(["-c", "user.name=buildli", "-c", "user.email=buildli@example.com", "-c", "commit.gpgsign=false"])
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "git {:?}: {}", args, String::from_utf8_lossy(&output.stderr));
+        String::from_utf8_lossy(&output.stdout).trim().to_string()
+    }
+
+    #[tokio::test]
+    async fn test_indexes_blobs_at_commit() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = dir.path().canonicalize().unwrap();
+        git(&repo, &["init", "-q"]);
+        std::fs::create_dir(repo.join("src")).unwrap();
+        std::fs::write(repo.join("src/lib.rs"), "fn first_version() {}\n").unwrap();
+        std::fs::write(repo.join("src/old.rs"), "fn removed_later() {}\n").unwrap();
+        git(&repo, &["add", "-A"]);
+        git(&repo, &["commit", "-q", "-m", "first"]);
+        let first = git(&repo, &["rev-parse", "HEAD"]);
+
+        std::fs::write(repo.join("src/lib.rs"), "fn second_version() {}\n").unwrap();
+        std::fs::remove_file(repo.join("src/old.rs")).unwrap();
+        git(&repo, &["add", "-A"]);
+        git(&repo, &["commit", "-q", "-m", "second"]);
+        // Uncommitted edits must not leak into either commit's index.
+        std::fs::write(repo.join("src/lib.rs"), "fn working_tree() {}\n").unwrap();
+
+        let tree = GitTree::open(&repo.join("src"), "HEAD~1").await.unwrap();
+        assert_eq!(tree.commit(), first);
+        assert_eq!(tree.repo_root(), repo);
+
+        let mut indexer = Indexer::new(LocalEmbeddings::new(), LocalVectorStore::new());
+        let mut stats = IndexStats::default();
+        indexer.index_tree(&repo.join("src"), &tree, &mut stats).await.unwrap();
+        assert_eq!(stats.indexed_files, 2);
+
+        let (results, _) = indexer.vector_store.scroll(None, 10).await.unwrap();
+        let mut contents: Vec<&str> = results
+            .iter()
+            .map(|result| result.metadata["content"].as_str().unwrap().trim_end())
+            .collect();
+        contents.sort();
+        assert_eq!(contents, ["fn first_version() {}", "fn removed_later() {}"]);
+        assert!(results.iter().all(|result| result.metadata["commit"] == first.as_str()));
+        assert!(results.iter().any(|result| result.metadata["relative_path"] == "src/old.rs"));
+    }
+}
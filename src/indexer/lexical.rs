@@ -0,0 +1,244 @@
+use super::parser::CodeChunk;
+use crate::utils::tokenizer;
+use crate::vector::SearchFilter;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use tokio::fs;
+
+/// BM25 term-frequency saturation.
+const K1: f32 = 1.2;
+/// BM25 document-length normalization.
+const B: f32 = 0.75;
+
+/// BM25 inverted index over chunk contents, kept next to the vector store so
+/// exact identifiers and error strings can be found even when their
+/// embeddings are not close to the query's.
+///
+/// Only per-chunk term counts are persisted; postings are rebuilt on load.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct LexicalIndex {
+    docs: HashMap<String, LexicalDoc>,
+    #[serde(skip)]
+    postings: HashMap<String, HashMap<String, u32>>,
+    #[serde(skip)]
+    total_length: u64,
+    #[serde(skip)]
+    index_path: PathBuf,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct LexicalDoc {
+    file_path: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    repo: Option<String>,
+    language: String,
+    length: u32,
+    terms: HashMap<String, u32>,
+}
+
+#[derive(Debug, Clone)]
+pub struct LexicalMatch {
+    pub id: String,
+    pub score: f32,
+}
+
+impl LexicalIndex {
+    /// Loads the index for `collection`, or an empty one if nothing has been
+    /// indexed into it yet.
+    pub async fn load(collection: &str) -> Result<Self> {
+        let index_path = crate::config::data_dir()?
+            .join("lexical")
+            .join(format!("{}.json", &super::manifest::content_hash(collection)[..16]));
+
+        if !index_path.exists() {
+            return Ok(Self {
+                index_path,
+                ..Default::default()
+            });
+        }
+
+        let data = fs::read_to_string(&index_path)
+            .await
+            .context("Failed to read lexical index")?;
+        let mut index: LexicalIndex = serde_json::from_str(&data).context("Failed to parse lexical index")?;
+        index.index_path = index_path;
+
+        let docs = std::mem::take(&mut index.docs);
+        for (id, doc) in docs {
+            index.add(id, doc);
+        }
+
+        Ok(index)
+    }
+
+    pub async fn save(&self) -> Result<()> {
+        if let Some(parent) = self.index_path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+
+        let tmp_path = self.index_path.with_extension("json.tmp");
+        fs::write(&tmp_path, serde_json::to_vec(self)?).await?;
+        fs::rename(&tmp_path, &self.index_path).await?;
+
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.docs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.docs.is_empty()
+    }
+
+    /// Indexes `chunk` under `id`, replacing any previous entry with that ID.
+    pub fn insert(&mut self, id: String, chunk: &CodeChunk) {
+        let mut text = chunk.content.clone();
+        if let Some(symbol) = &chunk.symbol {
+            text.push('\n');
+            text.push_str(symbol);
+        }
+
+        let mut terms: HashMap<String, u32> = HashMap::new();
+        let mut length = 0;
+        for term in tokenizer::tokenize(&text) {
+            *terms.entry(term).or_default() += 1;
+            length += 1;
+        }
+
+        self.remove(&id);
+        self.add(
+            id,
+            LexicalDoc {
+                file_path: chunk.file_path.clone(),
+                repo: chunk.repo.clone(),
+                language: chunk.language.clone(),
+                length,
+                terms,
+            },
+        );
+    }
+
+    pub fn remove(&mut self, id: &str) {
+        let Some(doc) = self.docs.remove(id) else {
+            return;
+        };
+
+        self.total_length -= doc.length as u64;
+        for term in doc.terms.keys() {
+            if let Some(posting) = self.postings.get_mut(term) {
+                posting.remove(id);
+                if posting.is_empty() {
+                    self.postings.remove(term);
+                }
+            }
+        }
+    }
+
+    /// Removes every chunk of `file_path`.
+    pub fn remove_file(&mut self, file_path: &str) {
+        let ids: Vec<String> = self
+            .docs
+            .iter()
+            .filter(|(_, doc)| doc.file_path == file_path)
+            .map(|(id, _)| id.clone())
+            .collect();
+
+        for id in ids {
+            self.remove(&id);
+        }
+    }
+
+    /// The `top_k` chunks passing `filter` with the highest BM25 score for `query`.
+    pub fn search(&self, query: &str, top_k: usize, filter: &SearchFilter) -> Vec<LexicalMatch> {
+        if self.docs.is_empty() {
+            return vec![];
+        }
+
+        let mut query_terms = tokenizer::tokenize(query);
+        query_terms.sort();
+        query_terms.dedup();
+
+        let doc_count = self.docs.len() as f32;
+        let avg_length = (self.total_length as f32 / doc_count).max(1.0);
+        let mut scores: HashMap<&str, f32> = HashMap::new();
+
+        for term in &query_terms {
+            let Some(posting) = self.postings.get(term) else {
+                continue;
+            };
+
+            let df = posting.len() as f32;
+            let idf = (1.0 + (doc_count - df + 0.5) / (df + 0.5)).ln();
+
+            for (id, &tf) in posting {
+                let doc = &self.docs[id];
+                if !filter.accepts(doc.repo.as_deref(), Some(&doc.language)) {
+                    continue;
+                }
+
+                let tf = tf as f32;
+                let norm = K1 * (1.0 - B + B * doc.length as f32 / avg_length);
+                *scores.entry(id.as_str()).or_default() += idf * tf * (K1 + 1.0) / (tf + norm);
+            }
+        }
+
+        let mut matches: Vec<LexicalMatch> = scores
+            .into_iter()
+            .map(|(id, score)| LexicalMatch {
+                id: id.to_string(),
+                score,
+            })
+            .collect();
+        matches.sort_by(|a, b| b.score.total_cmp(&a.score).then_with(|| a.id.cmp(&b.id)));
+        matches.truncate(top_k);
+
+        matches
+    }
+
+    fn add(&mut self, id: String, doc: LexicalDoc) {
+        self.total_length += doc.length as u64;
+        for (term, &tf) in &doc.terms {
+            self.postings.entry(term.clone()).or_default().insert(id.clone(), tf);
+        }
+        self.docs.insert(id, doc);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::indexer::parser::ChunkType;
+
+    fn chunk(file_path: &str, content: &str) -> CodeChunk {
+        CodeChunk {
+            file_path: file_path.to_string(),
+            content: content.to_string(),
+            line_start: 1,
+            line_end: 1,
+            chunk_type: ChunkType::Function,
+            language: "rs".to_string(),
+            repo: None,
+            commit: None,
+            relative_path: None,
+            symbol: None,
+        }
+    }
+
+    #[test]
+    fn test_exact_identifier_ranks_first() {
+        let mut index = LexicalIndex::default();
+        index.insert("a".to_string(), &chunk("auth.rs", "fn refresh_oauth_token(client: &Client) {}"));
+        index.insert("b".to_string(), &chunk("token.rs", "fn parse_token(token: &str) -> Token {}"));
+        index.insert("c".to_string(), &chunk("cache.rs", "fn refresh(cache: &mut Cache) {}"));
+
+        let matches = index.search("refresh_oauth_token", 10, &SearchFilter::default());
+        assert_eq!(matches[0].id, "a");
+
+        index.remove_file("auth.rs");
+        assert_eq!(index.len(), 2);
+        assert!(index.search("oauth", 10, &SearchFilter::default()).is_empty());
+    }
+}
@@ -0,0 +1,225 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+use tokio::fs;
+
+/// Records, per index root, the content hash and chunk IDs of every indexed
+/// file so unchanged files can be skipped and stale chunks replaced or purged.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    root: PathBuf,
+    collection: String,
+    files: HashMap<String, ManifestEntry>,
+    #[serde(skip)]
+    manifest_path: PathBuf,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub sha256: String,
+    pub chunk_ids: Vec<String>,
+    /// Commit the file was read from, if it was indexed with `--commit`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commit: Option<String>,
+}
+
+impl Manifest {
+    /// Loads the manifest for `root` in `collection`, or an empty one if the
+    /// root has not been indexed into that collection before.
+    pub async fn load(root: &Path, collection: &str) -> Result<Self> {
+        let root = root.canonicalize().unwrap_or_else(|_| root.to_path_buf());
+        let key = Sha256::digest(format!("{}\0{}", collection, root.display()).as_bytes());
+        let manifest_path = crate::config::data_dir()?
+            .join("manifests")
+            .join(format!("{}.json", &hex(&key)[..16]));
+
+        Self::load_from(&root, collection, manifest_path).await
+    }
+
+    /// Loads the manifest for the canonical `root` stored at `manifest_path`.
+    pub async fn load_from(root: &Path, collection: &str, manifest_path: PathBuf) -> Result<Self> {
+        let root = root.to_path_buf();
+        if !manifest_path.exists() {
+            return Ok(Self {
+                root,
+                collection: collection.to_string(),
+                files: HashMap::new(),
+                manifest_path,
+            });
+        }
+
+        let data = fs::read_to_string(&manifest_path)
+            .await
+            .context("Failed to read index manifest")?;
+        let mut manifest: Manifest = serde_json::from_str(&data).context("Failed to parse index manifest")?;
+        manifest.manifest_path = manifest_path;
+
+        Ok(manifest)
+    }
+
+    pub async fn save(&self) -> Result<()> {
+        if let Some(parent) = self.manifest_path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+
+        let tmp_path = self.manifest_path.with_extension("json.tmp");
+        fs::write(&tmp_path, serde_json::to_vec(self)?).await?;
+        fs::rename(&tmp_path, &self.manifest_path).await?;
+
+        Ok(())
+    }
+
+    /// Manifest key of `path`: its path relative to the root, so a file has
+    /// the same key whether the root was given as `.` or as an absolute path.
+    pub fn key(&self, path: &Path) -> String {
+        file_key(&self.root, path)
+    }
+
+    pub fn get(&self, file_path: &str) -> Option<&ManifestEntry> {
+        self.files.get(file_path)
+    }
+
+    pub fn is_unchanged(&self, file_path: &str, sha256: &str, commit: Option<&str>) -> bool {
+        self.files
+            .get(file_path)
+            .map(|entry| entry.sha256 == sha256 && entry.commit.as_deref() == commit)
+            .unwrap_or(false)
+    }
+
+    /// Content hashes of the files recorded at `commit`, for checking
+    /// [`is_unchanged`](Self::is_unchanged) away from the manifest.
+    pub fn hashes(&self, commit: Option<&str>) -> HashMap<String, String> {
+        self.files
+            .iter()
+            .filter(|(_, entry)| entry.commit.as_deref() == commit)
+            .map(|(path, entry)| (path.clone(), entry.sha256.clone()))
+            .collect()
+    }
+
+    pub fn insert(&mut self, file_path: String, entry: ManifestEntry) {
+        self.files.insert(file_path, entry);
+    }
+
+    pub fn remove(&mut self, file_path: &str) -> Option<ManifestEntry> {
+        self.files.remove(file_path)
+    }
+
+    /// Chunk IDs of every recorded file.
+    pub fn chunk_ids(&self) -> impl Iterator<Item = &String> {
+        self.files.values().flat_map(|entry| &entry.chunk_ids)
+    }
+
+    pub fn file_paths(&self) -> impl Iterator<Item = &String> {
+        self.files.keys()
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn file_count(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn chunk_count(&self) -> usize {
+        self.files.values().map(|entry| entry.chunk_ids.len()).sum()
+    }
+}
+
+/// Path of `path` relative to the canonical `root`, or `path` itself if it is
+/// outside of `root`. Files that no longer exist (or only exist in a git tree)
+/// are resolved through their parent directory.
+pub fn file_key(root: &Path, path: &Path) -> String {
+    let relative = super::normalize_path(path)
+        .filter(|path| !path.components().any(|c| c == Component::ParentDir))
+        .and_then(|path| path.strip_prefix(root).ok().map(Path::to_path_buf))
+        .or_else(|| {
+            let canonical = path.canonicalize().ok().or_else(|| {
+                let parent = path.parent()?.canonicalize().ok()?;
+                Some(parent.join(path.file_name()?))
+            })?;
+            canonical.strip_prefix(root).ok().map(Path::to_path_buf)
+        });
+
+    match relative {
+        Some(relative) => relative.to_string_lossy().to_string(),
+        None => path.display().to_string(),
+    }
+}
+
+pub fn content_hash(content: &str) -> String {
+    hex(&Sha256::digest(content.as_bytes()))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::embeddings::LocalEmbeddings;
+    use crate::indexer::{IndexStats, Indexer};
+    use crate::vector::{LocalVectorStore, VectorStore};
+
+    type TestIndexer = Indexer<LocalEmbeddings, LocalVectorStore>;
+
+    #[test]
+    fn test_keys_are_relative_to_the_canonical_root() {
+        let cwd = std::env::current_dir().unwrap().canonicalize().unwrap();
+        let manifest = Manifest {
+            root: cwd.clone(),
+            ..Default::default()
+        };
+
+        assert_eq!(manifest.key(Path::new("./src/lib.rs")), "src/lib.rs");
+        assert_eq!(manifest.key(&cwd.join("src/lib.rs")), "src/lib.rs");
+        assert_eq!(manifest.key(&cwd.join("src/../src/lib.rs")), "src/lib.rs");
+        assert_eq!(manifest.key(&cwd.join("src/deleted.rs")), "src/deleted.rs");
+    }
+
+    #[tokio::test]
+    async fn test_incremental_reindex() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("repo");
+        std::fs::create_dir(&root).unwrap();
+        let root = root.canonicalize().unwrap();
+        std::fs::write(root.join("a.rs"), "fn alpha() {}\n").unwrap();
+        std::fs::write(root.join("b.rs"), "fn beta() {}\n").unwrap();
+        std::fs::write(root.join("c.rs"), "fn gamma() {}\n").unwrap();
+
+        let mut indexer = Indexer::new(LocalEmbeddings::new(), LocalVectorStore::new());
+        let manifest_path = dir.path().join("manifest.json");
+        async fn run(indexer: &mut TestIndexer, root: &Path, manifest_path: &Path, path: PathBuf) -> IndexStats {
+            indexer.manifest = Some(Manifest::load_from(root, "test", manifest_path.to_path_buf()).await.unwrap());
+            let mut stats = IndexStats::default();
+            indexer.index_walk(&path, &mut stats).await.unwrap();
+            indexer.manifest.as_ref().unwrap().save().await.unwrap();
+            stats
+        }
+        async fn stored(indexer: &TestIndexer) -> usize {
+            indexer.vector_store.scroll(None, 100).await.unwrap().0.len()
+        }
+
+        let stats = run(&mut indexer, &root, &manifest_path, root.clone()).await;
+        assert_eq!((stats.indexed_files, stats.skipped_files), (3, 0));
+        assert_eq!(stored(&indexer).await, 3);
+
+        // Unchanged files are skipped, changed ones replaced and deleted ones purged.
+        std::fs::write(root.join("a.rs"), "fn alpha() {}\n\nfn delta() {}\n").unwrap();
+        std::fs::remove_file(root.join("b.rs")).unwrap();
+        let stats = run(&mut indexer, &root, &manifest_path, root.clone()).await;
+        assert_eq!((stats.indexed_files, stats.skipped_files, stats.removed_files), (1, 1, 1));
+        assert_eq!(stored(&indexer).await, 3);
+        let manifest = indexer.manifest.as_ref().unwrap();
+        assert_eq!(manifest.get("a.rs").unwrap().chunk_ids.len(), 2);
+        assert!(manifest.get("b.rs").is_none());
+
+        // The same root spelled differently is still unchanged.
+        let stats = run(&mut indexer, &root, &manifest_path, root.join("..").join("repo")).await;
+        assert_eq!((stats.indexed_files, stats.skipped_files, stats.removed_files), (0, 2, 0));
+        assert_eq!(stored(&indexer).await, 3);
+    }
+}
@@ -0,0 +1,156 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use tokio::fs;
+
+/// Statistics of the last run over each root indexed into a collection.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct IndexMetadata {
+    roots: BTreeMap<PathBuf, RootStatus>,
+    #[serde(skip)]
+    metadata_path: PathBuf,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RootStatus {
+    /// Canonical path of the root.
+    pub path: PathBuf,
+    /// Files seen by the last run.
+    pub total_files: usize,
+    /// Files with chunks in the index.
+    pub indexed_files: usize,
+    /// Files the last run failed to index.
+    pub failed_files: usize,
+    /// Chunks in the index.
+    pub total_chunks: usize,
+    /// Unix time the last run finished.
+    pub last_indexed: u64,
+    /// Commit the root was indexed at, if it was indexed with `--commit`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commit: Option<String>,
+    pub embedding_model: String,
+}
+
+/// Totals over a selection of roots.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct IndexStatus {
+    pub total_files: usize,
+    pub indexed_files: usize,
+    pub failed_files: usize,
+    pub total_chunks: usize,
+    /// HTTP date of the most recent run, or "never".
+    pub last_updated: String,
+    pub roots: Vec<RootStatus>,
+}
+
+impl IndexMetadata {
+    /// Loads the metadata of `collection`, or an empty store if nothing has
+    /// been indexed into it yet.
+    pub async fn load(collection: &str) -> Result<Self> {
+        let path = crate::config::data_dir()?
+            .join("metadata")
+            .join(format!("{}.json", collection));
+        Self::load_from(path).await
+    }
+
+    pub async fn load_from(metadata_path: PathBuf) -> Result<Self> {
+        if !metadata_path.exists() {
+            return Ok(Self {
+                roots: BTreeMap::new(),
+                metadata_path,
+            });
+        }
+
+        let data = fs::read_to_string(&metadata_path)
+            .await
+            .context("Failed to read index metadata")?;
+        let mut metadata: IndexMetadata = serde_json::from_str(&data).context("Failed to parse index metadata")?;
+        metadata.metadata_path = metadata_path;
+
+        Ok(metadata)
+    }
+
+    pub async fn save(&self) -> Result<()> {
+        if let Some(parent) = self.metadata_path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+
+        let tmp_path = self.metadata_path.with_extension("json.tmp");
+        fs::write(&tmp_path, serde_json::to_vec(self)?).await?;
+        fs::rename(&tmp_path, &self.metadata_path).await?;
+
+        Ok(())
+    }
+
+    pub fn record(&mut self, status: RootStatus) {
+        self.roots.insert(status.path.clone(), status);
+    }
+
+    /// Totals over the roots at or under any of `paths`, or over every root
+    /// when `paths` is empty.
+    pub fn status(&self, paths: &[PathBuf]) -> IndexStatus {
+        let paths: Vec<PathBuf> = paths
+            .iter()
+            .map(|path| path.canonicalize().unwrap_or_else(|_| path.clone()))
+            .collect();
+        let roots: Vec<RootStatus> = self
+            .roots
+            .values()
+            .filter(|root| paths.is_empty() || paths.iter().any(|path| root.path.starts_with(path)))
+            .cloned()
+            .collect();
+
+        let last_indexed = roots.iter().map(|root| root.last_indexed).max().unwrap_or_default();
+        IndexStatus {
+            total_files: roots.iter().map(|root| root.total_files).sum(),
+            indexed_files: roots.iter().map(|root| root.indexed_files).sum(),
+            failed_files: roots.iter().map(|root| root.failed_files).sum(),
+            total_chunks: roots.iter().map(|root| root.total_chunks).sum(),
+            last_updated: crate::utils::http_date(last_indexed),
+            roots,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn root(path: &str, files: usize) -> RootStatus {
+        RootStatus {
+            path: PathBuf::from(path),
+            total_files: files,
+            indexed_files: files,
+            failed_files: 0,
+            total_chunks: files * 3,
+            last_indexed: 1_700_000_000 + files as u64,
+            commit: None,
+            embedding_model: "local/feature-hash".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_status_survives_reload_and_filters_by_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("metadata").join("default.json");
+
+        let mut metadata = IndexMetadata::load_from(path.clone()).await.unwrap();
+        metadata.record(root("/work/api", 10));
+        metadata.record(root("/work/web", 20));
+        metadata.record(root("/other/tools", 5));
+        metadata.save().await.unwrap();
+
+        let metadata = IndexMetadata::load_from(path).await.unwrap();
+        let all = metadata.status(&[]);
+        assert_eq!(all.roots.len(), 3);
+        assert_eq!(all.total_chunks, 105);
+        assert_eq!(all.last_updated, "Tue, 14 Nov 2023 22:13:40 GMT");
+
+        let work = metadata.status(&[PathBuf::from("/work")]);
+        assert_eq!(work.indexed_files, 30);
+        let api = metadata.status(&[PathBuf::from("/work/api")]);
+        assert_eq!(api.roots, vec![root("/work/api", 10)]);
+        assert_eq!(metadata.status(&[PathBuf::from("/missing")]).last_updated, "never");
+    }
+}
@@ -0,0 +1,112 @@
+//! Moves a collection to another embedding model by re-embedding the chunk
+//! content stored in its payloads, so the source files are not needed.
+
+use crate::{
+    embeddings::EmbeddingProvider,
+    vector::{CollectionSchema, Document, VectorStore},
+    BuildliError, Result,
+};
+use tracing::info;
+
+/// Re-embeds every chunk of `source` with `embedder` and writes it to
+/// `target`, `batch_size` chunks at a time. Chunk IDs and payloads are kept,
+/// so manifests and the lexical index remain valid. Returns the number of
+/// chunks written.
+pub async fn migrate_embeddings<E, S, T>(embedder: &E, source: &S, target: &T, batch_size: usize) -> Result<usize>
+where
+    E: EmbeddingProvider,
+    S: VectorStore,
+    T: VectorStore,
+{
+    let dimension = embedder
+        .dimension()
+        .await
+        .map_err(|e| BuildliError::Embedding(e.to_string()))?;
+    let schema = CollectionSchema {
+        model: embedder.model_name(),
+        dimension,
+    };
+    target.initialize(&schema).await.map_err(vector_store_error)?;
+
+    let mut offset = None;
+    let mut migrated = 0;
+    loop {
+        let (chunks, next) = source
+            .scroll(offset, batch_size.max(1))
+            .await
+            .map_err(vector_store_error)?;
+
+        if !chunks.is_empty() {
+            let texts: Vec<String> = chunks
+                .iter()
+                .map(|chunk| {
+                    let content = chunk.metadata.get("content").and_then(|v| v.as_str());
+                    content.unwrap_or_default().to_string()
+                })
+                .collect();
+            let embeddings = embedder
+                .embed_batch(&texts)
+                .await
+                .map_err(|e| BuildliError::Embedding(e.to_string()))?;
+
+            let documents: Vec<Document> = chunks
+                .into_iter()
+                .zip(embeddings)
+                .map(|(chunk, embedding)| Document {
+                    id: chunk.id,
+                    embedding,
+                    metadata: chunk.metadata,
+                })
+                .collect();
+            migrated += documents.len();
+            target.upsert_documents(documents).await.map_err(vector_store_error)?;
+            info!("Re-embedded {} chunks", migrated);
+        }
+
+        match next {
+            Some(next) => offset = Some(next),
+            None => return Ok(migrated),
+        }
+    }
+}
+
+fn vector_store_error(e: anyhow::Error) -> BuildliError {
+    match e.downcast::<BuildliError>() {
+        Ok(e) => e,
+        Err(e) => BuildliError::VectorStore(e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::embeddings::LocalEmbeddings;
+    use crate::vector::{LocalVectorStore, SearchFilter};
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    #[tokio::test]
+    async fn test_re_embeds_stored_content() {
+        let source = LocalVectorStore::new();
+        let documents = ["fn parse_config() {}", "struct Indexer;", "fn main() {}"]
+            .iter()
+            .enumerate()
+            .map(|(i, content)| Document {
+                id: format!("chunk-{}", i),
+                embedding: vec![1.0, i as f32],
+                metadata: HashMap::from([("content".to_string(), json!(content))]),
+            })
+            .collect();
+        source.upsert_documents(documents).await.unwrap();
+
+        let embedder = LocalEmbeddings::new();
+        let target = LocalVectorStore::new();
+        let migrated = migrate_embeddings(&embedder, &source, &target, 2).await.unwrap();
+        assert_eq!(migrated, 3);
+
+        let query = embedder.embed("struct Indexer;").await.unwrap();
+        let results = target.search(query, 1, &SearchFilter::default()).await.unwrap();
+        assert_eq!(results[0].id, "chunk-1");
+        assert_eq!(results[0].metadata["content"], json!("struct Indexer;"));
+    }
+}
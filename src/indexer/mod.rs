@@ -1,35 +1,139 @@
 pub mod factory;
+pub mod git;
+pub mod lexical;
+pub mod manifest;
+pub mod metadata;
+pub mod migrate;
 pub mod parser;
+mod pipeline;
+pub mod progress;
 pub mod walker;
 
-use crate::{embeddings::EmbeddingProvider, vector::VectorStore, BuildliError, Result};
+use crate::{
+    config::IndexingConfig,
+    embeddings::EmbeddingProvider,
+    vector::{CollectionSchema, VectorStore},
+    BuildliError, Result,
+};
 use anyhow::Context;
-use parser::{CodeChunk, LanguageParser};
-use std::path::Path;
+use git::GitTree;
+use lexical::LexicalIndex;
+use manifest::{Manifest, ManifestEntry};
+use metadata::{IndexMetadata, RootStatus};
+use parser::LanguageParser;
+use pipeline::{Job, ParsedFile};
+use progress::{emit, ProgressEvent, ProgressSender};
+use std::collections::HashSet;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
 use tracing::{debug, info};
 use walker::FileWalker;
 
 pub struct Indexer<E: EmbeddingProvider, V: VectorStore> {
     parser: LanguageParser,
-    embedder: E,
+    embedder: Arc<E>,
     vector_store: V,
     file_walker: FileWalker,
+    repo: Option<String>,
+    repo_root: Option<PathBuf>,
+    commit: Option<String>,
+    collection: String,
+    manifest: Option<Manifest>,
+    lexical: Option<LexicalIndex>,
+    force: bool,
+    ignore_tests: bool,
+    indexing: IndexingConfig,
+    /// Chunks embedded and upserted together.
+    batch_size: usize,
+    progress: Option<ProgressSender>,
+    /// Whether to count the BPE tokens of parsed chunks for progress reports.
+    count_tokens: bool,
+    /// Set once the collection has been checked against the embedder.
+    schema: Option<CollectionSchema>,
 }
 
 impl<E: EmbeddingProvider, V: VectorStore> Indexer<E, V> {
     pub fn new(embedder: E, vector_store: V) -> Self {
         Self {
             parser: LanguageParser::new(),
-            embedder,
+            embedder: Arc::new(embedder),
             vector_store,
             file_walker: FileWalker::new(),
+            repo: None,
+            repo_root: None,
+            commit: None,
+            collection: "default".to_string(),
+            manifest: None,
+            lexical: None,
+            force: false,
+            ignore_tests: false,
+            indexing: IndexingConfig::default(),
+            batch_size: 100,
+            progress: None,
+            count_tokens: false,
+            schema: None,
         }
     }
 
+    /// Name of the collection being written to. Manifests and the lexical
+    /// index are kept per collection so switching backends or collections
+    /// triggers a full index.
+    pub fn with_collection(mut self, collection: impl Into<String>) -> Self {
+        self.collection = collection.into();
+        self
+    }
+
+    /// Skips test files and test-only code when indexing.
+    pub fn with_ignore_tests(mut self, ignore_tests: bool) -> Self {
+        self.parser = self.parser.with_skip_tests(ignore_tests);
+        self.file_walker = self.file_walker.with_ignore_tests(ignore_tests);
+        self.ignore_tests = ignore_tests;
+        self
+    }
+
+    /// Number of parse workers and concurrent embedding requests.
+    pub fn with_indexing_config(mut self, indexing: &IndexingConfig) -> Self {
+        self.indexing = indexing.clone();
+        self
+    }
+
+    /// Number of chunks to embed and upsert at a time.
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size;
+        self
+    }
+
+    /// Re-indexes files even if the manifest says they are unchanged.
+    pub fn with_force(mut self, force: bool) -> Self {
+        self.force = force;
+        self
+    }
+
+    /// Reports progress events to `progress` while indexing.
+    pub fn with_progress(mut self, progress: ProgressSender) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    /// Counts the tokens of parsed chunks for progress reports. Counting
+    /// costs a BPE pass over every chunk, so only enable it for providers
+    /// that bill by those tokens.
+    pub fn with_token_counting(mut self, count_tokens: bool) -> Self {
+        self.count_tokens = count_tokens;
+        self
+    }
+
     pub async fn index_path(&mut self, path: &Path, watch: bool) -> Result<IndexStats> {
         info!("Starting indexing of path: {}", path.display());
         
         let mut stats = IndexStats::default();
+        self.repo = detect_repo(path);
+        self.repo_root = find_repo_root(path);
+        self.commit = None;
+        emit(&self.progress, ProgressEvent::Started { root: path.to_path_buf() });
+        self.manifest = Some(Manifest::load(path, &self.collection).await?);
+        self.load_lexical_index().await?;
+        self.initialize_collection().await?;
         
         if watch {
             self.index_with_watch(path, &mut stats).await?;
@@ -41,16 +145,117 @@ impl<E: EmbeddingProvider, V: VectorStore> Indexer<E, V> {
     }
 
     async fn index_once(&mut self, path: &Path, stats: &mut IndexStats) -> Result<()> {
+        self.index_walk(path, stats).await?;
+        self.save_state().await?;
+        self.record_status(stats).await?;
+        emit(&self.progress, ProgressEvent::Finished);
+        Ok(())
+    }
+
+    /// Indexes the working-tree files under `path` and purges the ones that
+    /// are gone, without persisting anything.
+    async fn index_walk(&mut self, path: &Path, stats: &mut IndexStats) -> Result<()> {
         let files = self.file_walker.walk(path)?;
+        let seen: HashSet<String> = files.iter().map(|file| self.manifest_key(file)).collect();
+        emit(&self.progress, ProgressEvent::Discovered { files: files.len() });
+        
+        self.run_pipeline(
+            |jobs| async move {
+                for path in files {
+                    if jobs.send(Job { path, content: None }).await.is_err() {
+                        break;
+                    }
+                }
+                Ok(0)
+            },
+            stats,
+        )
+        .await?;
+        
+        self.purge_missing(&seen, stats).await
+    }
+
+    /// Indexes the files under `path` as they exist in `commit`, reading blobs
+    /// from the git object database instead of the working tree.
+    pub async fn index_commit(&mut self, path: &Path, commit: &str) -> Result<IndexStats> {
+        let tree = GitTree::open(path, commit)
+            .await
+            .map_err(|e| BuildliError::Indexing(e.to_string()))?;
+        info!("Indexing {} at commit {}", path.display(), tree.commit());
         
-        for file_path in files {
-            if let Err(e) = self.index_file(&file_path, stats).await {
-                debug!("Failed to index {}: {}", file_path.display(), e);
-                stats.failed_files += 1;
+        let mut stats = IndexStats::default();
+        emit(&self.progress, ProgressEvent::Started { root: path.to_path_buf() });
+        self.manifest = Some(Manifest::load(path, &self.collection).await?);
+        self.load_lexical_index().await?;
+        self.index_tree(path, &tree, &mut stats).await?;
+        self.save_state().await?;
+        self.record_status(&stats).await?;
+        emit(&self.progress, ProgressEvent::Finished);
+        Ok(stats)
+    }
+
+    /// Indexes the files under `path` as they exist in `tree` and purges the
+    /// ones that are not in it, without persisting anything.
+    async fn index_tree(&mut self, path: &Path, tree: &GitTree, stats: &mut IndexStats) -> Result<()> {
+        self.repo = detect_repo(path);
+        self.repo_root = find_repo_root(path);
+        self.commit = Some(tree.commit().to_string());
+        self.initialize_collection().await?;
+        let mut seen = HashSet::new();
+        
+        // Only index the part of the tree that `path` refers to.
+        let scope = path
+            .canonicalize()?
+            .strip_prefix(tree.repo_root().canonicalize()?)
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+        
+        let entries = tree.files().await.map_err(|e| BuildliError::Indexing(e.to_string()))?;
+        let mut blobs = tree.blob_reader().await.map_err(|e| BuildliError::Indexing(e.to_string()))?;
+        
+        let mut files = Vec::new();
+        for entry in entries {
+            let Ok(relative) = entry.path.strip_prefix(&scope) else {
+                continue;
+            };
+            // Report paths the same way a working-tree walk of `path` would.
+            let file_path = path.join(relative);
+            if self.file_walker.is_ignored(relative) {
+                continue;
             }
+            seen.insert(self.manifest_key(&file_path));
+            files.push((file_path, entry));
         }
+        emit(&self.progress, ProgressEvent::Discovered { files: files.len() });
         
-        Ok(())
+        self.run_pipeline(
+            |jobs| async move {
+                let mut failed = 0;
+                for (path, entry) in files {
+                    let content = match blobs.read(&entry.oid).await {
+                        Ok(bytes) => match String::from_utf8(bytes) {
+                            Ok(content) => content,
+                            // Binary file
+                            Err(_) => continue,
+                        },
+                        Err(e) => {
+                            debug!("Failed to read {} from git: {}", entry.path.display(), e);
+                            failed += 1;
+                            continue;
+                        }
+                    };
+                    if jobs.send(Job { path, content: Some(content) }).await.is_err() {
+                        break;
+                    }
+                }
+                blobs.close().await.map_err(|e| BuildliError::Indexing(e.to_string()))?;
+                Ok(failed)
+            },
+            stats,
+        )
+        .await?;
+        
+        self.purge_missing(&seen, stats).await
     }
 
     async fn index_with_watch(&mut self, path: &Path, stats: &mut IndexStats) -> Result<()> {
@@ -59,6 +264,11 @@ impl<E: EmbeddingProvider, V: VectorStore> Indexer<E, V> {
         let watcher = self.file_walker.watch(path)?;
         
         while let Ok(event) = watcher.recv() {
+            // Skip what a walk of `path` would skip.
+            if self.file_walker.is_ignored_under(path, event.path()) {
+                continue;
+            }
+            
             match event {
                 walker::WatchEvent::Created(path) | walker::WatchEvent::Modified(path) => {
                     if let Err(e) = self.index_file(&path, stats).await {
@@ -71,53 +281,260 @@ impl<E: EmbeddingProvider, V: VectorStore> Indexer<E, V> {
                     }
                 }
             }
+            
+            if let Err(e) = self.save_state().await {
+                debug!("Failed to save index state: {}", e);
+            }
         }
         
         Ok(())
     }
 
-    async fn index_file(&mut self, path: &Path, stats: &mut IndexStats) -> Result<()> {
+    pub async fn index_file(&mut self, path: &Path, stats: &mut IndexStats) -> Result<()> {
         debug!("Indexing file: {}", path.display());
         
-        let chunks = self.parser.parse_file(path).await?;
+        let content = tokio::fs::read_to_string(path)
+            .await
+            .context("Failed to read file")?;
+        
+        self.index_content(path, &content, stats).await
+    }
+
+    async fn index_content(&mut self, path: &Path, content: &str, stats: &mut IndexStats) -> Result<()> {
         stats.total_files += 1;
         
-        if chunks.is_empty() {
-            return Ok(());
+        let key = self.manifest_key(path);
+        let sha256 = manifest::content_hash(content);
+        if let Some(manifest) = self.manifest.as_ref().filter(|_| !self.force) {
+            if manifest.is_unchanged(&key, &sha256, self.commit.as_deref()) {
+                stats.skipped_files += 1;
+                emit(&self.progress, ProgressEvent::Unchanged);
+                return Ok(());
+            }
         }
         
-        let chunk_texts: Vec<String> = chunks.iter().map(|c| c.content.clone()).collect();
-        let embeddings = self.embedder.embed_batch(&chunk_texts).await
-            .map_err(|e| BuildliError::Embedding(e.to_string()))?;
+        let chunks = self.file_context().parse(&mut self.parser, path, content)?;
+        let file = ParsedFile::new(path.to_path_buf(), key, sha256, chunks, self.count_tokens);
+        emit(&self.progress, ProgressEvent::Parsed { chunks: file.chunks.len(), tokens: file.tokens });
+        let (chunks, tokens) = (file.chunks.len(), file.tokens);
+        let files = vec![file];
         
-        let documents: Vec<_> = chunks
-            .into_iter()
-            .zip(embeddings)
-            .map(|(chunk, embedding)| {
-                self.vector_store.create_document(chunk, embedding)
-            })
-            .collect();
+        self.initialize_collection().await?;
+        let embeddings = pipeline::embed_files(&*self.embedder, &files).await?;
+        emit(&self.progress, ProgressEvent::Embedded { chunks, tokens });
+        stats.total_chunks += self.store_files(files, embeddings).await?;
+        stats.indexed_files += 1;
+        emit(&self.progress, ProgressEvent::Stored { files: 1, chunks });
+        
+        Ok(())
+    }
+
+    /// Loads the manifest of `root`, so that files reindexed one at a time
+    /// through [`index_file`](Self::index_file) replace the chunks recorded
+    /// for them when `root` was indexed.
+    pub async fn load_manifest(&mut self, root: &Path) -> Result<()> {
+        self.repo = detect_repo(root);
+        self.repo_root = find_repo_root(root);
+        self.manifest = Some(Manifest::load(root, &self.collection).await?);
+        Ok(())
+    }
+
+    /// Removes every chunk previously indexed for `path`.
+    pub async fn delete_file_chunks(&mut self, path: &Path) -> Result<()> {
+        let key = self.manifest_key(path);
+        if self.manifest.as_ref().and_then(|manifest| manifest.get(&key)).is_some() {
+            return self.delete_entry(&key).await;
+        }
         
-        self.vector_store.upsert_documents(documents).await
-            .map_err(|e| BuildliError::VectorStore(e.to_string()))?;
+        // Without a manifest entry we do not know the chunk IDs, so match on the path instead.
+        if let Some(lexical) = &mut self.lexical {
+            lexical.remove_file(&path.display().to_string());
+        }
+        self.vector_store
+            .delete_by_file(path)
+            .await
+            .map_err(|e| BuildliError::VectorStore(e.to_string()))
+    }
+
+    /// Removes the manifest entry `key` and its chunks. Chunks that another
+    /// entry also refers to are kept: IDs only depend on the repo-relative
+    /// path, so a stale entry may share them with a live one.
+    async fn delete_entry(&mut self, key: &str) -> Result<()> {
+        let Some(manifest) = &mut self.manifest else {
+            return Ok(());
+        };
+        let Some(entry) = manifest.remove(key) else {
+            return Ok(());
+        };
+        let live: HashSet<&String> = manifest.chunk_ids().collect();
+        let chunk_ids: Vec<String> = entry.chunk_ids.into_iter().filter(|id| !live.contains(id)).collect();
         
-        stats.indexed_files += 1;
-        stats.total_chunks += chunk_texts.len();
+        if let Some(lexical) = &mut self.lexical {
+            for id in &chunk_ids {
+                lexical.remove(id);
+            }
+        }
+        self.vector_store
+            .delete_documents(chunk_ids)
+            .await
+            .map_err(|e| BuildliError::VectorStore(e.to_string()))
+    }
+
+    /// Key of `path` in the current root's manifest.
+    fn manifest_key(&self, path: &Path) -> String {
+        match &self.manifest {
+            Some(manifest) => manifest.key(path),
+            None => path.display().to_string(),
+        }
+    }
+
+    fn record_file(&mut self, key: String, sha256: String, chunk_ids: Vec<String>) {
+        if let Some(manifest) = &mut self.manifest {
+            manifest.insert(key, ManifestEntry {
+                sha256,
+                chunk_ids,
+                commit: self.commit.clone(),
+            });
+        }
+    }
+
+    /// Deletes the chunks of manifest files that were not seen in this run.
+    async fn purge_missing(&mut self, seen: &HashSet<String>, stats: &mut IndexStats) -> Result<()> {
+        let Some(manifest) = &self.manifest else {
+            return Ok(());
+        };
+        
+        let missing: Vec<String> = manifest
+            .file_paths()
+            .filter(|path| !seen.contains(*path))
+            .cloned()
+            .collect();
+        
+        for path in missing {
+            debug!("Removing chunks of deleted file {}", path);
+            self.delete_entry(&path).await?;
+            stats.removed_files += 1;
+            emit(&self.progress, ProgressEvent::Removed { files: 1 });
+        }
         
         Ok(())
     }
 
-    async fn delete_file_chunks(&mut self, path: &Path) -> Result<()> {
-        self.vector_store.delete_by_file(path).await
-            .map_err(|e| BuildliError::VectorStore(e.to_string()))?;
+    /// Loads the collection's lexical index, so that files indexed one at a
+    /// time through [`index_file`](Self::index_file) keep it up to date.
+    pub async fn load_lexical_index(&mut self) -> Result<()> {
+        if self.lexical.is_none() {
+            self.lexical = Some(LexicalIndex::load(&self.collection).await?);
+        }
         Ok(())
     }
+
+    /// Probes the embedder's vector size and makes sure the collection was
+    /// created for the same model before anything is written to it.
+    async fn initialize_collection(&mut self) -> Result<()> {
+        if self.schema.is_some() {
+            return Ok(());
+        }
+
+        let dimension = self
+            .embedder
+            .dimension()
+            .await
+            .map_err(|e| BuildliError::Embedding(e.to_string()))?;
+        let schema = CollectionSchema {
+            model: self.embedder.model_name(),
+            dimension,
+        };
+
+        self.vector_store
+            .initialize(&schema)
+            .await
+            .map_err(|e| match e.downcast::<BuildliError>() {
+                Ok(e) => e,
+                Err(e) => BuildliError::VectorStore(e.to_string()),
+            })?;
+        self.schema = Some(schema);
+        Ok(())
+    }
+
+    /// Records the statistics of the run over the current root in the
+    /// collection's metadata.
+    async fn record_status(&self, stats: &IndexStats) -> Result<()> {
+        let Some(manifest) = &self.manifest else {
+            return Ok(());
+        };
+
+        let mut metadata = IndexMetadata::load(&self.collection).await?;
+        metadata.record(RootStatus {
+            path: manifest.root().to_path_buf(),
+            total_files: stats.total_files,
+            indexed_files: manifest.file_count(),
+            failed_files: stats.failed_files,
+            total_chunks: manifest.chunk_count(),
+            last_indexed: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+            commit: self.commit.clone(),
+            embedding_model: self.embedder.model_name(),
+        });
+        metadata.save().await?;
+        Ok(())
+    }
+
+    /// Persists the manifest and lexical index.
+    pub async fn save_state(&self) -> Result<()> {
+        if let Some(manifest) = &self.manifest {
+            manifest.save().await?;
+        }
+        if let Some(lexical) = &self.lexical {
+            lexical.save().await?;
+        }
+        Ok(())
+    }
+}
+
+/// Name recorded in the `repo` payload field: the directory name of the
+/// enclosing git repository, or of `path` itself when it is not in one.
+pub fn detect_repo(path: &Path) -> Option<String> {
+    find_repo_root(path)?
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+}
+
+/// Absolute path of the git repository containing `path`, falling back to
+/// `path` itself (or its directory) outside of git.
+pub fn find_repo_root(path: &Path) -> Option<PathBuf> {
+    let path = normalize_path(path)?;
+    let start = if path.is_file() { path.parent()? } else { path.as_path() };
+    let root = start
+        .ancestors()
+        .find(|dir| dir.join(".git").exists())
+        .unwrap_or(start);
+    
+    Some(root.to_path_buf())
+}
+
+/// Makes `path` absolute and drops `.` components without resolving
+/// symlinks, so it also works for paths that only exist in a git tree.
+fn normalize_path(path: &Path) -> Option<PathBuf> {
+    let absolute = std::path::absolute(path).ok()?;
+    Some(
+        absolute
+            .components()
+            .filter(|c| !matches!(c, Component::CurDir))
+            .collect(),
+    )
 }
 
 #[derive(Debug, Default)]
 pub struct IndexStats {
     pub total_files: usize,
     pub indexed_files: usize,
+    /// Files whose content was unchanged since the last run.
+    pub skipped_files: usize,
+    /// Files that disappeared since the last run and had their chunks purged.
+    pub removed_files: usize,
     pub failed_files: usize,
     pub total_chunks: usize,
 }
\ No newline at end of file
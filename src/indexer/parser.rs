@@ -3,7 +3,8 @@ use once_cell::sync::Lazy;
 use std::collections::HashMap;
 use std::path::Path;
 use tokio::fs;
-use tree_sitter::{Language, Parser, Query, QueryCursor};
+use regex::Regex;
+use tree_sitter::{Language, Node, Parser, Query, QueryCursor};
 
 #[derive(Debug, Clone)]
 pub struct CodeChunk {
@@ -13,6 +14,14 @@ pub struct CodeChunk {
     pub line_end: usize,
     pub chunk_type: ChunkType,
     pub language: String,
+    /// Repository the chunk belongs to; filled in by the indexer.
+    pub repo: Option<String>,
+    /// Commit the chunk was read from when indexing a git revision.
+    pub commit: Option<String>,
+    /// Path relative to the repository root; filled in by the indexer.
+    pub relative_path: Option<String>,
+    /// Qualified name of the item the chunk covers, e.g. `Indexer::index_file`.
+    pub symbol: Option<String>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -30,6 +39,9 @@ static LANGUAGE_MAP: Lazy<HashMap<&str, Language>> = Lazy::new(|| {
     map.insert("rs", tree_sitter_rust::language());
     map.insert("py", tree_sitter_python::language());
     map.insert("js", tree_sitter_javascript::language());
+    map.insert("jsx", tree_sitter_javascript::language());
+    map.insert("mjs", tree_sitter_javascript::language());
+    map.insert("cjs", tree_sitter_javascript::language());
     map.insert("ts", tree_sitter_typescript::language_typescript());
     map.insert("tsx", tree_sitter_typescript::language_tsx());
     map.insert("go", tree_sitter_go::language());
@@ -43,9 +55,34 @@ static LANGUAGE_MAP: Lazy<HashMap<&str, Language>> = Lazy::new(|| {
     map
 });
 
+/// Maps a user-facing language name (`rust`, `TypeScript`, `c++`, ...) to the
+/// language codes stored on chunks, which are the file extensions in
+/// `LANGUAGE_MAP`. Unknown names are passed through lowercased.
+pub fn language_codes(name: &str) -> Vec<String> {
+    let name = name.trim().to_lowercase();
+    let codes: &[&str] = match name.as_str() {
+        "rust" => &["rs"],
+        "python" => &["py"],
+        "javascript" => &["js", "jsx", "mjs", "cjs"],
+        "typescript" => &["ts", "tsx"],
+        "golang" => &["go"],
+        "cpp" | "c++" => &["cpp", "cc", "cxx", "hpp"],
+        "c" => &["c", "h"],
+        _ => return vec![name],
+    };
+    codes.iter().map(|c| c.to_string()).collect()
+}
+
+/// Matches Rust attributes that mark test-only code: `#[cfg(test)]`,
+/// `#[test]` and framework variants such as `#[tokio::test]`.
+static RUST_TEST_ATTRIBUTE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^#\[\s*(cfg\s*\(\s*test\s*\)|([A-Za-z_][\w]*::)*test\b)").unwrap()
+});
+
 pub struct LanguageParser {
     parsers: HashMap<String, Parser>,
     queries: HashMap<String, Query>,
+    skip_tests: bool,
 }
 
 impl LanguageParser {
@@ -63,7 +100,18 @@ impl LanguageParser {
             }
         }
         
-        Self { parsers, queries }
+        Self {
+            parsers,
+            queries,
+            skip_tests: false,
+        }
+    }
+
+    /// Drops test-only items (currently Rust `#[cfg(test)]` modules and
+    /// `#[test]` functions) from the parsed chunks.
+    pub fn with_skip_tests(mut self, skip_tests: bool) -> Self {
+        self.skip_tests = skip_tests;
+        self
     }
 
     pub async fn parse_file(&mut self, path: &Path) -> Result<Vec<CodeChunk>> {
@@ -71,13 +119,19 @@ impl LanguageParser {
             .await
             .context("Failed to read file")?;
         
-        let language = self.detect_language(path, &content);
+        self.parse_content(path, &content)
+    }
+
+    /// Parses `content` as if it were the contents of `path`, for sources that
+    /// are not read from the working tree (e.g. git blobs).
+    pub fn parse_content(&mut self, path: &Path, content: &str) -> Result<Vec<CodeChunk>> {
+        let language = self.detect_language(path, content);
         
         if self.parsers.contains_key(&language) {
             let parser = self.parsers.get_mut(&language).unwrap();
-            Self::parse_with_tree_sitter(path, &content, &language, parser, &self.queries)
+            Self::parse_with_tree_sitter(path, content, &language, parser, &self.queries, self.skip_tests)
         } else {
-            Ok(Self::fallback_parse(path, &content, &language))
+            Ok(Self::fallback_parse(path, content, &language))
         }
     }
 
@@ -105,12 +159,14 @@ impl LanguageParser {
         language: &str,
         parser: &mut Parser,
         queries: &HashMap<String, Query>,
+        skip_tests: bool,
     ) -> Result<Vec<CodeChunk>> {
         let tree = parser
             .parse(content, None)
             .context("Failed to parse file")?;
         
         let mut chunks = Vec::new();
+        let mut skipped_tests = false;
         let mut cursor = QueryCursor::new();
         
         if let Some(query) = queries.get(language) {
@@ -119,6 +175,11 @@ impl LanguageParser {
             for match_ in matches {
                 for capture in match_.captures {
                     let node = capture.node;
+                    if skip_tests && language == "rs" && Self::is_rust_test_item(node, content) {
+                        skipped_tests = true;
+                        continue;
+                    }
+                    
                     let start_byte = node.start_byte();
                     let end_byte = node.end_byte();
                     let chunk_content = &content[start_byte..end_byte];
@@ -134,18 +195,77 @@ impl LanguageParser {
                         line_end: end_line,
                         chunk_type: Self::node_to_chunk_type(&capture.node.kind()),
                         language: language.to_string(),
+                        repo: None,
+                        commit: None,
+                        relative_path: None,
+                        symbol: Self::symbol_path(node, content, language),
                     });
                 }
             }
         }
         
-        if chunks.is_empty() {
+        // A file consisting only of tests should produce no chunks, not a fallback split.
+        if chunks.is_empty() && !skipped_tests {
             chunks = Self::fallback_parse(path, content, language);
         }
         
         Ok(chunks)
     }
 
+    /// Builds a qualified name for `node` from the names of the definitions
+    /// enclosing it, e.g. `Indexer::index_file` or `UserService.save`.
+    fn symbol_path(node: Node, content: &str, language: &str) -> Option<String> {
+        let mut names = Vec::new();
+        let mut current = Some(node);
+        
+        while let Some(item) = current {
+            let name_node = match item.kind() {
+                // `impl<T> Foo<T>` is recorded as `Foo`.
+                "impl_item" => item.child_by_field_name("type").map(|ty| match ty.kind() {
+                    "generic_type" => ty.child_by_field_name("type").unwrap_or(ty),
+                    _ => ty,
+                }),
+                "type_declaration" => item.named_child(0).and_then(|spec| spec.child_by_field_name("name")),
+                "function_item" | "struct_item" | "enum_item" | "trait_item" | "mod_item"
+                | "function_definition" | "class_definition" | "function_declaration"
+                | "class_declaration" | "method_definition" | "method_declaration"
+                | "interface_declaration" | "variable_declarator" => item.child_by_field_name("name"),
+                _ => None,
+            };
+            
+            if let Some(name_node) = name_node {
+                names.push(&content[name_node.byte_range()]);
+            }
+            current = item.parent();
+        }
+        
+        if names.is_empty() {
+            return None;
+        }
+        
+        names.reverse();
+        let separator = if language == "rs" { "::" } else { "." };
+        Some(names.join(separator))
+    }
+
+    /// Whether `node` or any of its ancestors is annotated with a test attribute.
+    fn is_rust_test_item(node: Node, content: &str) -> bool {
+        let mut current = Some(node);
+        
+        while let Some(item) = current {
+            let mut sibling = item.prev_sibling();
+            while let Some(attribute) = sibling.filter(|s| s.kind() == "attribute_item") {
+                if RUST_TEST_ATTRIBUTE.is_match(&content[attribute.byte_range()]) {
+                    return true;
+                }
+                sibling = attribute.prev_sibling();
+            }
+            current = item.parent();
+        }
+        
+        false
+    }
+
     fn create_query_for_language(ext: &str, language: &Language) -> Result<Query> {
         let query_string = match ext {
             "rs" => r#"
@@ -161,7 +281,7 @@ impl LanguageParser {
                 (class_definition) @class
                 (decorated_definition) @decorated
             "#,
-            "js" | "ts" | "tsx" => r#"
+            "js" | "jsx" | "mjs" | "cjs" | "ts" | "tsx" => r#"
                 (function_declaration) @function
                 (arrow_function) @arrow_function
                 (class_declaration) @class
@@ -231,6 +351,10 @@ impl LanguageParser {
                 line_end: end,
                 chunk_type: ChunkType::Other,
                 language: language.to_string(),
+                repo: None,
+                commit: None,
+                relative_path: None,
+                symbol: None,
             });
             
             i += chunk_size - overlap;
@@ -238,4 +362,35 @@ impl LanguageParser {
         
         chunks
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_skip_rust_tests() {
+        let source = "fn add(a: i32, b: i32) -> i32 {\n    a + b\n}\n\n#[cfg(test)]\nmod tests {\n    #[test]\n    fn adds() {\n        assert_eq!(super::add(1, 2), 3);\n    }\n}\n\n#[tokio::test]\nasync fn standalone() {}\n";
+        let mut parser = LanguageParser::new().with_skip_tests(true);
+        let chunks = parser.parse_content(Path::new("lib.rs"), source).unwrap();
+        
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].line_start, 1);
+    }
+
+    #[test]
+    fn test_language_codes() {
+        assert_eq!(language_codes("Rust"), vec!["rs"]);
+        assert_eq!(language_codes("javascript"), vec!["js", "jsx", "mjs", "cjs"]);
+        assert_eq!(language_codes(" C++ "), vec!["cpp", "cc", "cxx", "hpp"]);
+        assert_eq!(language_codes("Kotlin"), vec!["kotlin"]);
+
+        // Every code a language expands to is one that chunks are tagged with.
+        let mut parser = LanguageParser::new();
+        for code in language_codes("javascript") {
+            let path = std::path::PathBuf::from(format!("app.{}", code));
+            let chunks = parser.parse_content(&path, "function render() {}\n").unwrap();
+            assert_eq!(chunks[0].language, code);
+        }
+    }
+}
@@ -0,0 +1,393 @@
+//! Staged indexing pipeline. Files flow through bounded channels, so a slow
+//! stage holds back the ones before it:
+//!
+//! 1. a source sends the files to index (a directory walk or git blobs);
+//! 2. parse workers on the blocking thread pool hash, skip unchanged files
+//!    and parse the rest with tree-sitter;
+//! 3. parsed files are grouped into batches of about `batch_size` chunks and
+//!    embedded with several requests in flight;
+//! 4. embedded batches replace the files' previous chunks with one upsert
+//!    each and are recorded in the manifest and lexical index.
+
+use super::parser::{CodeChunk, LanguageParser};
+use super::progress::{emit, ProgressEvent, ProgressSender};
+use super::{find_repo_root, manifest, normalize_path, IndexStats, Indexer};
+use crate::{embeddings::{batching, EmbeddingProvider}, vector::VectorStore, BuildliError, Result};
+use futures::StreamExt;
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+use tracing::debug;
+
+/// Capacity of the channels between stages, in files.
+const CHANNEL_CAPACITY: usize = 64;
+
+/// A file for the pipeline to index, read from disk unless `content` is set.
+pub(super) struct Job {
+    pub path: PathBuf,
+    pub content: Option<String>,
+}
+
+/// A parsed file whose chunks are ready to embed.
+pub(super) struct ParsedFile {
+    pub path: PathBuf,
+    /// Manifest key of the file.
+    pub key: String,
+    pub sha256: String,
+    pub chunks: Vec<CodeChunk>,
+    /// Tokens in the chunks, counted only when token counting is enabled.
+    pub tokens: usize,
+}
+
+impl ParsedFile {
+    pub fn new(path: PathBuf, key: String, sha256: String, chunks: Vec<CodeChunk>, count_tokens: bool) -> Self {
+        let tokens = match count_tokens {
+            true => chunks.iter().map(|chunk| batching::count_tokens(&chunk.content)).sum(),
+            false => 0,
+        };
+        Self {
+            path,
+            key,
+            sha256,
+            chunks,
+            tokens,
+        }
+    }
+}
+
+enum Parsed {
+    File(ParsedFile),
+    Unchanged,
+    Failed,
+}
+
+/// Where files come from, recorded on their chunks.
+#[derive(Clone)]
+pub(super) struct FileContext {
+    pub repo: Option<String>,
+    pub repo_root: Option<PathBuf>,
+    pub commit: Option<String>,
+    /// Canonical root of the manifest, which keys files relative to it.
+    pub manifest_root: Option<PathBuf>,
+}
+
+impl FileContext {
+    /// Parses `content` as the contents of `path` and tags the chunks with
+    /// their repository, commit and repo-relative path.
+    pub fn parse(&self, parser: &mut LanguageParser, path: &Path, content: &str) -> Result<Vec<CodeChunk>> {
+        let mut chunks = parser.parse_content(path, content)?;
+
+        let repo_root = self.repo_root.clone().or_else(|| find_repo_root(path));
+        let repo = self.repo.clone().or_else(|| super::detect_repo(path));
+        let relative_path = repo_root.zip(normalize_path(path)).and_then(|(root, file)| {
+            file.strip_prefix(root).ok().map(|p| p.to_string_lossy().to_string())
+        });
+
+        for chunk in &mut chunks {
+            chunk.repo = repo.clone();
+            chunk.commit = self.commit.clone();
+            chunk.relative_path = relative_path.clone();
+        }
+
+        Ok(chunks)
+    }
+}
+
+/// Parsed files embedded together, along with the files the parse stage
+/// skipped or failed on since the previous batch.
+#[derive(Default)]
+struct Batch {
+    files: Vec<ParsedFile>,
+    chunks: usize,
+    unchanged: usize,
+    failed: usize,
+}
+
+impl<E: EmbeddingProvider, V: VectorStore> Indexer<E, V> {
+    /// Indexes the files that `source` sends, and returns what `source`
+    /// returns: the number of files it failed to read.
+    pub(super) async fn run_pipeline<S, F>(&mut self, source: S, stats: &mut IndexStats) -> Result<()>
+    where
+        S: FnOnce(mpsc::Sender<Job>) -> F,
+        F: Future<Output = Result<usize>>,
+    {
+        let (job_tx, job_rx) = mpsc::channel::<Job>(CHANNEL_CAPACITY);
+        let (parsed_tx, parsed_rx) = mpsc::channel::<Parsed>(CHANNEL_CAPACITY);
+
+        let job_rx = Arc::new(Mutex::new(job_rx));
+        let context = Arc::new(self.file_context());
+        let known = Arc::new(match &self.manifest {
+            Some(manifest) if !self.force => manifest.hashes(self.commit.as_deref()),
+            _ => HashMap::new(),
+        });
+        for _ in 0..self.indexing.parse_workers() {
+            let (job_rx, parsed_tx) = (Arc::clone(&job_rx), parsed_tx.clone());
+            let (context, known) = (Arc::clone(&context), Arc::clone(&known));
+            let (ignore_tests, count_tokens) = (self.ignore_tests, self.count_tokens);
+            let progress = self.progress.clone();
+            tokio::task::spawn_blocking(move || {
+                let mut parser = LanguageParser::new().with_skip_tests(ignore_tests);
+                loop {
+                    let Some(job) = job_rx.lock().unwrap().blocking_recv() else {
+                        return;
+                    };
+                    let parsed = parse_file(&mut parser, &context, &known, count_tokens, job);
+                    report_parsed(&progress, &parsed);
+                    if parsed_tx.blocking_send(parsed).is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+        drop(parsed_tx);
+
+        let embedder = Arc::clone(&self.embedder);
+        let embedded = batches(parsed_rx, self.batch_size)
+            .map(move |batch| {
+                let embedder = Arc::clone(&embedder);
+                async move {
+                    let embeddings = embed_files(&*embedder, &batch.files).await;
+                    (batch, embeddings)
+                }
+            })
+            .buffer_unordered(self.indexing.embedding_requests.max(1));
+
+        let this = &mut *self;
+        let sink = async move {
+            futures::pin_mut!(embedded);
+            while let Some((batch, embeddings)) = embedded.next().await {
+                this.store_batch(batch, embeddings, stats).await;
+            }
+            stats
+        };
+
+        let (read_failures, stats) = tokio::join!(source(job_tx), sink);
+        let read_failures = read_failures?;
+        stats.failed_files += read_failures;
+        if read_failures > 0 {
+            emit(&self.progress, ProgressEvent::Failed { files: read_failures });
+        }
+        Ok(())
+    }
+
+    pub(super) fn file_context(&self) -> FileContext {
+        FileContext {
+            repo: self.repo.clone(),
+            repo_root: self.repo_root.clone(),
+            commit: self.commit.clone(),
+            manifest_root: self.manifest.as_ref().map(|manifest| manifest.root().to_path_buf()),
+        }
+    }
+
+    async fn store_batch(&mut self, batch: Batch, embeddings: Result<Vec<Vec<f32>>>, stats: &mut IndexStats) {
+        let files = batch.files.len();
+        let (chunks, tokens) = (batch.chunks, batch.files.iter().map(|file| file.tokens).sum());
+        stats.total_files += files + batch.unchanged + batch.failed;
+        stats.skipped_files += batch.unchanged;
+        stats.failed_files += batch.failed;
+
+        let result = match embeddings {
+            Ok(embeddings) => {
+                emit(&self.progress, ProgressEvent::Embedded { chunks, tokens });
+                self.store_files(batch.files, embeddings).await
+            }
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(chunks) => {
+                stats.indexed_files += files;
+                stats.total_chunks += chunks;
+                emit(&self.progress, ProgressEvent::Stored { files, chunks });
+            }
+            Err(e) => {
+                debug!("Failed to index a batch of {} files: {}", files, e);
+                stats.failed_files += files;
+                emit(&self.progress, ProgressEvent::Failed { files });
+            }
+        }
+    }
+
+    /// Replaces the chunks previously indexed for `files` with their new
+    /// chunks, in one upsert. `embeddings` holds one embedding per chunk, in
+    /// order. Returns the number of chunks stored.
+    pub(super) async fn store_files(&mut self, files: Vec<ParsedFile>, embeddings: Vec<Vec<f32>>) -> Result<usize> {
+        let mut embeddings = embeddings.into_iter();
+        let mut documents = Vec::new();
+        let mut chunk_ids = Vec::with_capacity(files.len());
+
+        for file in &files {
+            self.delete_file_chunks(&file.path).await?;
+
+            let ids: Vec<String> = file
+                .chunks
+                .iter()
+                .cloned()
+                .zip(embeddings.by_ref())
+                .map(|(chunk, embedding)| {
+                    let document = self.vector_store.create_document(chunk, embedding);
+                    let id = document.id.clone();
+                    documents.push(document);
+                    id
+                })
+                .collect();
+            chunk_ids.push(ids);
+        }
+
+        let chunks = documents.len();
+        if !documents.is_empty() {
+            self.vector_store
+                .upsert_documents(documents)
+                .await
+                .map_err(|e| BuildliError::VectorStore(e.to_string()))?;
+        }
+
+        for (file, ids) in files.into_iter().zip(chunk_ids) {
+            if let Some(lexical) = &mut self.lexical {
+                for (id, chunk) in ids.iter().zip(&file.chunks) {
+                    lexical.insert(id.clone(), chunk);
+                }
+            }
+            self.record_file(file.key, file.sha256, ids);
+        }
+
+        Ok(chunks)
+    }
+}
+
+fn report_parsed(progress: &Option<ProgressSender>, parsed: &Parsed) {
+    match parsed {
+        Parsed::File(file) => emit(progress, ProgressEvent::Parsed { chunks: file.chunks.len(), tokens: file.tokens }),
+        Parsed::Unchanged => emit(progress, ProgressEvent::Unchanged),
+        Parsed::Failed => emit(progress, ProgressEvent::Failed { files: 1 }),
+    }
+}
+
+/// Reads (if needed), hashes and parses one file. Files whose hash is in
+/// `known` are unchanged since the last run.
+fn parse_file(
+    parser: &mut LanguageParser,
+    context: &FileContext,
+    known: &HashMap<String, String>,
+    count_tokens: bool,
+    job: Job,
+) -> Parsed {
+    let key = match &context.manifest_root {
+        Some(root) => manifest::file_key(root, &job.path),
+        None => job.path.display().to_string(),
+    };
+    let content = match job.content {
+        Some(content) => content,
+        None => match std::fs::read_to_string(&job.path) {
+            Ok(content) => content,
+            Err(e) => {
+                debug!("Failed to read {}: {}", key, e);
+                return Parsed::Failed;
+            }
+        },
+    };
+
+    let sha256 = manifest::content_hash(&content);
+    if known.get(&key) == Some(&sha256) {
+        return Parsed::Unchanged;
+    }
+
+    match context.parse(parser, &job.path, &content) {
+        Ok(chunks) => Parsed::File(ParsedFile::new(job.path, key, sha256, chunks, count_tokens)),
+        Err(e) => {
+            debug!("Failed to parse {}: {}", key, e);
+            Parsed::Failed
+        }
+    }
+}
+
+/// Groups parsed files into batches of at least `batch_size` chunks, except
+/// for the last one. Files are never split across batches.
+fn batches(parsed: mpsc::Receiver<Parsed>, batch_size: usize) -> impl futures::Stream<Item = Batch> {
+    futures::stream::unfold(parsed, move |mut parsed| async move {
+        let mut batch = Batch::default();
+        while batch.chunks < batch_size.max(1) {
+            match parsed.recv().await {
+                Some(Parsed::File(file)) => {
+                    batch.chunks += file.chunks.len();
+                    batch.files.push(file);
+                }
+                Some(Parsed::Unchanged) => batch.unchanged += 1,
+                Some(Parsed::Failed) => batch.failed += 1,
+                None => break,
+            }
+        }
+
+        let empty = batch.files.is_empty() && batch.unchanged == 0 && batch.failed == 0;
+        (!empty).then_some((batch, parsed))
+    })
+}
+
+/// Embeds the chunks of `files`, in order.
+pub(super) async fn embed_files<E: EmbeddingProvider + ?Sized>(embedder: &E, files: &[ParsedFile]) -> Result<Vec<Vec<f32>>> {
+    let texts: Vec<String> = files
+        .iter()
+        .flat_map(|file| file.chunks.iter().map(|chunk| chunk.content.clone()))
+        .collect();
+    if texts.is_empty() {
+        return Ok(vec![]);
+    }
+
+    embedder
+        .embed_batch(&texts)
+        .await
+        .map_err(|e| BuildliError::Embedding(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::IndexingConfig;
+    use crate::embeddings::LocalEmbeddings;
+    use crate::vector::{LocalVectorStore, SearchFilter};
+
+    #[tokio::test]
+    async fn test_indexes_files_through_all_stages() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut files = Vec::new();
+        for i in 0..20 {
+            let path = dir.path().join(format!("module_{}.rs", i));
+            std::fs::write(&path, format!("fn handler_{}() {{}}\n\nfn helper_{}() {{}}\n", i, i)).unwrap();
+            files.push(path);
+        }
+        let binary = dir.path().join("blob.rs");
+        std::fs::write(&binary, [0xff, 0xfe, 0x00]).unwrap();
+        files.push(binary);
+
+        let indexing = IndexingConfig {
+            parse_workers: 3,
+            embedding_requests: 2,
+        };
+        let mut indexer = Indexer::new(LocalEmbeddings::new(), LocalVectorStore::new())
+            .with_indexing_config(&indexing)
+            .with_batch_size(5);
+        let mut stats = IndexStats::default();
+        indexer
+            .run_pipeline(
+                |jobs| async move {
+                    for path in files {
+                        jobs.send(Job { path, content: None }).await.unwrap();
+                    }
+                    Ok(0)
+                },
+                &mut stats,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(stats.total_files, 21);
+        assert_eq!(stats.indexed_files, 20);
+        assert_eq!(stats.failed_files, 1);
+        assert_eq!(stats.total_chunks, 40);
+
+        let query = indexer.embedder.embed("fn helper_7() {}").await.unwrap();
+        let results = indexer.vector_store.search(query, 1, &SearchFilter::default()).await.unwrap();
+        assert!(results[0].metadata["file_path"].as_str().unwrap().ends_with("module_7.rs"));
+    }
+}
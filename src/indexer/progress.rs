@@ -0,0 +1,182 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::fs;
+use tokio::sync::mpsc;
+
+/// Progress of an indexing run, emitted as the indexer works through it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ProgressEvent {
+    /// Indexing of a root path started.
+    Started { root: PathBuf },
+    /// Files were found to index.
+    Discovered { files: usize },
+    /// A file was parsed into chunks.
+    Parsed { chunks: usize, tokens: usize },
+    /// A file was unchanged since the last run and skipped.
+    Unchanged,
+    /// Chunks were embedded.
+    Embedded { chunks: usize, tokens: usize },
+    /// Files and their chunks were written to the vector store.
+    Stored { files: usize, chunks: usize },
+    /// Files failed to read, parse, embed or store.
+    Failed { files: usize },
+    /// Deleted files had their chunks purged.
+    Removed { files: usize },
+    /// Indexing of the root finished.
+    Finished,
+}
+
+pub type ProgressSender = mpsc::UnboundedSender<ProgressEvent>;
+
+/// Running totals of an indexing run. Indexing processes keep a snapshot of
+/// it on disk per collection, for the server's status endpoints to read.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Progress {
+    pub root: Option<PathBuf>,
+    pub running: bool,
+    pub discovered_files: usize,
+    pub parsed_files: usize,
+    pub unchanged_files: usize,
+    pub stored_files: usize,
+    pub failed_files: usize,
+    pub removed_files: usize,
+    pub parsed_chunks: usize,
+    pub embedded_chunks: usize,
+    pub stored_chunks: usize,
+    /// Tokens sent to the embedding provider.
+    pub tokens: usize,
+    /// Unix time the run started.
+    pub started_at: u64,
+    /// Unix time of the last event.
+    pub updated_at: u64,
+}
+
+impl Progress {
+    pub fn apply(&mut self, event: &ProgressEvent) {
+        match event {
+            ProgressEvent::Started { root } => {
+                *self = Self {
+                    root: Some(root.clone()),
+                    running: true,
+                    started_at: unix_now(),
+                    ..Self::default()
+                };
+            }
+            ProgressEvent::Discovered { files } => self.discovered_files += files,
+            ProgressEvent::Parsed { chunks, .. } => {
+                self.parsed_files += 1;
+                self.parsed_chunks += chunks;
+            }
+            ProgressEvent::Unchanged => self.unchanged_files += 1,
+            ProgressEvent::Embedded { chunks, tokens } => {
+                self.embedded_chunks += chunks;
+                self.tokens += tokens;
+            }
+            ProgressEvent::Stored { files, chunks } => {
+                self.stored_files += files;
+                self.stored_chunks += chunks;
+            }
+            ProgressEvent::Failed { files } => self.failed_files += files,
+            ProgressEvent::Removed { files } => self.removed_files += files,
+            ProgressEvent::Finished => self.running = false,
+        }
+        self.updated_at = unix_now();
+    }
+
+    /// Files done with, whether stored, unchanged or failed.
+    pub fn processed_files(&self) -> usize {
+        self.stored_files + self.unchanged_files + self.failed_files
+    }
+
+    /// Time of the last event as an HTTP date, or "never".
+    pub fn last_updated(&self) -> String {
+        crate::utils::http_date(self.updated_at)
+    }
+
+    /// Path of the progress snapshot for `collection`.
+    pub fn snapshot_path(collection: &str) -> Result<PathBuf> {
+        Ok(crate::config::data_dir()?
+            .join("progress")
+            .join(format!("{}.json", collection)))
+    }
+
+    /// Loads the last snapshot saved for `collection`, if any.
+    pub async fn load(collection: &str) -> Result<Option<Self>> {
+        Self::load_from(&Self::snapshot_path(collection)?).await
+    }
+
+    pub async fn load_from(path: &Path) -> Result<Option<Self>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let data = fs::read_to_string(path)
+            .await
+            .context("Failed to read index progress")?;
+        Ok(Some(serde_json::from_str(&data).context("Failed to parse index progress")?))
+    }
+
+    pub async fn save(&self, collection: &str) -> Result<()> {
+        self.save_to(&Self::snapshot_path(collection)?).await
+    }
+
+    pub async fn save_to(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        let tmp_path = path.with_extension("json.tmp");
+        fs::write(&tmp_path, serde_json::to_vec(self)?).await?;
+        fs::rename(&tmp_path, path).await?;
+        Ok(())
+    }
+}
+
+/// Sends `event` if progress is being reported. A receiver that went away
+/// only stops the reporting, never the indexing.
+pub(super) fn emit(progress: &Option<ProgressSender>, event: ProgressEvent) {
+    if let Some(progress) = progress {
+        let _ = progress.send(event);
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_progress_totals_survive_a_snapshot() {
+        let mut progress = Progress::default();
+        for event in [
+            ProgressEvent::Started { root: PathBuf::from("/repo") },
+            ProgressEvent::Discovered { files: 3 },
+            ProgressEvent::Parsed { chunks: 4, tokens: 100 },
+            ProgressEvent::Unchanged,
+            ProgressEvent::Failed { files: 1 },
+            ProgressEvent::Embedded { chunks: 4, tokens: 100 },
+            ProgressEvent::Stored { files: 1, chunks: 4 },
+        ] {
+            progress.apply(&event);
+        }
+        assert!(progress.running);
+        assert_eq!(progress.processed_files(), 3);
+        assert_eq!(progress.tokens, 100);
+
+        let line = serde_json::to_string(&ProgressEvent::Stored { files: 1, chunks: 4 }).unwrap();
+        assert_eq!(line, r#"{"event":"stored","files":1,"chunks":4}"#);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("progress").join("default.json");
+        progress.apply(&ProgressEvent::Finished);
+        progress.save_to(&path).await.unwrap();
+        assert_eq!(Progress::load_from(&path).await.unwrap(), Some(progress));
+    }
+}
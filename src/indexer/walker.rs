@@ -13,8 +13,17 @@ pub enum WatchEvent {
     Deleted(PathBuf),
 }
 
+impl WatchEvent {
+    pub fn path(&self) -> &Path {
+        match self {
+            WatchEvent::Created(path) | WatchEvent::Modified(path) | WatchEvent::Deleted(path) => path,
+        }
+    }
+}
+
 pub struct FileWalker {
     ignore_patterns: Vec<String>,
+    ignore_tests: bool,
 }
 
 impl FileWalker {
@@ -30,6 +39,7 @@ impl FileWalker {
                 ".pytest_cache".to_string(),
                 ".mypy_cache".to_string(),
             ],
+            ignore_tests: false,
         }
     }
 
@@ -38,6 +48,11 @@ impl FileWalker {
         self
     }
 
+    pub fn with_ignore_tests(mut self, ignore_tests: bool) -> Self {
+        self.ignore_tests = ignore_tests;
+        self
+    }
+
     pub fn walk(&self, root: &Path) -> Result<Vec<PathBuf>> {
         let mut files = Vec::new();
         
@@ -52,7 +67,7 @@ impl FileWalker {
             let entry = entry.context("Failed to read directory entry")?;
             let path = entry.path();
             
-            if path.is_file() && !self.should_ignore(path) {
+            if path.is_file() && !self.is_ignored_under(root, path) {
                 files.push(path.to_path_buf());
             }
         }
@@ -95,7 +110,25 @@ impl FileWalker {
         Ok(rx)
     }
 
-    fn should_ignore(&self, path: &Path) -> bool {
+    /// Like [`is_ignored`](Self::is_ignored) for a path found under `root`.
+    /// Rules are matched against the path below `root`, so that the location
+    /// of the root itself (e.g. under a `tests/` dir) does not matter.
+    pub fn is_ignored_under(&self, root: &Path, path: &Path) -> bool {
+        let canonical_root = root.canonicalize().ok();
+        let relative = path
+            .strip_prefix(root)
+            .ok()
+            .or_else(|| path.strip_prefix(canonical_root.as_deref()?).ok())
+            .filter(|p| !p.as_os_str().is_empty())
+            .unwrap_or(path);
+        self.is_ignored(relative)
+    }
+
+    pub fn is_ignored(&self, path: &Path) -> bool {
+        if self.ignore_tests && is_test_file(path) {
+            return true;
+        }
+        
         for pattern in &self.ignore_patterns {
             if path.to_string_lossy().contains(pattern) {
                 return true;
@@ -111,4 +144,70 @@ impl FileWalker {
         
         false
     }
-}
\ No newline at end of file
+}
+
+/// Detects test code from common per-language layout and naming conventions:
+/// `tests/` and `__tests__/` directories, Maven/Gradle `src/test/`, Go
+/// `*_test.go`, Python `test_*.py`/`*_test.py`/`conftest.py` and JS/TS
+/// `*.spec.*`/`*.test.*` files.
+pub fn is_test_file(path: &Path) -> bool {
+    let components: Vec<String> = path
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy().to_string())
+        .collect();
+    let Some((file_name, dirs)) = components.split_last() else {
+        return false;
+    };
+    
+    if dirs.iter().any(|dir| dir == "tests" || dir == "__tests__") {
+        return true;
+    }
+    
+    if dirs.windows(2).any(|pair| pair[0] == "src" && pair[1] == "test") {
+        return true;
+    }
+    
+    let (stem, ext) = match file_name.rsplit_once('.') {
+        Some((stem, ext)) => (stem, ext),
+        None => return false,
+    };
+    
+    match ext {
+        "go" => stem.ends_with("_test"),
+        "py" => stem.starts_with("test_") || stem.ends_with("_test") || stem == "conftest",
+        "js" | "jsx" | "ts" | "tsx" | "mjs" | "cjs" => {
+            stem.ends_with(".spec") || stem.ends_with(".test")
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_test_file() {
+        assert!(is_test_file(Path::new("tests/integration.rs")));
+        assert!(is_test_file(Path::new("pkg/server_test.go")));
+        assert!(is_test_file(Path::new("app/test_models.py")));
+        assert!(is_test_file(Path::new("web/button.spec.ts")));
+        assert!(is_test_file(Path::new("src/test/java/com/acme/FooTest.java")));
+        
+        assert!(!is_test_file(Path::new("src/main.rs")));
+        assert!(!is_test_file(Path::new("pkg/server.go")));
+        assert!(!is_test_file(Path::new("app/testing_utils.py")));
+        assert!(!is_test_file(Path::new("src/main/java/com/acme/Foo.java")));
+    }
+
+    #[test]
+    fn test_is_ignored_under_root() {
+        let walker = FileWalker::new().with_ignore_tests(true);
+        let root = Path::new("/work/tests/app");
+
+        assert!(!walker.is_ignored_under(root, &root.join("src/lib.rs")));
+        assert!(walker.is_ignored_under(root, &root.join("tests/api.rs")));
+        assert!(walker.is_ignored_under(root, &root.join("target/debug/build.rs")));
+        assert!(walker.is_ignored_under(root, &root.join(".git/HEAD")));
+    }
+}
@@ -1,7 +1,10 @@
+pub mod bug;
 pub mod cli;
 pub mod config;
 pub mod embeddings;
+pub mod http;
 pub mod indexer;
+pub mod patch;
 pub mod query;
 pub mod server;
 pub mod utils;
@@ -29,6 +32,9 @@ pub enum BuildliError {
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
     
+    #[error("Patch error: {0}")]
+    Patch(String),
+    
     #[error("Network error: {0}")]
     Network(String),
     
@@ -1,19 +1,32 @@
 use anyhow::Result;
 use buildli::{
-    cli::{Cli, Commands},
-    config::ConfigManager,
-    embeddings::{LocalEmbeddings, OpenAIEmbeddings},
+    bug::BugSolver,
+    cli::{Cli, Commands, ProgressFormat},
+    config::{Config, ConfigManager},
     indexer::{
         factory::{BuildliIndexer, EmbeddingProviderType, VectorStoreType},
-        Indexer,
+        migrate::migrate_embeddings,
+        progress::{Progress, ProgressEvent},
+        IndexStats, Indexer,
     },
-    query::{factory::BuildliQueryEngine, LlmClient, QueryEngine},
-    utils::{print_error, print_info, print_success, print_warning},
-    vector::{PersistentLocalVectorStore, QdrantStore, VectorStore},
+    patch::{
+        journal::{JournalEntry, PatchJournal},
+        Patch,
+    },
+    query::{factory::BuildliQueryEngine, ChatEvent},
+    utils::{create_progress_bar, print_error, print_info, print_success, print_warning},
+    embeddings::EmbeddingProvider,
+    vector::{CollectionSchema, PersistentLocalVectorStore, SearchFilter, VectorStore},
 };
 use clap::Parser;
 use colored::Colorize;
+use futures::StreamExt;
+use indicatif::{MultiProgress, ProgressBar};
+use std::io::Write;
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
 use tracing_subscriber::{fmt, prelude::*, EnvFilter};
 
 #[tokio::main]
@@ -26,8 +39,19 @@ async fn main() -> Result<()> {
         EnvFilter::new("info")
     };
     
+    // Keep stdout to the JSON progress stream when one is requested.
+    let json_progress = matches!(
+        cli.command,
+        Commands::Index { progress: ProgressFormat::Json, .. } | Commands::Reindex { progress: ProgressFormat::Json, .. }
+    );
+    let log_layer = if json_progress {
+        fmt::layer().with_writer(std::io::stderr).boxed()
+    } else {
+        fmt::layer().boxed()
+    };
+    
     tracing_subscriber::registry()
-        .with(fmt::layer())
+        .with(log_layer)
         .with(filter)
         .init();
     
@@ -35,14 +59,26 @@ async fn main() -> Result<()> {
     let _config = config_manager.load().await?;
     
     match cli.command {
-        Commands::Index { paths, watch, commit, ignore_tests } => {
-            handle_index(config_manager, paths, watch, commit, ignore_tests).await?;
+        Commands::Index { paths, watch, commit, ignore_tests, progress } => {
+            handle_index(config_manager, paths, watch, commit, ignore_tests, false, progress).await?;
+        }
+        Commands::Reindex { paths, migrate_embeddings, ignore_tests, progress } => {
+            if migrate_embeddings {
+                handle_migrate_embeddings(config_manager).await?;
+            } else {
+                handle_index(config_manager, paths, false, None, ignore_tests, true, progress).await?;
+            }
         }
         Commands::Query { question, top_k, json, repo, lang } => {
             handle_query(config_manager, question, top_k, json, repo, lang).await?;
         }
-        Commands::Bug { desc, apply, patch_file, no_stream } => {
-            handle_bug(config_manager, desc, apply, patch_file, no_stream).await?;
+        Commands::Bug { desc, apply, patch_file, no_stream, undo } => {
+            if undo {
+                handle_bug_undo(config_manager).await?;
+            } else {
+                let desc = desc.expect("clap requires --desc unless --undo is given");
+                handle_bug(config_manager, desc, apply, patch_file, no_stream).await?;
+            }
         }
         Commands::Serve { port, token } => {
             handle_serve(config_manager, port, token).await?;
@@ -50,6 +86,9 @@ async fn main() -> Result<()> {
         Commands::Config { set, print } => {
             handle_config(config_manager, set, print).await?;
         }
+        Commands::ConvertStore { quantization, pq_subvectors, no_rerank } => {
+            handle_convert_store(config_manager, quantization, pq_subvectors, no_rerank).await?;
+        }
         Commands::Update { channel } => {
             handle_update(channel).await?;
         }
@@ -62,8 +101,10 @@ async fn handle_index(
     config_manager: ConfigManager,
     paths: Vec<PathBuf>,
     watch: bool,
-    _commit: Option<String>,
-    _ignore_tests: bool,
+    commit: Option<String>,
+    ignore_tests: bool,
+    force: bool,
+    progress: ProgressFormat,
 ) -> Result<()> {
     let config = config_manager.load().await?;
     
@@ -72,24 +113,25 @@ async fn handle_index(
         return Ok(());
     }
     
-    let embedder = match config.embedding.provider.as_str() {
-        "openai" => EmbeddingProviderType::OpenAI(OpenAIEmbeddings::new(
-            config.llm.api_key.clone().unwrap(),
-            config.embedding.model.clone(),
-        )),
-        _ => EmbeddingProviderType::Local(LocalEmbeddings::new()),
-    };
+    let embedder = EmbeddingProviderType::from_config(&config);
+    // Only OpenAI bills by cl100k tokens; counting them costs a BPE pass per chunk.
+    let count_tokens = matches!(embedder, EmbeddingProviderType::OpenAI(_));
     
-    let vector_store = match config.vector.backend.as_str() {
-        "qdrant" => {
-            let store = QdrantStore::new(&config.vector.url, &config.vector.collection_name).await?;
-            store.initialize(&config.vector.collection_name, 384).await?;
-            VectorStoreType::Qdrant(store)
-        }
-        _ => VectorStoreType::Local(PersistentLocalVectorStore::new().await?),
-    };
+    let vector_store = VectorStoreType::from_config(&config).await?;
+    
+    let mut indexer: BuildliIndexer = Indexer::new(embedder, vector_store)
+        .with_collection(config.vector.index_key())
+        .with_token_counting(count_tokens)
+        .with_ignore_tests(ignore_tests)
+        .with_force(force)
+        .with_batch_size(config.embedding.batch_size)
+        .with_indexing_config(&config.indexing);
     
-    let mut indexer: BuildliIndexer = Indexer::new(embedder, vector_store);
+    let (progress_tx, progress_rx) = mpsc::unbounded_channel();
+    indexer = indexer.with_progress(progress_tx);
+    let reporter = tokio::spawn(report_progress(progress_rx, progress, config.vector.index_key()));
+    // JSON progress is the only thing written to stdout.
+    let quiet = progress == ProgressFormat::Json;
     
     let paths_to_index = if paths.is_empty() {
         config.paths.index_root.clone()
@@ -97,32 +139,133 @@ async fn handle_index(
         paths
     };
     
-    print_info(&format!("Starting indexing of {} paths", paths_to_index.len()));
+    if !quiet {
+        print_info(&format!("Starting indexing of {} paths", paths_to_index.len()));
+    }
     
     for path in paths_to_index {
-        let stats = indexer.index_path(&path, watch).await?;
+        let stats = match &commit {
+            Some(commit) => indexer.index_commit(&path, commit).await?,
+            None => indexer.index_path(&path, watch).await?,
+        };
+        let revision = commit
+            .as_ref()
+            .map(|commit| format!(" at commit {}", commit))
+            .unwrap_or_default();
+        if quiet {
+            continue;
+        }
         print_success(&format!(
-            "Indexed {} files ({} chunks) from {}",
+            "Indexed {} files ({} chunks) from {}{}",
             stats.indexed_files,
             stats.total_chunks,
-            path.display()
+            path.display(),
+            revision
         ));
         
+        if stats.skipped_files > 0 || stats.removed_files > 0 {
+            print_info(&format!(
+                "{} files unchanged, {} removed files purged",
+                stats.skipped_files, stats.removed_files
+            ));
+        }
+        
         if stats.failed_files > 0 {
             print_warning(&format!("{} files failed to index", stats.failed_files));
         }
     }
     
+    drop(indexer);
+    reporter.await?;
     Ok(())
 }
 
+/// Renders the indexer's progress events and saves a snapshot of the totals
+/// for the server's status endpoints, at most once a second.
+async fn report_progress(
+    mut events: mpsc::UnboundedReceiver<ProgressEvent>,
+    format: ProgressFormat,
+    collection: String,
+) {
+    const INTERVAL: Duration = Duration::from_secs(1);
+    let mut progress = Progress::default();
+    let mut bars = None;
+    let mut last_report = Instant::now();
+    
+    while let Some(event) = events.recv().await {
+        progress.apply(&event);
+        let boundary = matches!(event, ProgressEvent::Started { .. } | ProgressEvent::Finished);
+        
+        if format == ProgressFormat::Bars && matches!(event, ProgressEvent::Started { .. }) {
+            bars = Some(IndexBars::new());
+        }
+        if let Some(bars) = &bars {
+            bars.update(&progress);
+            if matches!(event, ProgressEvent::Finished) {
+                bars.finish();
+            }
+        }
+        
+        if boundary || last_report.elapsed() >= INTERVAL {
+            last_report = Instant::now();
+            if format == ProgressFormat::Json {
+                if let Ok(line) = serde_json::to_string(&progress) {
+                    println!("{}", line);
+                }
+            }
+            if let Err(e) = progress.save(&collection).await {
+                tracing::debug!("Failed to save index progress: {}", e);
+            }
+        }
+    }
+}
+
+/// Bars for files parsed, chunks embedded and files done with.
+struct IndexBars {
+    parse: ProgressBar,
+    embed: ProgressBar,
+    store: ProgressBar,
+}
+
+impl IndexBars {
+    fn new() -> Self {
+        let multi = MultiProgress::new();
+        Self {
+            parse: multi.add(create_progress_bar(0, "Parsing  ")),
+            embed: multi.add(create_progress_bar(0, "Embedding")),
+            store: multi.add(create_progress_bar(0, "Storing  ")),
+        }
+    }
+    
+    fn update(&self, progress: &Progress) {
+        self.parse.set_length(progress.discovered_files as u64);
+        self.parse.set_position((progress.parsed_files + progress.unchanged_files) as u64);
+        self.embed.set_length(progress.parsed_chunks as u64);
+        self.embed.set_position(progress.embedded_chunks as u64);
+        self.store.set_length(progress.discovered_files as u64);
+        self.store.set_position(progress.processed_files() as u64);
+        if progress.tokens > 0 {
+            self.embed.set_message(format!("Embedding ({} tokens)", progress.tokens));
+        }
+        if progress.failed_files > 0 {
+            self.store.set_message(format!("Storing ({} failed)", progress.failed_files));
+        }
+    }
+    
+    fn finish(&self) {
+        self.parse.finish();
+        self.embed.finish();
+        self.store.finish();
+    }
+}
+
 async fn handle_query(
     config_manager: ConfigManager,
     question: String,
     top_k: usize,
     json: bool,
-    _repo: Option<Vec<String>>,
-    _lang: Option<Vec<String>>,
+    repo: Option<Vec<String>>,
+    lang: Option<Vec<String>>,
 ) -> Result<()> {
     let config = config_manager.load().await?;
     
@@ -131,46 +274,41 @@ async fn handle_query(
         return Ok(());
     }
     
-    let embedder = match config.embedding.provider.as_str() {
-        "openai" => EmbeddingProviderType::OpenAI(OpenAIEmbeddings::new(
-            config.llm.api_key.clone().unwrap(),
-            config.embedding.model.clone(),
-        )),
-        _ => EmbeddingProviderType::Local(LocalEmbeddings::new()),
-    };
+    let query_engine = BuildliQueryEngine::from_config(&config).await?;
     
-    let vector_store = match config.vector.backend.as_str() {
-        "qdrant" => VectorStoreType::Qdrant(
-            QdrantStore::new(&config.vector.url, &config.vector.collection_name).await?,
-        ),
-        _ => VectorStoreType::Local(PersistentLocalVectorStore::new().await?),
-    };
-    
-    let llm_client = LlmClient::new(
-        config.llm.api_key.unwrap(),
-        config.llm.model.clone(),
-        config.llm.temperature,
-    );
-    
-    let query_engine: BuildliQueryEngine = QueryEngine::new(embedder, vector_store, llm_client);
-    
-    let response = query_engine.query(&question, top_k, !json).await?;
+    let filter = SearchFilter::new(repo.unwrap_or_default(), lang.unwrap_or_default());
     
     if json {
+        let response = query_engine.query(&question, top_k, &filter).await?;
         println!("{}", serde_json::to_string_pretty(&response)?);
-    } else {
-        println!("\n{}", response.answer);
-        
-        if !response.references.is_empty() {
-            println!("\n{}", "References:".bold());
-            for reference in response.references {
-                println!(
-                    "  {} {}:{}",
-                    "→".cyan(),
-                    reference.file_path,
-                    reference.line_start
-                );
-            }
+        return Ok(());
+    }
+    
+    let (references, mut answer) = query_engine.query_stream(&question, top_k, &filter).await?;
+    let mut stdout = std::io::stdout();
+    println!();
+    while let Some(event) = answer.next().await {
+        if let ChatEvent::Delta(delta) = event? {
+            stdout.write_all(delta.as_bytes())?;
+            stdout.flush()?;
+        }
+    }
+    println!();
+    
+    if !references.is_empty() {
+        println!("\n{}", "References:".bold());
+        for reference in references {
+            let revision = match &reference.commit {
+                Some(commit) => format!(" @ {}", &commit[..commit.len().min(12)]),
+                None => String::new(),
+            };
+            println!(
+                "  {} {}:{}{}",
+                "→".cyan(),
+                reference.file_path,
+                reference.line_start,
+                revision.dimmed()
+            );
         }
     }
     
@@ -178,13 +316,123 @@ async fn handle_query(
 }
 
 async fn handle_bug(
-    _config_manager: ConfigManager,
+    config_manager: ConfigManager,
     desc: String,
-    _apply: bool,
-    _patch_file: Option<PathBuf>,
-    _no_stream: bool,
+    apply: bool,
+    patch_file: Option<PathBuf>,
+    no_stream: bool,
 ) -> Result<()> {
-    print_info(&format!("Bug solver mode for '{}' is not yet implemented", desc));
+    let config = config_manager.load().await?;
+    
+    if config.llm.api_key.is_none() {
+        print_error("OpenAI API key not set. Please run: buildli config --set llm.api_key=<your-key>");
+        return Ok(());
+    }
+    
+    let query_engine = BuildliQueryEngine::from_config(&config).await?;
+    let solver = BugSolver::new(Arc::new(query_engine), std::env::current_dir()?.canonicalize()?);
+    
+    print_info(&format!("Analyzing bug: {}", desc));
+    
+    let mut streamed = String::new();
+    let solution = if no_stream {
+        let solution = solver.solve(&desc).await?;
+        println!("\n{}", solution.explanation);
+        solution
+    } else {
+        let mut stdout = std::io::stdout();
+        let solution = solver
+            .solve_streaming(&desc, |delta| {
+                streamed.push_str(delta);
+                let _ = stdout.write_all(delta.as_bytes());
+                let _ = stdout.flush();
+            })
+            .await?;
+        println!();
+        solution
+    };
+    
+    let Some(patch) = solution.patch else {
+        print_warning("No patch was generated for this bug");
+        return Ok(());
+    };
+    
+    // The streamed answer already contains the diff unless it had to be repaired.
+    if no_stream || !streamed.contains(patch.trim()) {
+        println!("\n{}", "Patch:".bold());
+        println!("{}", patch);
+    }
+    
+    if !solution.affected_files.is_empty() {
+        println!("{}", "Affected files:".bold());
+        for file in &solution.affected_files {
+            println!("  {} {}", "→".cyan(), file);
+        }
+    }
+    
+    if let Some(error) = &solution.validation_error {
+        print_warning(&format!("Patch does not apply cleanly: {}", error));
+    } else {
+        print_success("Patch applies cleanly to the working tree");
+    }
+    
+    if let Some(patch_file) = patch_file {
+        tokio::fs::write(&patch_file, &patch).await?;
+        print_success(&format!("Patch saved to {}", patch_file.display()));
+    }
+    
+    if apply {
+        if solution.validation_error.is_some() {
+            print_error("Refusing to apply a patch that does not apply cleanly");
+            return Ok(());
+        }
+        
+        let entry = PatchJournal::new()?.apply(&Patch::parse(&patch)?, solver.workspace_root())?;
+        print_success(&format!(
+            "Applied patch {} to {} files (undo with: buildli bug --undo)",
+            entry.id,
+            entry.files.len()
+        ));
+        
+        reindex_patched_files(&config, &entry).await?;
+    }
+    
+    Ok(())
+}
+
+async fn handle_bug_undo(config_manager: ConfigManager) -> Result<()> {
+    let config = config_manager.load().await?;
+    let entry = PatchJournal::new()?.undo(None)?;
+    
+    print_success(&format!("Restored {} files from patch {}", entry.files.len(), entry.id));
+    for file in &entry.files {
+        println!("  {} {}", "→".cyan(), file.path.display());
+    }
+    
+    reindex_patched_files(&config, &entry).await?;
+    Ok(())
+}
+
+async fn reindex_patched_files(config: &Config, entry: &JournalEntry) -> Result<()> {
+    let embedder = EmbeddingProviderType::from_config(config);
+    let vector_store = VectorStoreType::from_config(config).await?;
+    let mut indexer: BuildliIndexer = Indexer::new(embedder, vector_store).with_collection(config.vector.index_key());
+    indexer.load_manifest(&entry.workspace_root).await?;
+    indexer.load_lexical_index().await?;
+    let mut stats = IndexStats::default();
+    
+    for file in &entry.files {
+        indexer.delete_file_chunks(&file.path).await?;
+        if file.path.exists() {
+            if let Err(e) = indexer.index_file(&file.path, &mut stats).await {
+                print_warning(&format!("Failed to reindex {}: {}", file.path.display(), e));
+            }
+        }
+    }
+    
+    indexer.save_state().await?;
+    
+    print_info(&format!("Reindexed {} files ({} chunks)", stats.indexed_files, stats.total_chunks));
     Ok(())
 }
 
@@ -224,6 +472,80 @@ async fn handle_config(
     Ok(())
 }
 
+async fn handle_migrate_embeddings(config_manager: ConfigManager) -> Result<()> {
+    let config = config_manager.load().await?;
+
+    if config.llm.api_key.is_none() && config.embedding.provider == "openai" {
+        print_error("OpenAI API key not set. Please run: buildli config --set llm.api_key=<your-key>");
+        return Ok(());
+    }
+
+    let embedder = EmbeddingProviderType::from_config(&config);
+    let schema = CollectionSchema {
+        model: embedder.model_name(),
+        dimension: embedder.dimension().await?,
+    };
+    let collection = &config.vector.collection_name;
+    let source = VectorStoreType::from_config(&config).await?;
+    if source.schema().await?.as_ref() == Some(&schema) {
+        print_info(&format!("Collection '{}' already uses {}", collection, schema.model));
+        return Ok(());
+    }
+
+    let timestamp = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?.as_secs();
+    let target_name = format!("{}_{}", collection, timestamp);
+    let mut target_config = config.clone();
+    target_config.vector.collection_name = target_name.clone();
+    let target = VectorStoreType::from_config(&target_config).await?;
+
+    print_info(&format!(
+        "Re-embedding '{}' with {} into '{}'...",
+        collection, schema.model, target_name
+    ));
+    let migrated = migrate_embeddings(&embedder, &source, &target, config.embedding.batch_size).await?;
+    drop(target);
+    source.promote(&config, &target_name).await?;
+
+    print_success(&format!(
+        "Re-embedded {} chunks; '{}' now refers to '{}'",
+        migrated, collection, target_name
+    ));
+    Ok(())
+}
+
+async fn handle_convert_store(
+    config_manager: ConfigManager,
+    quantization: String,
+    pq_subvectors: Option<usize>,
+    no_rerank: bool,
+) -> Result<()> {
+    let mut config = config_manager.load().await?;
+    if config.vector.backend == "qdrant" {
+        print_error("Quantization only applies to the local vector store");
+        return Ok(());
+    }
+
+    let store = PersistentLocalVectorStore::new(&config.vector).await?;
+    let bytes_before = store.bytes_per_vector().await;
+
+    config.vector.quantization.method = quantization;
+    if let Some(pq_subvectors) = pq_subvectors {
+        config.vector.quantization.pq_subvectors = pq_subvectors;
+    }
+    config.vector.quantization.rerank = !no_rerank;
+
+    print_info(&format!("Converting {} vectors to {}...", store.len().await, config.vector.quantization.method));
+    store.convert(&config.vector.quantization).await?;
+    config_manager.save(&config).await?;
+
+    print_success(&format!(
+        "Converted local vector store ({} -> {} bytes per vector)",
+        bytes_before,
+        store.bytes_per_vector().await
+    ));
+    Ok(())
+}
+
 async fn handle_update(channel: String) -> Result<()> {
     print_info(&format!("Checking for updates on {} channel...", channel));
     print_warning("Auto-update feature not yet implemented. Please update manually.");
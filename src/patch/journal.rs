@@ -0,0 +1,248 @@
+use super::Patch;
+use crate::{BuildliError, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+const JOURNAL_FILE: &str = "journal.json";
+
+/// Applies patches atomically and keeps a backup of every file it touches so
+/// the change can be undone later.
+///
+/// Each applied patch gets its own directory under `<data dir>/patches`
+/// containing the original file contents and a `journal.json` describing them.
+pub struct PatchJournal {
+    root: PathBuf,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub id: String,
+    pub created_at: u64,
+    pub workspace_root: PathBuf,
+    pub files: Vec<JournalFile>,
+    #[serde(default)]
+    pub undone: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalFile {
+    pub path: PathBuf,
+    /// Name of the backup inside the entry directory, or `None` if the file
+    /// did not exist before the patch was applied.
+    pub backup: Option<String>,
+    /// Whether the patch left this file in place (as opposed to deleting it).
+    pub exists_after: bool,
+}
+
+impl PatchJournal {
+    pub fn new() -> Result<Self> {
+        Ok(Self::at(crate::config::data_dir()?.join("patches")))
+    }
+
+    pub fn at(root: PathBuf) -> Self {
+        Self { root }
+    }
+
+    /// Applies `patch` to the files under `workspace_root`. Either every file
+    /// is updated or, on failure, every file is restored to its original state.
+    /// Paths are journaled as absolute paths, so undo works from any directory.
+    pub fn apply(&self, patch: &Patch, workspace_root: &Path) -> Result<JournalEntry> {
+        // Resolve every hunk before touching the disk so a bad hunk aborts cleanly.
+        let changes = patch.apply_in_memory(workspace_root)?;
+
+        let created_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let id = format!("{}-{}", created_at, &uuid::Uuid::new_v4().simple().to_string()[..8]);
+        let entry_dir = self.root.join(&id);
+        fs::create_dir_all(&entry_dir)?;
+
+        let mut files = Vec::with_capacity(changes.len());
+        for (index, (path, content)) in changes.iter().enumerate() {
+            let backup = if path.exists() {
+                let name = format!("{}.orig", index);
+                fs::copy(path, entry_dir.join(&name))?;
+                Some(name)
+            } else {
+                None
+            };
+
+            files.push(JournalFile {
+                path: path.clone(),
+                backup,
+                exists_after: content.is_some(),
+            });
+        }
+
+        let entry = JournalEntry {
+            id,
+            created_at,
+            workspace_root: workspace_root.canonicalize()?,
+            files,
+            undone: false,
+        };
+        self.write_entry(&entry)?;
+
+        for (index, (path, content)) in changes.iter().enumerate() {
+            let result = match content {
+                Some(content) => write_atomic(path, content.as_bytes()),
+                None => fs::remove_file(path),
+            };
+
+            if let Err(e) = result {
+                warn!("Failed to write {}, rolling back patch: {}", path.display(), e);
+                self.restore(&entry_dir, &entry.files[..=index])?;
+                fs::remove_dir_all(&entry_dir)?;
+                return Err(BuildliError::Patch(format!(
+                    "failed to write {}: {} (all changes were rolled back)",
+                    path.display(),
+                    e
+                )));
+            }
+        }
+
+        info!("Applied patch {} to {} files", entry.id, entry.files.len());
+        Ok(entry)
+    }
+
+    /// Restores the files touched by the given entry, or by the most recently
+    /// applied patch that has not been undone yet.
+    pub fn undo(&self, id: Option<&str>) -> Result<JournalEntry> {
+        let mut entry = match id {
+            Some(id) => self.read_entry(id)?,
+            None => self
+                .entries()?
+                .into_iter()
+                .rev()
+                .find(|e| !e.undone)
+                .ok_or_else(|| BuildliError::Patch("no applied patches to undo".to_string()))?,
+        };
+
+        if entry.undone {
+            return Err(BuildliError::Patch(format!("patch {} was already undone", entry.id)));
+        }
+
+        self.restore(&self.root.join(&entry.id), &entry.files)?;
+        entry.undone = true;
+        self.write_entry(&entry)?;
+
+        info!("Undid patch {}", entry.id);
+        Ok(entry)
+    }
+
+    /// All journal entries, oldest first.
+    pub fn entries(&self) -> Result<Vec<JournalEntry>> {
+        if !self.root.exists() {
+            return Ok(vec![]);
+        }
+
+        let mut entries = Vec::new();
+        for dir in fs::read_dir(&self.root)? {
+            let dir = dir?;
+            if let Ok(entry) = self.read_entry(&dir.file_name().to_string_lossy()) {
+                entries.push(entry);
+            }
+        }
+
+        entries.sort_by(|a, b| (a.created_at, &a.id).cmp(&(b.created_at, &b.id)));
+        Ok(entries)
+    }
+
+    fn restore(&self, entry_dir: &Path, files: &[JournalFile]) -> Result<()> {
+        for file in files {
+            match &file.backup {
+                Some(backup) => {
+                    let original = fs::read(entry_dir.join(backup))?;
+                    write_atomic(&file.path, &original)?;
+                }
+                None => {
+                    if file.path.exists() {
+                        fs::remove_file(&file.path)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn read_entry(&self, id: &str) -> Result<JournalEntry> {
+        let data = fs::read_to_string(self.root.join(id).join(JOURNAL_FILE))
+            .map_err(|e| BuildliError::Patch(format!("unknown patch {}: {}", id, e)))?;
+        serde_json::from_str(&data).map_err(|e| BuildliError::Patch(format!("corrupt journal for {}: {}", id, e)))
+    }
+
+    fn write_entry(&self, entry: &JournalEntry) -> Result<()> {
+        let data = serde_json::to_string_pretty(entry).map_err(|e| BuildliError::Patch(e.to_string()))?;
+        write_atomic(&self.root.join(&entry.id).join(JOURNAL_FILE), data.as_bytes())?;
+        Ok(())
+    }
+}
+
+/// Writes `content` to a sibling temp file and renames it over `path`, so
+/// readers never observe a partially written file.
+fn write_atomic(path: &Path, content: &[u8]) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let file_name = path.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+    let tmp_path = path.with_file_name(format!(".{}.buildli-tmp", file_name));
+    fs::write(&tmp_path, content)?;
+
+    if let Ok(metadata) = fs::metadata(path) {
+        let _ = fs::set_permissions(&tmp_path, metadata.permissions());
+    }
+
+    fs::rename(&tmp_path, path).inspect_err(|_| {
+        let _ = fs::remove_file(&tmp_path);
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_and_undo() {
+        let workspace = tempfile::tempdir().unwrap();
+        let journal_dir = tempfile::tempdir().unwrap();
+        fs::write(workspace.path().join("a.txt"), "one\ntwo\nthree\n").unwrap();
+
+        let patch = Patch::parse(
+            "--- a/a.txt\n+++ b/a.txt\n@@ -1,3 +1,3 @@\n one\n-two\n+TWO\n three\n--- /dev/null\n+++ b/b.txt\n@@ -0,0 +1 @@\n+new\n",
+        )
+        .unwrap();
+        let journal = PatchJournal::at(journal_dir.path().to_path_buf());
+
+        let entry = journal.apply(&patch, workspace.path()).unwrap();
+        assert!(entry.files.iter().all(|file| file.path.is_absolute()));
+        assert_eq!(fs::read_to_string(workspace.path().join("a.txt")).unwrap(), "one\nTWO\nthree\n");
+        assert_eq!(fs::read_to_string(workspace.path().join("b.txt")).unwrap(), "new\n");
+
+        journal.undo(None).unwrap();
+        assert_eq!(fs::read_to_string(workspace.path().join("a.txt")).unwrap(), "one\ntwo\nthree\n");
+        assert!(!workspace.path().join("b.txt").exists());
+    }
+
+    #[test]
+    fn test_failed_hunk_leaves_files_untouched() {
+        let workspace = tempfile::tempdir().unwrap();
+        let journal_dir = tempfile::tempdir().unwrap();
+        fs::write(workspace.path().join("a.txt"), "one\ntwo\n").unwrap();
+        fs::write(workspace.path().join("c.txt"), "unrelated\n").unwrap();
+
+        let patch = Patch::parse(
+            "--- a/a.txt\n+++ b/a.txt\n@@ -1,2 +1,2 @@\n one\n-two\n+TWO\n--- a/c.txt\n+++ b/c.txt\n@@ -1 +1 @@\n-missing\n+x\n",
+        )
+        .unwrap();
+        let journal = PatchJournal::at(journal_dir.path().to_path_buf());
+
+        assert!(journal.apply(&patch, workspace.path()).is_err());
+        assert_eq!(fs::read_to_string(workspace.path().join("a.txt")).unwrap(), "one\ntwo\n");
+        assert!(journal.entries().unwrap().is_empty());
+    }
+}
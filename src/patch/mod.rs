@@ -0,0 +1,486 @@
+pub mod journal;
+
+use crate::{BuildliError, Result};
+use std::path::{Component, Path, PathBuf};
+use tracing::debug;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum HunkLine {
+    Context(String),
+    Remove(String),
+    Add(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct Hunk {
+    pub old_start: usize,
+    pub new_start: usize,
+    pub lines: Vec<HunkLine>,
+}
+
+#[derive(Debug, Clone)]
+pub struct FilePatch {
+    pub old_path: Option<String>,
+    pub new_path: Option<String>,
+    pub hunks: Vec<Hunk>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Patch {
+    pub files: Vec<FilePatch>,
+}
+
+impl FilePatch {
+    /// Path of the file this patch touches, preferring the post-image name.
+    pub fn path(&self) -> &str {
+        self.new_path
+            .as_deref()
+            .or(self.old_path.as_deref())
+            .unwrap_or_default()
+    }
+
+    pub fn is_new_file(&self) -> bool {
+        self.old_path.is_none()
+    }
+
+    pub fn is_deleted_file(&self) -> bool {
+        self.new_path.is_none()
+    }
+
+    /// Applies the hunks to `original`. Hunks are located near the line stated
+    /// in their header, tolerating offsets, dropped context lines and trailing
+    /// whitespace differences, since generated diffs are rarely exact.
+    pub fn apply_to(&self, original: &str) -> Result<String> {
+        let had_trailing_newline = original.is_empty() || original.ends_with('\n');
+        let source: Vec<&str> = original.lines().collect();
+        let mut output: Vec<&str> = Vec::with_capacity(source.len());
+        let mut cursor = 0;
+        // Offset of the previous hunk, applied to the next one as GNU patch does.
+        let mut drift: isize = 0;
+
+        for (index, hunk) in self.hunks.iter().enumerate() {
+            let located = locate_hunk(&source, hunk, cursor, drift).ok_or_else(|| {
+                BuildliError::Patch(format!(
+                    "hunk {} does not apply to {} near line {}",
+                    index + 1,
+                    self.path(),
+                    hunk.old_start
+                ))
+            })?;
+
+            if located.offset != 0 || located.fuzz != 0 {
+                debug!(
+                    "Hunk {} of {} applied with offset {} and fuzz {}",
+                    index + 1,
+                    self.path(),
+                    located.offset,
+                    located.fuzz
+                );
+            }
+
+            let lines = &hunk.lines[located.skip_leading..hunk.lines.len() - located.skip_trailing];
+            let old_len = lines.iter().filter(|l| !matches!(l, HunkLine::Add(_))).count();
+
+            output.extend_from_slice(&source[cursor..located.start]);
+            // Context lines keep the file's own text so whitespace-fuzzy matches do not rewrite them.
+            let mut source_line = located.start;
+            for line in lines {
+                match line {
+                    HunkLine::Context(_) => {
+                        output.push(source[source_line]);
+                        source_line += 1;
+                    }
+                    HunkLine::Remove(_) => source_line += 1,
+                    HunkLine::Add(text) => output.push(text),
+                }
+            }
+
+            cursor = located.start + old_len;
+            drift = located.offset;
+        }
+
+        output.extend_from_slice(&source[cursor..]);
+
+        let mut result = output.join("\n");
+        if had_trailing_newline && !result.is_empty() {
+            result.push('\n');
+        }
+        Ok(result)
+    }
+}
+
+/// Maximum number of leading/trailing context lines that may be ignored when
+/// a hunk does not match with its full context.
+const MAX_FUZZ: usize = 2;
+
+/// Maximum distance, in lines, between where a hunk says it applies and where
+/// it is found. Beyond that a short hunk is more likely to match unrelated
+/// code than the lines it was written for.
+const MAX_OFFSET: usize = 200;
+
+struct LocatedHunk {
+    start: usize,
+    offset: isize,
+    fuzz: usize,
+    skip_leading: usize,
+    skip_trailing: usize,
+}
+
+fn locate_hunk(source: &[&str], hunk: &Hunk, min_start: usize, drift: isize) -> Option<LocatedHunk> {
+    let leading_context = hunk.lines.iter().take_while(|l| matches!(l, HunkLine::Context(_))).count();
+    let trailing_context = hunk.lines.iter().rev().take_while(|l| matches!(l, HunkLine::Context(_))).count();
+
+    for fuzz in 0..=MAX_FUZZ {
+        let skip_leading = fuzz.min(leading_context);
+        let skip_trailing = fuzz.min(trailing_context);
+        if fuzz > 0 && skip_leading + skip_trailing == 0 {
+            break;
+        }
+        if skip_leading + skip_trailing >= hunk.lines.len() {
+            break;
+        }
+
+        let old: Vec<&str> = hunk.lines[skip_leading..hunk.lines.len() - skip_trailing]
+            .iter()
+            .filter_map(|line| match line {
+                HunkLine::Context(s) | HunkLine::Remove(s) => Some(s.as_str()),
+                HunkLine::Add(_) => None,
+            })
+            .collect();
+
+        // A zero-length old side means "insert after line N" rather than "at line N".
+        let stated = if old.is_empty() && fuzz == 0 {
+            hunk.old_start as isize
+        } else {
+            hunk.old_start as isize - 1 + skip_leading as isize
+        };
+        let expected = (stated + drift).max(0);
+
+        for loose in [false, true] {
+            if let Some(start) = search_around(source, &old, expected as usize, min_start, loose) {
+                return Some(LocatedHunk {
+                    start,
+                    offset: start as isize - stated,
+                    fuzz,
+                    skip_leading,
+                    skip_trailing,
+                });
+            }
+        }
+    }
+
+    None
+}
+
+/// Searches outward from `expected`, up to [`MAX_OFFSET`] lines either way,
+/// for the closest position where `old` matches `source`.
+fn search_around(source: &[&str], old: &[&str], expected: usize, min_start: usize, loose: bool) -> Option<usize> {
+    if old.len() > source.len() {
+        return None;
+    }
+    let last_start = source.len() - old.len();
+    let matches_at = |start: usize| {
+        start >= min_start
+            && start <= last_start
+            && source[start..start + old.len()]
+                .iter()
+                .zip(old)
+                .all(|(a, b)| if loose { a.trim_end() == b.trim_end() } else { a == b })
+    };
+
+    for distance in 0..=MAX_OFFSET.min(source.len()) {
+        if matches_at(expected + distance) {
+            return Some(expected + distance);
+        }
+        if distance > 0 && distance <= expected && matches_at(expected - distance) {
+            return Some(expected - distance);
+        }
+    }
+
+    None
+}
+
+impl Patch {
+    pub fn parse(diff: &str) -> Result<Self> {
+        let mut files: Vec<FilePatch> = Vec::new();
+        let mut lines = diff.lines().peekable();
+
+        while let Some(line) = lines.next() {
+            if let Some(old) = line.strip_prefix("--- ") {
+                let new = match lines.next().and_then(|l| l.strip_prefix("+++ ")) {
+                    Some(new) => new,
+                    None => {
+                        return Err(BuildliError::Patch(format!(
+                            "expected '+++' header after '{}'",
+                            line
+                        )))
+                    }
+                };
+
+                files.push(FilePatch {
+                    old_path: parse_header_path(old),
+                    new_path: parse_header_path(new),
+                    hunks: Vec::new(),
+                });
+            } else if line.starts_with("@@") {
+                let file = files.last_mut().ok_or_else(|| {
+                    BuildliError::Patch("hunk found before any file header".to_string())
+                })?;
+                let (old_start, new_start) = parse_hunk_header(line)?;
+                let mut hunk = Hunk {
+                    old_start,
+                    new_start,
+                    lines: Vec::new(),
+                };
+
+                while let Some(next) = lines.peek() {
+                    if next.starts_with("@@") || next.starts_with("--- ") || next.starts_with("diff ") {
+                        break;
+                    }
+                    let next = lines.next().unwrap();
+                    if let Some(rest) = next.strip_prefix('+') {
+                        hunk.lines.push(HunkLine::Add(rest.to_string()));
+                    } else if let Some(rest) = next.strip_prefix('-') {
+                        hunk.lines.push(HunkLine::Remove(rest.to_string()));
+                    } else if let Some(rest) = next.strip_prefix(' ') {
+                        hunk.lines.push(HunkLine::Context(rest.to_string()));
+                    } else if next.is_empty() {
+                        // Editors and LLMs frequently strip the leading space from blank context lines.
+                        hunk.lines.push(HunkLine::Context(String::new()));
+                    } else if next.starts_with('\\') {
+                        // "\ No newline at end of file"
+                        continue;
+                    } else {
+                        break;
+                    }
+                }
+
+                // Trailing blank lines are usually diff padding, not context.
+                while matches!(hunk.lines.last(), Some(HunkLine::Context(s)) if s.is_empty()) {
+                    hunk.lines.pop();
+                }
+
+                file.hunks.push(hunk);
+            }
+        }
+
+        files.retain(|f| !f.hunks.is_empty());
+
+        if files.is_empty() {
+            return Err(BuildliError::Patch("diff contains no hunks".to_string()));
+        }
+
+        Ok(Self { files })
+    }
+
+    pub fn affected_files(&self) -> Vec<String> {
+        self.files.iter().map(|f| f.path().to_string()).collect()
+    }
+
+    /// Verifies that every file patch applies cleanly to the files under `root`.
+    pub fn check(&self, root: &Path) -> Result<()> {
+        self.apply_in_memory(root).map(|_| ())
+    }
+
+    /// Computes the post-patch contents of every affected file without touching
+    /// the disk. `None` means the file is deleted by the patch. Paths are
+    /// absolute and guaranteed to lie inside `root`.
+    pub fn apply_in_memory(&self, root: &Path) -> Result<Vec<(PathBuf, Option<String>)>> {
+        let root = root.canonicalize().map_err(|e| {
+            BuildliError::Patch(format!("cannot resolve workspace root {}: {}", root.display(), e))
+        })?;
+        let mut results = Vec::with_capacity(self.files.len());
+
+        for file in &self.files {
+            let target = resolve_in_root(&root, file.path())?;
+
+            let original = if file.is_new_file() {
+                if target.exists() {
+                    return Err(BuildliError::Patch(format!(
+                        "{} already exists but the patch creates it",
+                        file.path()
+                    )));
+                }
+                String::new()
+            } else {
+                std::fs::read_to_string(resolve_in_root(&root, file.old_path.as_deref().unwrap())?)
+                    .map_err(|e| BuildliError::Patch(format!("cannot read {}: {}", file.path(), e)))?
+            };
+
+            let patched = file.apply_to(&original)?;
+            results.push((target, (!file.is_deleted_file()).then_some(patched)));
+
+            // Renames remove the pre-image path once the new file is written.
+            if let (Some(old), Some(new)) = (&file.old_path, &file.new_path) {
+                if old != new {
+                    results.push((resolve_in_root(&root, old)?, None));
+                }
+            }
+        }
+
+        Ok(results)
+    }
+}
+
+/// Extracts the first fenced ```diff (or ```patch) block from an LLM response.
+pub fn extract_diff_block(text: &str) -> Option<String> {
+    let mut in_block = false;
+    let mut block = Vec::new();
+
+    for line in text.lines() {
+        let trimmed = line.trim_start();
+        if !in_block {
+            if trimmed.starts_with("```diff") || trimmed.starts_with("```patch") {
+                in_block = true;
+            }
+        } else if trimmed.starts_with("```") {
+            return Some(block.join("\n") + "\n");
+        } else {
+            block.push(line);
+        }
+    }
+
+    // Unterminated block: the model likely ran out of tokens, but what we have may still parse.
+    in_block.then(|| block.join("\n") + "\n")
+}
+
+fn parse_header_path(header: &str) -> Option<String> {
+    let path = header.split('\t').next().unwrap_or(header).trim();
+    if path == "/dev/null" {
+        return None;
+    }
+    let path = path
+        .strip_prefix("a/")
+        .or_else(|| path.strip_prefix("b/"))
+        .unwrap_or(path);
+    Some(path.trim_start_matches("./").to_string())
+}
+
+/// Joins a path from a diff header onto the canonical `root`, refusing paths
+/// that could lead outside of it: absolute paths, `..` components, and
+/// symlinks that point elsewhere.
+fn resolve_in_root(root: &Path, path: &str) -> Result<PathBuf> {
+    let refuse = |reason: &str| BuildliError::Patch(format!("refusing to patch {}: {}", path, reason));
+
+    let relative = Path::new(path);
+    if relative.as_os_str().is_empty()
+        || relative.components().any(|c| !matches!(c, Component::Normal(_) | Component::CurDir))
+    {
+        return Err(refuse("the path must be relative to the workspace and stay inside it"));
+    }
+
+    let target = root.join(relative);
+    let existing = target.ancestors().find(|p| p.exists()).unwrap_or(root);
+    let resolved = existing
+        .canonicalize()
+        .map_err(|e| refuse(&format!("cannot resolve {}: {}", existing.display(), e)))?;
+    if !resolved.starts_with(root) {
+        return Err(refuse("it resolves outside of the workspace"));
+    }
+
+    Ok(target)
+}
+
+fn parse_hunk_header(line: &str) -> Result<(usize, usize)> {
+    let invalid = || BuildliError::Patch(format!("invalid hunk header: {}", line));
+
+    let mut parts = line.split_whitespace().skip(1);
+    let old = parts.next().and_then(|p| p.strip_prefix('-')).ok_or_else(invalid)?;
+    let new = parts.next().and_then(|p| p.strip_prefix('+')).ok_or_else(invalid)?;
+
+    let start = |range: &str| -> Result<usize> {
+        range
+            .split(',')
+            .next()
+            .and_then(|s| s.parse().ok())
+            .ok_or_else(invalid)
+    };
+
+    Ok((start(old)?, start(new)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORIGINAL: &str = "fn main() {\n    let x = 1;\n    println!(\"{}\", x);\n}\n";
+
+    #[test]
+    fn test_parse_and_apply() {
+        let diff = "--- a/src/main.rs\n+++ b/src/main.rs\n@@ -1,4 +1,4 @@\n fn main() {\n-    let x = 1;\n+    let x = 2;\n     println!(\"{}\", x);\n }\n";
+        let patch = Patch::parse(diff).unwrap();
+
+        assert_eq!(patch.affected_files(), vec!["src/main.rs"]);
+        assert_eq!(
+            patch.files[0].apply_to(ORIGINAL).unwrap(),
+            ORIGINAL.replace("x = 1", "x = 2")
+        );
+    }
+
+    #[test]
+    fn test_applies_with_offset_and_fuzz() {
+        let shifted = format!("// header\n// more header\n{}", ORIGINAL);
+        // Wrong line number and a context line that no longer exists in the file.
+        let diff = "--- a/src/main.rs\n+++ b/src/main.rs\n@@ -1,4 +1,4 @@\n fn main() {\n-    let x = 1;\n+    let x = 2;\n     println!(\"{}\", x);\n }\n // stale\n";
+        let patch = Patch::parse(diff).unwrap();
+
+        assert_eq!(
+            patch.files[0].apply_to(&shifted).unwrap(),
+            shifted.replace("x = 1", "x = 2")
+        );
+    }
+
+    #[test]
+    fn test_rejects_mismatched_context() {
+        let diff = "--- a/src/main.rs\n+++ b/src/main.rs\n@@ -2,1 +2,1 @@\n-    let y = 1;\n+    let y = 2;\n";
+        let patch = Patch::parse(diff).unwrap();
+
+        assert!(patch.files[0].apply_to(ORIGINAL).is_err());
+    }
+
+    #[test]
+    fn test_does_not_apply_far_from_stated_line() {
+        let filler = "// filler\n".repeat(500);
+        let source = format!("{}{}{}", ORIGINAL, filler, ORIGINAL.replace("x = 1", "x = 3"));
+        // Meant for the second block, whose line no longer matches; the first is 500 lines away.
+        let diff = "--- a/src/main.rs\n+++ b/src/main.rs\n@@ -505,4 +505,4 @@\n fn main() {\n-    let x = 1;\n+    let x = 2;\n     println!(\"{}\", x);\n }\n";
+        let patch = Patch::parse(diff).unwrap();
+        assert!(patch.files[0].apply_to(&source).is_err());
+
+        let diff = diff.replace("-505,4 +505,4", "-1,4 +1,4");
+        let patch = Patch::parse(&diff).unwrap();
+        assert_eq!(patch.files[0].apply_to(&source).unwrap(), source.replacen("x = 1", "x = 2", 1));
+    }
+
+    #[test]
+    fn test_refuses_paths_outside_workspace() {
+        let dir = tempfile::tempdir().unwrap();
+        let workspace = dir.path().join("workspace");
+        std::fs::create_dir(&workspace).unwrap();
+        std::fs::write(dir.path().join("secret.txt"), "one\n").unwrap();
+
+        for path in ["../secret.txt", "a/../../secret.txt", "/etc/passwd"] {
+            let diff = format!("--- {0}\n+++ {0}\n@@ -1 +1 @@\n-one\n+two\n", path);
+            let patch = Patch::parse(&diff).unwrap();
+            let error = patch.check(&workspace).unwrap_err().to_string();
+            assert!(error.contains("refusing to patch"), "{}: {}", path, error);
+        }
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(dir.path(), workspace.join("link")).unwrap();
+            let patch = Patch::parse("--- a/link/secret.txt\n+++ b/link/secret.txt\n@@ -1 +1 @@\n-one\n+two\n").unwrap();
+            assert!(patch.check(&workspace).is_err());
+        }
+        assert_eq!(std::fs::read_to_string(dir.path().join("secret.txt")).unwrap(), "one\n");
+    }
+
+    #[test]
+    fn test_extract_diff_block() {
+        let response = "The bug is here.\n\n```diff\n--- a/x\n+++ b/x\n@@ -1 +1 @@\n-a\n+b\n```\nDone.";
+        let diff = extract_diff_block(response).unwrap();
+
+        assert!(diff.starts_with("--- a/x"));
+        assert!(Patch::parse(&diff).is_ok());
+    }
+}
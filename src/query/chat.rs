@@ -0,0 +1,205 @@
+use crate::{embeddings::batching::count_tokens, vector::SearchResult};
+use serde::{Deserialize, Serialize};
+
+/// Earlier question and answer pairs sent along with each new question.
+const MAX_HISTORY_TURNS: usize = 10;
+
+/// Retrieved chunks kept across turns, most recently retrieved first.
+const MAX_CONTEXT_CHUNKS: usize = 30;
+
+/// Token budget for the code sent with each question.
+const MAX_CONTEXT_TOKENS: usize = 8_000;
+
+/// Token budget for the earlier turns sent with each question.
+const MAX_HISTORY_TOKENS: usize = 4_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChatRole {
+    System,
+    User,
+    Assistant,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChatMessage {
+    pub role: ChatRole,
+    pub content: String,
+}
+
+impl ChatMessage {
+    pub fn new(role: ChatRole, content: impl Into<String>) -> Self {
+        Self {
+            role,
+            content: content.into(),
+        }
+    }
+}
+
+/// A multi-turn conversation: the questions and answers so far, and the code
+/// retrieved for them, so follow-up questions can refer back to both.
+#[derive(Debug, Default)]
+pub struct ChatSession {
+    history: Vec<ChatMessage>,
+    context: Vec<SearchResult>,
+    /// Chunks retrieved for the question being answered, kept once it is
+    /// recorded.
+    pending: Vec<SearchResult>,
+}
+
+impl ChatSession {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of answered questions.
+    pub fn turns(&self) -> usize {
+        self.history.len() / 2
+    }
+
+    /// Chunks retrieved so far, most recent first.
+    pub fn context(&self) -> &[SearchResult] {
+        &self.context
+    }
+
+    /// Sets the chunks retrieved for the question being answered. They are
+    /// only added to the session's context by [`ChatSession::record`], so a
+    /// failed turn leaves the context as it was.
+    pub fn stage_context(&mut self, results: Vec<SearchResult>) {
+        self.pending = results;
+    }
+
+    /// Context for the question being answered: the staged chunks ahead of
+    /// the earlier ones, without duplicates, cut to the chunk and token
+    /// budgets.
+    pub fn turn_context(&self) -> Vec<SearchResult> {
+        let mut context: Vec<SearchResult> = Vec::new();
+        let mut tokens = 0;
+        for result in self.pending.iter().chain(&self.context) {
+            if context.len() == MAX_CONTEXT_CHUNKS {
+                break;
+            }
+            if context.iter().any(|r| r.id == result.id) {
+                continue;
+            }
+            let content = result.metadata.get("content").and_then(|v| v.as_str()).unwrap_or_default();
+            tokens += count_tokens(content);
+            if tokens > MAX_CONTEXT_TOKENS && !context.is_empty() {
+                break;
+            }
+            context.push(result.clone());
+        }
+        context
+    }
+
+    /// Records an answered question and keeps the chunks staged for it.
+    /// Only the question is kept, not the context it was asked with, which
+    /// is sent afresh with every turn.
+    pub fn record(&mut self, question: &str, answer: &str) {
+        self.context = self.turn_context();
+        self.pending.clear();
+
+        self.history.push(ChatMessage::new(ChatRole::User, question));
+        self.history.push(ChatMessage::new(ChatRole::Assistant, answer));
+
+        let excess = self.history.len().saturating_sub(MAX_HISTORY_TURNS * 2);
+        self.history.drain(..excess);
+    }
+
+    /// Messages for the next turn: the system prompt, the most recent turns
+    /// that fit the history budget, then `prompt`.
+    pub fn messages(&self, system: &str, prompt: String) -> Vec<ChatMessage> {
+        let mut start = self.history.len();
+        let mut tokens = 0;
+        for turn in self.history.chunks(2).rev() {
+            tokens += turn.iter().map(|message| count_tokens(&message.content)).sum::<usize>();
+            if tokens > MAX_HISTORY_TOKENS {
+                break;
+            }
+            start -= turn.len();
+        }
+
+        let mut messages = Vec::with_capacity(self.history.len() - start + 2);
+        messages.push(ChatMessage::new(ChatRole::System, system));
+        messages.extend(self.history[start..].iter().cloned());
+        messages.push(ChatMessage::new(ChatRole::User, prompt));
+        messages
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn result(id: &str) -> SearchResult {
+        sized(id, "")
+    }
+
+    fn sized(id: &str, content: &str) -> SearchResult {
+        SearchResult {
+            id: id.to_string(),
+            score: 1.0,
+            metadata: HashMap::from([("content".to_string(), content.into())]),
+        }
+    }
+
+    fn turn(session: &mut ChatSession, results: Vec<SearchResult>) {
+        session.stage_context(results);
+        session.record("question", "answer");
+    }
+
+    #[test]
+    fn test_session_keeps_recent_history_and_context() {
+        let mut session = ChatSession::new();
+        turn(&mut session, vec![result("a"), result("b")]);
+        turn(&mut session, vec![result("c"), result("a")]);
+        let ids: Vec<&str> = session.context().iter().map(|r| r.id.as_str()).collect();
+        assert_eq!(ids, vec!["c", "a", "b"]);
+
+        for turn in 0..MAX_HISTORY_TURNS + 2 {
+            session.record(&format!("question {}", turn), &format!("answer {}", turn));
+        }
+        assert_eq!(session.turns(), MAX_HISTORY_TURNS);
+
+        let messages = session.messages("system", "next question".to_string());
+        assert_eq!(messages.len(), MAX_HISTORY_TURNS * 2 + 2);
+        assert_eq!(messages[0], ChatMessage::new(ChatRole::System, "system"));
+        assert_eq!(messages[1], ChatMessage::new(ChatRole::User, "question 2"));
+        assert_eq!(messages[2], ChatMessage::new(ChatRole::Assistant, "answer 2"));
+        assert_eq!(messages.last().unwrap().content, "next question");
+    }
+
+    #[test]
+    fn test_failed_turn_leaves_context_unchanged() {
+        let mut session = ChatSession::new();
+        turn(&mut session, vec![result("a")]);
+
+        session.stage_context(vec![result("b")]);
+        let ids: Vec<String> = session.turn_context().into_iter().map(|r| r.id).collect();
+        assert_eq!(ids, vec!["b", "a"]);
+        assert_eq!(session.context().len(), 1);
+
+        session.stage_context(vec![result("c")]);
+        session.record("question", "answer");
+        let ids: Vec<&str> = session.context().iter().map(|r| r.id.as_str()).collect();
+        assert_eq!(ids, vec!["c", "a"]);
+    }
+
+    #[test]
+    fn test_session_trims_to_token_budgets() {
+        let chunk = "word ".repeat(MAX_CONTEXT_TOKENS / 3 - 10);
+        let mut session = ChatSession::new();
+        turn(&mut session, vec![sized("a", &chunk), sized("b", &chunk), sized("c", &chunk), sized("d", &chunk)]);
+        let ids: Vec<&str> = session.context().iter().map(|r| r.id.as_str()).collect();
+        assert_eq!(ids, vec!["a", "b", "c"]);
+
+        let answer = "word ".repeat(MAX_HISTORY_TOKENS / 2 - 10);
+        for _ in 0..3 {
+            session.record("question", &answer);
+        }
+        let messages = session.messages("system", "next question".to_string());
+        assert_eq!(messages.len(), 2 * 2 + 2);
+        assert_eq!(messages[1].role, ChatRole::User);
+    }
+}
@@ -1,6 +1,32 @@
 use crate::{
-    indexer::factory::{EmbeddingProviderType, VectorStoreType},
-    query::QueryEngine,
+    config::Config,
+    indexer::{
+        factory::{EmbeddingProviderType, VectorStoreType},
+        lexical::LexicalIndex,
+    },
+    query::{LlmClient, QueryEngine},
+    BuildliError, Result,
 };
 
-pub type BuildliQueryEngine = QueryEngine<EmbeddingProviderType, VectorStoreType>;
\ No newline at end of file
+pub type BuildliQueryEngine = QueryEngine<EmbeddingProviderType, VectorStoreType>;
+
+impl BuildliQueryEngine {
+    /// Builds the configured query engine, with hybrid retrieval over the
+    /// collection's lexical index. A local vector store is opened read-only
+    /// until something writes to it.
+    pub async fn from_config(config: &Config) -> Result<Self> {
+        let api_key = config.llm.api_key.clone().ok_or_else(|| {
+            BuildliError::Config("OpenAI API key not configured. Set llm.api_key in config.".to_string())
+        })?;
+
+        let embedder = EmbeddingProviderType::from_config(config);
+        let vector_store = VectorStoreType::from_config_read_only(config)
+            .await
+            .map_err(|e| BuildliError::VectorStore(e.to_string()))?;
+        let llm_client = LlmClient::new(api_key, config.llm.model.clone(), config.llm.temperature)
+            .with_http_config(&config.http);
+        let lexical_index = LexicalIndex::load(&config.vector.index_key()).await?;
+
+        Ok(QueryEngine::new(embedder, vector_store, llm_client).with_lexical_index(lexical_index))
+    }
+}
@@ -1,58 +1,78 @@
+pub mod chat;
 pub mod factory;
 
-use crate::{embeddings::EmbeddingProvider, vector::{VectorStore, SearchResult}, BuildliError, Result};
-use futures::StreamExt;
-use reqwest::Client;
+use crate::{config::HttpConfig, query::chat::{ChatMessage, ChatRole, ChatSession}, embeddings::{batching::count_tokens, EmbeddingProvider}, http::HttpClient, indexer::lexical::LexicalIndex, vector::{SearchFilter, SearchResult, VectorStore}, BuildliError, Result};
+use futures::{Stream, StreamExt};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
+use std::collections::{HashMap, VecDeque};
+use std::pin::Pin;
+use std::sync::Arc;
 use std::time::Duration;
-use tokio::io::AsyncWriteExt;
 use tracing::debug;
 
+const SYSTEM_PROMPT: &str = "You are a helpful code assistant.";
+
+const NO_RESULTS: &str = "No relevant code found for your query.";
+
+const LLM_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Longest wait for the next part of an answer. Streamed answers may take
+/// longer than this in total.
+const LLM_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Reciprocal rank fusion constant; larger values flatten the advantage of
+/// the first few ranks.
+const RRF_K: f32 = 60.0;
+
+/// Each retriever contributes this many times `top_k` candidates to fusion.
+const CANDIDATE_FACTOR: usize = 3;
+
 pub struct QueryEngine<E: EmbeddingProvider, V: VectorStore> {
     embedder: E,
-    vector_store: V,
+    vector_store: Arc<V>,
     llm_client: LlmClient,
+    lexical: Option<LexicalIndex>,
 }
 
 impl<E: EmbeddingProvider, V: VectorStore> QueryEngine<E, V> {
     pub fn new(embedder: E, vector_store: V, llm_client: LlmClient) -> Self {
         Self {
             embedder,
-            vector_store,
+            vector_store: Arc::new(vector_store),
             llm_client,
+            lexical: None,
         }
     }
 
-    pub async fn query(
-        &self,
-        question: &str,
-        top_k: usize,
-        stream_output: bool,
-    ) -> Result<QueryResponse> {
+    /// The store queries are answered from, to write to it without opening
+    /// it a second time.
+    pub fn vector_store(&self) -> Arc<V> {
+        self.vector_store.clone()
+    }
+
+    /// Enables hybrid retrieval: BM25 matches from `index` are fused with the
+    /// vector search results.
+    pub fn with_lexical_index(mut self, index: LexicalIndex) -> Self {
+        self.lexical = Some(index);
+        self
+    }
+
+    pub async fn query(&self, question: &str, top_k: usize, filter: &SearchFilter) -> Result<QueryResponse> {
         debug!("Processing query: {}", question);
         
-        let query_embedding = self.embedder.embed(question).await
-            .map_err(|e| BuildliError::Embedding(e.to_string()))?;
-        
-        let search_results = self.vector_store.search(query_embedding, top_k).await
-            .map_err(|e| BuildliError::VectorStore(e.to_string()))?;
+        let search_results = self.retrieve(question, top_k, filter).await?;
         
         if search_results.is_empty() {
             return Ok(QueryResponse {
-                answer: "No relevant code found for your query.".to_string(),
+                answer: NO_RESULTS.to_string(),
                 references: vec![],
             });
         }
         
         let context = self.build_context(&search_results);
         let references = self.extract_references(&search_results);
-        
-        let answer = if stream_output {
-            self.llm_client.stream_completion(question, &context).await?
-        } else {
-            self.llm_client.completion(question, &context).await?
-        };
+        let answer = self.llm_client.completion(question, &context).await?;
         
         Ok(QueryResponse {
             answer,
@@ -60,7 +80,125 @@ impl<E: EmbeddingProvider, V: VectorStore> QueryEngine<E, V> {
         })
     }
 
-    fn build_context(&self, results: &[SearchResult]) -> String {
+    /// Like [`query`](Self::query), but returns the references as soon as
+    /// they are retrieved, along with the answer as a stream of deltas.
+    pub async fn query_stream(
+        &self,
+        question: &str,
+        top_k: usize,
+        filter: &SearchFilter,
+    ) -> Result<(Vec<CodeReference>, ChatStream)> {
+        debug!("Processing streaming query: {}", question);
+        
+        let search_results = self.retrieve(question, top_k, filter).await?;
+        let references = self.extract_references(&search_results);
+        
+        if search_results.is_empty() {
+            let answer = ChatEvent::Delta(NO_RESULTS.to_string());
+            return Ok((references, Box::pin(futures::stream::iter([Ok(answer)]))));
+        }
+        
+        let context = self.build_context(&search_results);
+        let stream = self
+            .llm_client
+            .chat_stream(SYSTEM_PROMPT, &LlmClient::answer_prompt(question, &context))
+            .await?;
+        
+        Ok((references, stream))
+    }
+
+    /// Answers the next question of a conversation. Chunks retrieved for
+    /// earlier questions stay in the context next to the ones retrieved for
+    /// `question`, and earlier turns are sent as history. Returns the newly
+    /// retrieved references and the answer as a stream of deltas; record the
+    /// answer in `session` once it is complete, which also keeps the newly
+    /// retrieved chunks for later turns.
+    pub async fn chat(
+        &self,
+        session: &mut ChatSession,
+        question: &str,
+        top_k: usize,
+        filter: &SearchFilter,
+    ) -> Result<(Vec<CodeReference>, ChatStream)> {
+        debug!("Processing chat turn {}: {}", session.turns() + 1, question);
+        
+        let search_results = self.retrieve(question, top_k, filter).await?;
+        let references = self.extract_references(&search_results);
+        session.stage_context(search_results);
+        let turn_context = session.turn_context();
+        
+        if turn_context.is_empty() {
+            let answer = ChatEvent::Delta(NO_RESULTS.to_string());
+            return Ok((references, Box::pin(futures::stream::iter([Ok(answer)]))));
+        }
+        
+        let context = self.build_context(&turn_context);
+        let messages = session.messages(SYSTEM_PROMPT, LlmClient::answer_prompt(question, &context));
+        let stream = self.llm_client.chat_stream_messages(&messages).await?;
+        
+        Ok((references, stream))
+    }
+
+    /// Returns the `top_k` chunks that pass `filter` and best match
+    /// `question`. With a lexical index, vector and BM25 results are merged
+    /// with reciprocal rank fusion; otherwise this is a plain vector search.
+    pub async fn retrieve(&self, question: &str, top_k: usize, filter: &SearchFilter) -> Result<Vec<SearchResult>> {
+        let query_embedding = self.embedder.embed(question).await
+            .map_err(|e| BuildliError::Embedding(e.to_string()))?;
+        
+        let lexical = match &self.lexical {
+            Some(index) if !index.is_empty() => index,
+            _ => {
+                return self.vector_store.search(query_embedding, top_k, filter).await
+                    .map_err(|e| BuildliError::VectorStore(e.to_string()));
+            }
+        };
+        
+        let candidates = top_k * CANDIDATE_FACTOR;
+        let vector_results = self.vector_store.search(query_embedding, candidates, filter).await
+            .map_err(|e| BuildliError::VectorStore(e.to_string()))?;
+        let lexical_ids: Vec<String> = lexical
+            .search(question, candidates, filter)
+            .into_iter()
+            .map(|m| m.id)
+            .collect();
+        
+        let vector_ids: Vec<String> = vector_results.iter().map(|r| r.id.clone()).collect();
+        let fused = reciprocal_rank_fusion(&[&vector_ids, &lexical_ids], top_k);
+        debug!("Fused {} vector and {} lexical candidates", vector_ids.len(), lexical_ids.len());
+        
+        let mut results: HashMap<String, SearchResult> = vector_results
+            .into_iter()
+            .map(|result| (result.id.clone(), result))
+            .collect();
+        let missing: Vec<String> = fused
+            .iter()
+            .filter(|(id, _)| !results.contains_key(id))
+            .map(|(id, _)| id.clone())
+            .collect();
+        if !missing.is_empty() {
+            let fetched = self.vector_store.get_documents(&missing).await
+                .map_err(|e| BuildliError::VectorStore(e.to_string()))?;
+            results.extend(fetched.into_iter().map(|result| (result.id.clone(), result)));
+        }
+        
+        // Normalize so a chunk ranked first by both retrievers scores 1.0.
+        let max_score = 2.0 / (RRF_K + 1.0);
+        Ok(fused
+            .into_iter()
+            .filter_map(|(id, score)| {
+                let mut result = results.remove(&id)?;
+                result.score = score / max_score;
+                Some(result)
+            })
+            .collect())
+    }
+
+    pub fn llm_client(&self) -> &LlmClient {
+        &self.llm_client
+    }
+
+    pub fn build_context(&self, results: &[SearchResult]) -> String {
         let mut context = String::new();
         
         for (i, result) in results.iter().enumerate() {
@@ -68,6 +206,9 @@ impl<E: EmbeddingProvider, V: VectorStore> QueryEngine<E, V> {
                 if let Some(file_path) = result.metadata.get("file_path").and_then(|v| v.as_str()) {
                     context.push_str(&format!("\n--- Result {} (score: {:.3}) ---\n", i + 1, result.score));
                     context.push_str(&format!("File: {}\n", file_path));
+                    if let Some(commit) = result.metadata.get("commit").and_then(|v| v.as_str()) {
+                        context.push_str(&format!("Commit: {}\n", commit));
+                    }
                     if let Some(line_start) = result.metadata.get("line_start").and_then(|v| v.as_u64()) {
                         context.push_str(&format!("Lines: {}", line_start));
                         if let Some(line_end) = result.metadata.get("line_end").and_then(|v| v.as_u64()) {
@@ -85,7 +226,7 @@ impl<E: EmbeddingProvider, V: VectorStore> QueryEngine<E, V> {
         context
     }
 
-    fn extract_references(&self, results: &[SearchResult]) -> Vec<CodeReference> {
+    pub fn extract_references(&self, results: &[SearchResult]) -> Vec<CodeReference> {
         results
             .iter()
             .filter_map(|result| {
@@ -100,18 +241,123 @@ impl<E: EmbeddingProvider, V: VectorStore> QueryEngine<E, V> {
                     line_end,
                     snippet,
                     relevance_score: result.score,
+                    commit: result.metadata.get("commit").and_then(|v| v.as_str()).map(String::from),
                 })
             })
             .collect()
     }
 }
 
+/// Merges ranked ID lists, scoring each ID by the sum of `1 / (RRF_K + rank)`
+/// over the lists it appears in. Returns the best `top_k` IDs with their scores.
+fn reciprocal_rank_fusion(rankings: &[&[String]], top_k: usize) -> Vec<(String, f32)> {
+    let mut scores: HashMap<&str, f32> = HashMap::new();
+    for ranking in rankings {
+        for (rank, id) in ranking.iter().enumerate() {
+            *scores.entry(id.as_str()).or_default() += 1.0 / (RRF_K + rank as f32 + 1.0);
+        }
+    }
+    
+    let mut fused: Vec<(String, f32)> = scores
+        .into_iter()
+        .map(|(id, score)| (id.to_string(), score))
+        .collect();
+    fused.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    fused.truncate(top_k);
+    fused
+}
+
+/// Parses the Server-Sent Events of a streamed chat completion.
+fn chat_events<S, B>(bytes: S) -> ChatStream
+where
+    S: Stream<Item = reqwest::Result<B>> + Send + 'static,
+    B: AsRef<[u8]>,
+{
+    struct State<S> {
+        bytes: Pin<Box<S>>,
+        // SSE events, and the UTF-8 characters in them, can be split across
+        // network chunks, so only complete lines are decoded and parsed.
+        buffer: Vec<u8>,
+        pending: VecDeque<ChatEvent>,
+        done: bool,
+    }
+    
+    let state = State {
+        bytes: Box::pin(bytes),
+        buffer: Vec::new(),
+        pending: VecDeque::new(),
+        done: false,
+    };
+    
+    Box::pin(futures::stream::unfold(state, |mut state| async move {
+        loop {
+            if let Some(event) = state.pending.pop_front() {
+                return Some((Ok(event), state));
+            }
+            if state.done {
+                return None;
+            }
+            
+            let chunk = match state.bytes.next().await {
+                Some(Ok(chunk)) => chunk,
+                Some(Err(e)) => {
+                    state.done = true;
+                    return Some((Err(BuildliError::Network(e.to_string())), state));
+                }
+                None => return None,
+            };
+            state.buffer.extend_from_slice(chunk.as_ref());
+            
+            while let Some(newline) = state.buffer.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = state.buffer.drain(..=newline).collect();
+                let line = String::from_utf8_lossy(&line);
+                let Some(data) = line.trim_end().strip_prefix("data: ") else {
+                    continue;
+                };
+                if data == "[DONE]" {
+                    state.done = true;
+                    break;
+                }
+                
+                if let Ok(json) = serde_json::from_str::<serde_json::Value>(data) {
+                    if let Some(content) = json["choices"][0]["delta"]["content"].as_str() {
+                        state.pending.push_back(ChatEvent::Delta(content.to_string()));
+                    }
+                    // The last chunk before [DONE] carries the usage and no choices.
+                    if let Ok(usage) = serde_json::from_value::<Usage>(json["usage"].clone()) {
+                        state.pending.push_back(ChatEvent::Usage(usage));
+                    }
+                }
+            }
+        }
+    }))
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct QueryResponse {
     pub answer: String,
     pub references: Vec<CodeReference>,
 }
 
+/// Piece of a streamed chat completion.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChatEvent {
+    /// The next piece of the answer.
+    Delta(String),
+    /// Tokens billed for the completion, reported at the end.
+    Usage(Usage),
+}
+
+pub type ChatStream = Pin<Box<dyn Stream<Item = Result<ChatEvent>> + Send>>;
+
+/// Tokens billed for a chat completion.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Usage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub total_tokens: u64,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CodeReference {
     pub file_path: String,
@@ -119,10 +365,13 @@ pub struct CodeReference {
     pub line_end: usize,
     pub snippet: String,
     pub relevance_score: f32,
+    /// Commit the chunk was indexed from, if it came from `buildli index --commit`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commit: Option<String>,
 }
 
 pub struct LlmClient {
-    client: Client,
+    client: HttpClient,
     api_key: String,
     model: String,
     temperature: f32,
@@ -130,52 +379,33 @@ pub struct LlmClient {
 
 impl LlmClient {
     pub fn new(api_key: String, model: String, temperature: f32) -> Self {
-        let client = Client::builder()
-            .timeout(Duration::from_secs(60))
-            .build()
-            .unwrap();
-        
         Self {
-            client,
+            client: HttpClient::streaming(LLM_CONNECT_TIMEOUT, LLM_IDLE_TIMEOUT, &HttpConfig::default()),
             api_key,
             model,
             temperature,
         }
     }
 
+    pub fn with_http_config(mut self, config: &HttpConfig) -> Self {
+        self.client = HttpClient::streaming(LLM_CONNECT_TIMEOUT, LLM_IDLE_TIMEOUT, config);
+        self
+    }
+
     pub async fn completion(&self, question: &str, context: &str) -> Result<String> {
-        let prompt = format!(
-            "You are a helpful code assistant. Based on the following code context, answer the user's question.\n\
-            \n\
-            Context:\n{}\n\
-            \n\
-            Question: {}\n\
-            \n\
-            Please provide a clear and concise answer, referencing specific files and line numbers when relevant.",
-            context, question
-        );
-        
+        self.chat(SYSTEM_PROMPT, &Self::answer_prompt(question, context)).await
+    }
+
+    /// Sends a single-turn chat request and returns the full answer.
+    pub async fn chat(&self, system: &str, prompt: &str) -> Result<String> {
+        let messages = Self::single_turn(system, prompt);
         let request = json!({
             "model": self.model,
-            "messages": [
-                {"role": "system", "content": "You are a helpful code assistant."},
-                {"role": "user", "content": prompt}
-            ],
+            "messages": messages,
             "temperature": self.temperature,
         });
         
-        let response = self
-            .client
-            .post("https://api.openai.com/v1/chat/completions")
-            .header("Authorization", format!("Bearer {}", self.api_key))
-            .json(&request)
-            .send()
-            .await
-            .map_err(|e| BuildliError::Network(format!("Failed to send request to OpenAI: {}", e)))?;
-        
-        if !response.status().is_success() {
-            return Err(BuildliError::Network(format!("OpenAI API error: {}", response.status())));
-        }
+        let response = self.send_chat(&request, &messages).await?;
         
         let response_body: serde_json::Value = response.json().await
             .map_err(|e| BuildliError::Network(e.to_string()))?;
@@ -187,8 +417,64 @@ impl LlmClient {
         Ok(answer)
     }
 
-    pub async fn stream_completion(&self, question: &str, context: &str) -> Result<String> {
-        let prompt = format!(
+    /// Streams a single-turn chat request, invoking `on_delta` for every content
+    /// delta, and returns the accumulated answer.
+    pub async fn stream_chat<F>(&self, system: &str, prompt: &str, mut on_delta: F) -> Result<String>
+    where
+        F: FnMut(&str) + Send,
+    {
+        let mut stream = self.chat_stream(system, prompt).await?;
+        let mut answer = String::new();
+        while let Some(event) = stream.next().await {
+            if let ChatEvent::Delta(delta) = event? {
+                on_delta(&delta);
+                answer.push_str(&delta);
+            }
+        }
+        Ok(answer)
+    }
+
+    /// Sends a single-turn chat request and returns the answer as it is
+    /// generated, followed by the token usage.
+    pub async fn chat_stream(&self, system: &str, prompt: &str) -> Result<ChatStream> {
+        self.chat_stream_messages(&Self::single_turn(system, prompt)).await
+    }
+
+    /// Streams a chat request over a whole conversation.
+    pub async fn chat_stream_messages(&self, messages: &[ChatMessage]) -> Result<ChatStream> {
+        let request = json!({
+            "model": self.model,
+            "messages": messages,
+            "temperature": self.temperature,
+            "stream": true,
+            "stream_options": {"include_usage": true},
+        });
+        
+        let response = self.send_chat(&request, messages).await?;
+        Ok(chat_events(response.bytes_stream()))
+    }
+
+    fn single_turn(system: &str, prompt: &str) -> [ChatMessage; 2] {
+        [
+            ChatMessage::new(ChatRole::System, system),
+            ChatMessage::new(ChatRole::User, prompt),
+        ]
+    }
+
+    async fn send_chat(&self, request: &serde_json::Value, messages: &[ChatMessage]) -> Result<reqwest::Response> {
+        let tokens = messages.iter().map(|message| count_tokens(&message.content)).sum();
+        self.client
+            .send(tokens, |client| {
+                client
+                    .post("https://api.openai.com/v1/chat/completions")
+                    .header("Authorization", format!("Bearer {}", self.api_key))
+                    .json(request)
+            })
+            .await
+    }
+
+    fn answer_prompt(question: &str, context: &str) -> String {
+        format!(
             "You are a helpful code assistant. Based on the following code context, answer the user's question.\n\
             \n\
             Context:\n{}\n\
@@ -197,61 +483,45 @@ impl LlmClient {
             \n\
             Please provide a clear and concise answer, referencing specific files and line numbers when relevant.",
             context, question
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_chat_events_reassemble_split_lines() {
+        let body = concat!(
+            "data: {\"choices\":[{\"delta\":{\"role\":\"assistant\"}}]}\n\n",
+            "data: {\"choices\":[{\"delta\":{\"content\":\"Tokens are \"}}]}\n\n",
+            "data: {\"choices\":[{\"delta\":{\"content\":\"checked in auth.rs \u{2014} ✓ 認証\"}}]}\n\n",
+            "data: {\"choices\":[],\"usage\":{\"prompt_tokens\":50,\"completion_tokens\":6,\"total_tokens\":56}}\n\n",
+            "data: [DONE]\n\n",
+        );
+        // Odd-sized chunks split the multi-byte characters too.
+        let chunks: Vec<reqwest::Result<Vec<u8>>> = body
+            .as_bytes()
+            .chunks(7)
+            .map(|chunk| Ok(chunk.to_vec()))
+            .collect();
+
+        let events: Vec<ChatEvent> = chat_events(futures::stream::iter(chunks))
+            .map(|event| event.unwrap())
+            .collect()
+            .await;
+        assert_eq!(
+            events,
+            vec![
+                ChatEvent::Delta("Tokens are ".to_string()),
+                ChatEvent::Delta("checked in auth.rs \u{2014} ✓ 認証".to_string()),
+                ChatEvent::Usage(Usage {
+                    prompt_tokens: 50,
+                    completion_tokens: 6,
+                    total_tokens: 56,
+                }),
+            ]
         );
-        
-        let request = json!({
-            "model": self.model,
-            "messages": [
-                {"role": "system", "content": "You are a helpful code assistant."},
-                {"role": "user", "content": prompt}
-            ],
-            "temperature": self.temperature,
-            "stream": true,
-        });
-        
-        let response = self
-            .client
-            .post("https://api.openai.com/v1/chat/completions")
-            .header("Authorization", format!("Bearer {}", self.api_key))
-            .json(&request)
-            .send()
-            .await
-            .map_err(|e| BuildliError::Network(format!("Failed to send request to OpenAI: {}", e)))?;
-        
-        if !response.status().is_success() {
-            return Err(BuildliError::Network(format!("OpenAI API error: {}", response.status())));
-        }
-        
-        let mut stream = response.bytes_stream();
-        let mut full_response = String::new();
-        let mut stdout = tokio::io::stdout();
-        
-        while let Some(chunk) = stream.next().await {
-            let chunk = chunk.map_err(|e| BuildliError::Network(e.to_string()))?;
-            let text = String::from_utf8_lossy(&chunk);
-            
-            for line in text.lines() {
-                if line.starts_with("data: ") {
-                    let data = &line[6..];
-                    if data == "[DONE]" {
-                        break;
-                    }
-                    
-                    if let Ok(json) = serde_json::from_str::<serde_json::Value>(data) {
-                        if let Some(content) = json["choices"][0]["delta"]["content"].as_str() {
-                            full_response.push_str(content);
-                            stdout.write_all(content.as_bytes()).await
-                                .map_err(|e| BuildliError::Io(e))?;
-                            stdout.flush().await
-                                .map_err(|e| BuildliError::Io(e))?;
-                        }
-                    }
-                }
-            }
-        }
-        
-        stdout.write_all(b"\n").await
-            .map_err(|e| BuildliError::Io(e))?;
-        Ok(full_response)
     }
-}
\ No newline at end of file
+}
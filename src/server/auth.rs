@@ -0,0 +1,299 @@
+use crate::{config::ServerConfig, server::error::ApiError};
+use anyhow::{bail, Result};
+use axum::{
+    extract::{Request, State},
+    http::{
+        header::{AUTHORIZATION, SEC_WEBSOCKET_PROTOCOL, UPGRADE},
+        HeaderMap, StatusCode,
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::sync::Arc;
+use tonic::{service::Interceptor, Status};
+
+/// What a token may do. Each scope includes the ones before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Scope {
+    /// Query and read index status.
+    Read,
+    /// Also apply patches to the workspace and index.
+    Admin,
+}
+
+impl std::str::FromStr for Scope {
+    type Err = anyhow::Error;
+
+    fn from_str(scope: &str) -> Result<Self> {
+        match scope {
+            "read" => Ok(Scope::Read),
+            "admin" => Ok(Scope::Admin),
+            other => bail!("Unknown token scope '{}': expected read or admin", other),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthError {
+    /// No bearer token, or one that matches no configured token.
+    Unauthenticated,
+    /// A valid token without the scope the request needs.
+    Forbidden,
+}
+
+struct Token {
+    name: String,
+    secret: Vec<u8>,
+    scope: Scope,
+}
+
+/// Checks bearer tokens against the `--token` flag and the tokens in the
+/// `[server]` config. With no tokens at all, every request is allowed.
+#[derive(Clone)]
+pub struct Authenticator {
+    tokens: Arc<Vec<Token>>,
+}
+
+impl Authenticator {
+    /// `cli_token` is accepted with the admin scope.
+    pub fn new(cli_token: Option<String>, config: &ServerConfig) -> Result<Self> {
+        let mut tokens = Vec::new();
+        if let Some(token) = cli_token {
+            tokens.push(Token {
+                name: "cli".to_string(),
+                secret: token.into_bytes(),
+                scope: Scope::Admin,
+            });
+        }
+        for token in &config.tokens {
+            let secret = match token.token.strip_prefix("env:") {
+                Some(env_var) => std::env::var(env_var).map_err(|_| {
+                    anyhow::anyhow!("API token '{}' reads unset environment variable {}", token.name, env_var)
+                })?,
+                None => token.token.clone(),
+            };
+            if secret.is_empty() {
+                bail!("API token '{}' is empty", token.name);
+            }
+            tokens.push(Token {
+                name: token.name.clone(),
+                secret: secret.into_bytes(),
+                scope: token.scope.parse()?,
+            });
+        }
+
+        Ok(Self {
+            tokens: Arc::new(tokens),
+        })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.tokens.is_empty()
+    }
+
+    /// Finds the token presented in an `Authorization` header value and
+    /// returns its name and scope. Without authentication enabled, every
+    /// request is anonymous with the admin scope.
+    pub fn authenticate(&self, authorization: Option<&str>) -> Result<(Option<&str>, Scope), AuthError> {
+        if !self.is_enabled() {
+            return Ok((None, Scope::Admin));
+        }
+
+        let presented = authorization
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or(AuthError::Unauthenticated)?
+            .trim()
+            .as_bytes();
+
+        // Compare against every token so timing reveals nothing about which,
+        // if any, matched.
+        let mut matched = None;
+        for token in self.tokens.iter() {
+            if constant_time_eq(&token.secret, presented) && matched.is_none() {
+                matched = Some(token);
+            }
+        }
+
+        let token = matched.ok_or(AuthError::Unauthenticated)?;
+        Ok((Some(&token.name), token.scope))
+    }
+
+    /// Like [`authenticate`](Self::authenticate), but also requires the
+    /// token to have the `required` scope.
+    pub fn authorize(&self, authorization: Option<&str>, required: Scope) -> Result<Option<&str>, AuthError> {
+        let (name, scope) = self.authenticate(authorization)?;
+        if scope < required {
+            return Err(AuthError::Forbidden);
+        }
+        Ok(name)
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    let mut diff = a.len() ^ b.len();
+    for i in 0..a.len().max(b.len()) {
+        let x = a.get(i).copied().unwrap_or(0);
+        let y = b.get(i).copied().unwrap_or(0);
+        diff |= usize::from(x ^ y);
+    }
+    diff == 0
+}
+
+impl From<AuthError> for ApiError {
+    fn from(error: AuthError) -> Self {
+        match error {
+            AuthError::Unauthenticated => ApiError {
+                status: StatusCode::UNAUTHORIZED,
+                message: "Missing or invalid bearer token".to_string(),
+            },
+            AuthError::Forbidden => ApiError {
+                status: StatusCode::FORBIDDEN,
+                message: "Token lacks the scope for this request".to_string(),
+            },
+        }
+    }
+}
+
+impl From<AuthError> for Status {
+    fn from(error: AuthError) -> Self {
+        match error {
+            AuthError::Unauthenticated => Status::unauthenticated("Missing or invalid bearer token"),
+            AuthError::Forbidden => Status::permission_denied("Token lacks the scope for this request"),
+        }
+    }
+}
+
+/// Axum middleware state: the authenticator and the scope routes need.
+#[derive(Clone)]
+pub struct RequireScope {
+    pub auth: Authenticator,
+    pub scope: Scope,
+}
+
+/// WebSocket subprotocol prefix for passing a bearer token, since browsers
+/// cannot set headers on a WebSocket handshake.
+pub const BEARER_PROTOCOL_PREFIX: &str = "bearer.";
+
+/// The `Authorization` header, or, failing that and only on a WebSocket
+/// upgrade, a `bearer.<token>` subprotocol rewritten as one.
+fn authorization(headers: &HeaderMap) -> Option<String> {
+    if let Some(value) = headers.get(AUTHORIZATION) {
+        return value.to_str().ok().map(str::to_string);
+    }
+    let upgrade = headers.get(UPGRADE).and_then(|value| value.to_str().ok());
+    if !upgrade.is_some_and(|value| value.eq_ignore_ascii_case("websocket")) {
+        return None;
+    }
+    headers
+        .get_all(SEC_WEBSOCKET_PROTOCOL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .find_map(|protocol| protocol.trim().strip_prefix(BEARER_PROTOCOL_PREFIX))
+        .map(|token| format!("Bearer {}", token))
+}
+
+/// Rejects requests without a bearer token of the required scope.
+pub async fn require_scope(State(required): State<RequireScope>, request: Request, next: Next) -> Response {
+    let authorization = authorization(request.headers());
+
+    match required.auth.authorize(authorization.as_deref(), required.scope) {
+        Ok(name) => {
+            if let Some(name) = name {
+                tracing::debug!("{} {} authorized as {}", request.method(), request.uri().path(), name);
+            }
+            next.run(request).await
+        }
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+/// Tonic interceptor that checks the `authorization` metadata of every call
+/// and records the token's scope in the request extensions, for methods that
+/// need more than read access to check with [`require_grpc_scope`].
+#[derive(Clone)]
+pub struct GrpcAuth {
+    pub auth: Authenticator,
+}
+
+impl Interceptor for GrpcAuth {
+    fn call(&mut self, mut request: tonic::Request<()>) -> Result<tonic::Request<()>, Status> {
+        let authorization = request
+            .metadata()
+            .get(AUTHORIZATION.as_str())
+            .and_then(|value| value.to_str().ok());
+        let (_, scope) = self.auth.authenticate(authorization)?;
+        request.extensions_mut().insert(scope);
+        Ok(request)
+    }
+}
+
+/// Checks the scope [`GrpcAuth`] recorded for a call.
+pub fn require_grpc_scope<T>(request: &tonic::Request<T>, required: Scope) -> Result<(), AuthError> {
+    match request.extensions().get::<Scope>() {
+        Some(scope) if *scope >= required => Ok(()),
+        Some(_) => Err(AuthError::Forbidden),
+        None => Err(AuthError::Unauthenticated),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ApiToken;
+
+    #[test]
+    fn test_tokens_are_checked_for_scope() {
+        let config = ServerConfig {
+            tokens: vec![ApiToken {
+                name: "dashboard".to_string(),
+                token: "read-secret".to_string(),
+                scope: "read".to_string(),
+            }],
+        };
+        let auth = Authenticator::new(Some("admin-secret".to_string()), &config).unwrap();
+
+        assert_eq!(auth.authorize(Some("Bearer read-secret"), Scope::Read), Ok(Some("dashboard")));
+        assert_eq!(auth.authorize(Some("Bearer read-secret"), Scope::Admin), Err(AuthError::Forbidden));
+        assert_eq!(auth.authorize(Some("Bearer admin-secret"), Scope::Admin), Ok(Some("cli")));
+        assert_eq!(auth.authorize(Some("Bearer read-secre"), Scope::Read), Err(AuthError::Unauthenticated));
+        assert_eq!(auth.authorize(Some("read-secret"), Scope::Read), Err(AuthError::Unauthenticated));
+        assert_eq!(auth.authorize(None, Scope::Read), Err(AuthError::Unauthenticated));
+
+        let open = Authenticator::new(None, &ServerConfig::default()).unwrap();
+        assert_eq!(open.authorize(None, Scope::Admin), Ok(None));
+    }
+
+    #[test]
+    fn test_tokens_from_environment() {
+        let config = |token: &str| ServerConfig {
+            tokens: vec![ApiToken {
+                name: "dashboard".to_string(),
+                token: token.to_string(),
+                scope: "read".to_string(),
+            }],
+        };
+        std::env::set_var("BUILDLI_TEST_AUTH_TOKEN", "env-secret");
+        std::env::set_var("BUILDLI_TEST_AUTH_EMPTY", "");
+
+        let auth = Authenticator::new(None, &config("env:BUILDLI_TEST_AUTH_TOKEN")).unwrap();
+        assert_eq!(auth.authorize(Some("Bearer env-secret"), Scope::Read), Ok(Some("dashboard")));
+        assert!(Authenticator::new(None, &config("env:BUILDLI_TEST_AUTH_EMPTY")).is_err());
+        assert!(Authenticator::new(None, &config("env:BUILDLI_TEST_AUTH_UNSET")).is_err());
+    }
+
+    #[test]
+    fn test_token_from_websocket_protocol() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(authorization(&headers), None);
+
+        headers.insert(SEC_WEBSOCKET_PROTOCOL, "buildli.chat, bearer.read-secret".parse().unwrap());
+        assert_eq!(authorization(&headers), None);
+
+        headers.insert(UPGRADE, "websocket".parse().unwrap());
+        assert_eq!(authorization(&headers).as_deref(), Some("Bearer read-secret"));
+
+        headers.insert(AUTHORIZATION, "Bearer admin-secret".parse().unwrap());
+        assert_eq!(authorization(&headers).as_deref(), Some("Bearer admin-secret"));
+    }
+}
@@ -0,0 +1,110 @@
+use super::{query_engine, AppState, QueryRequest};
+use crate::{
+    query::{chat::ChatSession, ChatEvent},
+    server::error::ApiError,
+    vector::SearchFilter,
+};
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        State,
+    },
+    response::Response,
+};
+use futures::StreamExt;
+use serde_json::json;
+use std::sync::Arc;
+
+/// Subprotocol the server selects when a client offers it.
+pub const CHAT_PROTOCOL: &str = "buildli.chat";
+
+/// Opens a chat session over a WebSocket. The client sends questions as JSON
+/// text messages with the body of `/v1/query`; each is answered with a
+/// `references` message, a `token` message per answer delta, then `done` or
+/// `error`. The session lasts as long as the connection.
+///
+/// Clients that cannot set an `Authorization` header pass the token as a
+/// `bearer.<token>` subprotocol next to [`CHAT_PROTOCOL`], which the server
+/// selects.
+pub(super) async fn chat_handler(
+    State(state): State<Arc<AppState>>,
+    ws: WebSocketUpgrade,
+) -> Result<Response, ApiError> {
+    // Fail the upgrade, rather than every question, if the engine is unusable.
+    query_engine(&state).await?;
+    Ok(ws
+        .protocols([CHAT_PROTOCOL])
+        .on_upgrade(move |socket| chat_session(socket, state)))
+}
+
+async fn chat_session(mut socket: WebSocket, state: Arc<AppState>) {
+    let mut session = ChatSession::new();
+
+    while let Some(Ok(message)) = socket.recv().await {
+        let text = match message {
+            Message::Text(text) => text,
+            Message::Close(_) => break,
+            _ => continue,
+        };
+
+        let answered = match serde_json::from_str::<QueryRequest>(&text) {
+            Ok(request) => answer(&mut socket, &state, &mut session, request).await,
+            Err(e) => send(&mut socket, json!({ "type": "error", "error": e.to_string() })).await,
+        };
+        if !answered {
+            // The client went away.
+            break;
+        }
+    }
+
+    tracing::debug!("Chat session closed after {} turns", session.turns());
+}
+
+/// Answers one question, returning false once the socket is closed.
+async fn answer(
+    socket: &mut WebSocket,
+    state: &AppState,
+    session: &mut ChatSession,
+    request: QueryRequest,
+) -> bool {
+    if let Err(e) = request.validate() {
+        return send(socket, json!({ "type": "error", "error": e.message })).await;
+    }
+    // Each turn uses the current engine, so a session outlives reloads.
+    let engine = match query_engine(state).await {
+        Ok(engine) => engine,
+        Err(e) => return send(socket, json!({ "type": "error", "error": e.message })).await,
+    };
+
+    let filter = SearchFilter::new(request.repos, request.languages);
+    let (references, mut stream) = match engine.chat(session, &request.question, request.top_k, &filter).await {
+        Ok(answer) => answer,
+        Err(e) => return send(socket, json!({ "type": "error", "error": e.to_string() })).await,
+    };
+    if !send(socket, json!({ "type": "references", "references": references })).await {
+        return false;
+    }
+
+    let mut answer = String::new();
+    let mut usage = None;
+    while let Some(event) = stream.next().await {
+        match event {
+            Ok(ChatEvent::Delta(delta)) => {
+                if !send(socket, json!({ "type": "token", "delta": delta })).await {
+                    return false;
+                }
+                answer.push_str(&delta);
+            }
+            Ok(ChatEvent::Usage(reported)) => usage = Some(reported),
+            // A failed answer is left out of the history.
+            Err(e) => return send(socket, json!({ "type": "error", "error": e.to_string() })).await,
+        }
+    }
+
+    session.record(&request.question, &answer);
+    send(socket, json!({ "type": "done", "usage": usage })).await
+}
+
+async fn send(socket: &mut WebSocket, message: serde_json::Value) -> bool {
+    socket.send(Message::Text(message.to_string())).await.is_ok()
+}
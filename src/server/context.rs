@@ -0,0 +1,151 @@
+use crate::{
+    config::{Config, ConfigManager},
+    query::factory::BuildliQueryEngine,
+};
+use anyhow::Result;
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// How long to wait for a burst of file events to settle before reloading.
+const RELOAD_DEBOUNCE: Duration = Duration::from_millis(250);
+
+/// State shared by the HTTP and gRPC services: the config and the query
+/// engine built from it. Both are built once and rebuilt when the config
+/// file changes or an indexing run finishes, instead of on every request.
+pub struct AppContext {
+    config_manager: ConfigManager,
+    config: RwLock<Arc<Config>>,
+    engine: RwLock<Option<Arc<BuildliQueryEngine>>>,
+}
+
+impl AppContext {
+    /// Loads the config and builds the query engine. An engine that cannot be
+    /// built yet, e.g. without an API key, is retried when a request needs it.
+    pub async fn load(config_manager: ConfigManager) -> Result<Arc<Self>> {
+        let config = config_manager.load().await?;
+        let context = Arc::new(Self {
+            config_manager,
+            config: RwLock::new(Arc::new(config)),
+            engine: RwLock::new(None),
+        });
+
+        if let Err(e) = context.query_engine().await {
+            tracing::warn!("Query engine unavailable: {}", e);
+        }
+        Ok(context)
+    }
+
+    pub fn config(&self) -> Arc<Config> {
+        self.config.read().unwrap().clone()
+    }
+
+    /// The shared query engine, built from the current config if it has not
+    /// been yet.
+    pub async fn query_engine(&self) -> crate::Result<Arc<BuildliQueryEngine>> {
+        if let Some(engine) = self.engine.read().unwrap().clone() {
+            return Ok(engine);
+        }
+
+        let engine = Arc::new(BuildliQueryEngine::from_config(&self.config()).await?);
+        *self.engine.write().unwrap() = Some(engine.clone());
+        Ok(engine)
+    }
+
+    /// Rereads the config and rebuilds the query engine. Requests keep the
+    /// previous engine until the new one is ready, and a config that fails to
+    /// load leaves both in place.
+    pub async fn reload(&self) {
+        let config = match self.config_manager.load().await {
+            Ok(config) => config,
+            Err(e) => {
+                tracing::warn!("Keeping the previous configuration: {:#}", e);
+                return;
+            }
+        };
+
+        let engine = match BuildliQueryEngine::from_config(&config).await {
+            Ok(engine) => Some(Arc::new(engine)),
+            Err(e) => {
+                tracing::warn!("Query engine unavailable: {}", e);
+                None
+            }
+        };
+        *self.config.write().unwrap() = Arc::new(config);
+        *self.engine.write().unwrap() = engine;
+        tracing::info!("Reloaded configuration and query engine");
+    }
+
+    /// Reloads whenever the config file or the index metadata changes. The
+    /// metadata is written at the end of every indexing run, including runs
+    /// by other processes. Watching stops when the returned watcher is dropped.
+    pub fn watch(self: &Arc<Self>) -> Result<RecommendedWatcher> {
+        let config_path = self.config_manager.path().to_path_buf();
+        let metadata_dir = crate::config::data_dir()?.join("metadata");
+        let config_dir = config_path.parent().map(Path::to_path_buf).unwrap_or_default();
+        // Watch the directories: files are replaced by renames, which would
+        // end a watch on the file itself.
+        std::fs::create_dir_all(&config_dir)?;
+        std::fs::create_dir_all(&metadata_dir)?;
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let watched_metadata = metadata_dir.clone();
+        let is_relevant = move |path: &PathBuf| {
+            *path == config_path
+                || (path.starts_with(&watched_metadata) && path.extension().is_some_and(|ext| ext == "json"))
+        };
+        let mut watcher = notify::recommended_watcher(move |res: notify::Result<Event>| {
+            if let Ok(event) = res {
+                if !event.kind.is_access() && event.paths.iter().any(&is_relevant) {
+                    let _ = tx.send(());
+                }
+            }
+        })?;
+        watcher.watch(&config_dir, RecursiveMode::NonRecursive)?;
+        // Collections with a backend prefix keep their metadata in subdirectories.
+        watcher.watch(&metadata_dir, RecursiveMode::Recursive)?;
+
+        let context = Arc::downgrade(self);
+        tokio::spawn(async move {
+            while rx.recv().await.is_some() {
+                tokio::time::sleep(RELOAD_DEBOUNCE).await;
+                while rx.try_recv().is_ok() {}
+
+                let Some(context) = context.upgrade() else {
+                    break;
+                };
+                context.reload().await;
+            }
+        });
+
+        Ok(watcher)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_reload_picks_up_config_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_manager = ConfigManager::at(dir.path().join("config.toml"));
+        let context = AppContext::load(config_manager.clone()).await.unwrap();
+        assert!(context.query_engine().await.is_err());
+
+        let mut config = Config::default();
+        config.llm.model = "gpt-4.1".to_string();
+        config_manager.save(&config).await.unwrap();
+        assert_ne!(context.config().llm.model, "gpt-4.1");
+
+        context.reload().await;
+        assert_eq!(context.config().llm.model, "gpt-4.1");
+
+        // A broken config leaves the last good one in place.
+        std::fs::write(dir.path().join("config.toml"), "not = [toml").unwrap();
+        context.reload().await;
+        assert_eq!(context.config().llm.model, "gpt-4.1");
+    }
+}
@@ -0,0 +1,55 @@
+use crate::BuildliError;
+use axum::{
+    extract::rejection::JsonRejection,
+    http::StatusCode,
+    response::{IntoResponse, Json, Response},
+};
+use serde_json::json;
+
+/// Error returned by the REST API as `{"error": message}`.
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: StatusCode,
+    pub message: String,
+}
+
+impl ApiError {
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        Self {
+            status: StatusCode::BAD_REQUEST,
+            message: message.into(),
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.status, Json(json!({ "error": self.message }))).into_response()
+    }
+}
+
+impl From<BuildliError> for ApiError {
+    fn from(error: BuildliError) -> Self {
+        let status = match &error {
+            BuildliError::Query(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            // The server is missing configuration or its vector store.
+            BuildliError::Config(_) | BuildliError::VectorStore(_) => StatusCode::SERVICE_UNAVAILABLE,
+            // An upstream API failed.
+            BuildliError::Embedding(_) | BuildliError::Network(_) => StatusCode::BAD_GATEWAY,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        Self {
+            status,
+            message: error.to_string(),
+        }
+    }
+}
+
+impl From<JsonRejection> for ApiError {
+    fn from(rejection: JsonRejection) -> Self {
+        Self {
+            status: rejection.status(),
+            message: rejection.body_text(),
+        }
+    }
+}
@@ -1,16 +1,29 @@
 use crate::{
-    config::ConfigManager,
-    embeddings::{LocalEmbeddings, OpenAIEmbeddings},
-    indexer::factory::{EmbeddingProviderType, VectorStoreType},
-    query::{factory::BuildliQueryEngine, LlmClient, QueryEngine},
-    vector::{PersistentLocalVectorStore, QdrantStore, VectorStore},
+    bug::BugSolver,
+    config::Config,
+    indexer::{
+        factory::EmbeddingProviderType,
+        metadata::IndexMetadata,
+        Indexer,
+    },
+    patch::{
+        journal::{JournalEntry, PatchJournal},
+        Patch,
+    },
+    query::{factory::BuildliQueryEngine, ChatEvent},
+    server::{
+        auth::{require_grpc_scope, Authenticator, GrpcAuth, Scope},
+        context::AppContext,
+        MAX_TOP_K,
+    },
+    vector::SearchFilter,
     BuildliError,
 };
+use std::path::{Path, PathBuf};
 use std::pin::Pin;
 use std::sync::Arc;
-use tokio::sync::RwLock;
 use tokio_stream::{Stream, StreamExt};
-use tonic::{Request, Response, Status};
+use tonic::{service::interceptor::InterceptedService, Request, Response, Status};
 
 // Import the generated proto types
 pub mod proto {
@@ -20,28 +33,23 @@ pub mod proto {
 use proto::{
     buildli_service_server::{BuildliService, BuildliServiceServer},
     BugSolveRequest, BugSolveResponse, CodeReference, IndexStatusRequest, IndexStatusResponse,
-    QueryRequest, QueryResponse,
+    QueryRequest, QueryResponse, RootStatus,
 };
 
 pub struct BuildliGrpcService {
-    config_manager: ConfigManager,
-    stats: Arc<RwLock<IndexStats>>,
-}
-
-#[derive(Default)]
-struct IndexStats {
-    total_files: i64,
-    indexed_files: i64,
-    total_chunks: i64,
-    last_updated: String,
+    context: Arc<AppContext>,
 }
 
 impl BuildliGrpcService {
-    pub fn new(config_manager: ConfigManager) -> Self {
-        Self {
-            config_manager,
-            stats: Arc::new(RwLock::new(IndexStats::default())),
-        }
+    pub fn new(context: Arc<AppContext>) -> Self {
+        Self { context }
+    }
+
+    async fn query_engine(&self) -> Result<Arc<BuildliQueryEngine>, Status> {
+        self.context.query_engine().await.map_err(|e| match e {
+            BuildliError::Config(message) => Status::failed_precondition(message),
+            e => Status::internal(format!("Failed to build query engine: {}", e)),
+        })
     }
 }
 
@@ -55,84 +63,56 @@ impl BuildliService for BuildliGrpcService {
         request: Request<QueryRequest>,
     ) -> Result<Response<Self::QueryStream>, Status> {
         let query_request = request.into_inner();
-        let config = self.config_manager.load().await.map_err(|e| {
-            Status::internal(format!("Failed to load configuration: {}", e))
-        })?;
-
-        if config.llm.api_key.is_none() {
-            return Err(Status::failed_precondition(
-                "OpenAI API key not configured. Set llm.api_key in config.",
-            ));
+        if query_request.question.trim().is_empty() {
+            return Err(Status::invalid_argument("question must not be empty"));
         }
-
-        // Create embedder
-        let embedder = match config.embedding.provider.as_str() {
-            "openai" => EmbeddingProviderType::OpenAI(OpenAIEmbeddings::new(
-                config.llm.api_key.clone().unwrap(),
-                config.embedding.model.clone(),
-            )),
-            _ => EmbeddingProviderType::Local(LocalEmbeddings::new()),
-        };
-
-        // Create vector store
-        let vector_store = match config.vector.backend.as_str() {
-            "qdrant" => {
-                let store = QdrantStore::new(&config.vector.url, &config.vector.collection_name)
-                    .await
-                    .map_err(|e| Status::internal(format!("Failed to create vector store: {}", e)))?;
-                VectorStoreType::Qdrant(store)
-            }
-            _ => {
-                let store = PersistentLocalVectorStore::new()
-                    .await
-                    .map_err(|e| Status::internal(format!("Failed to create vector store: {}", e)))?;
-                VectorStoreType::Local(store)
-            }
-        };
-
-        // Create LLM client
-        let llm_client = LlmClient::new(
-            config.llm.api_key.unwrap(),
-            config.llm.model.clone(),
-            config.llm.temperature,
-        );
-
-        // Create query engine
-        let query_engine: BuildliQueryEngine = QueryEngine::new(embedder, vector_store, llm_client);
+        let query_engine = self.query_engine().await?;
         
-        let top_k = query_request.top_k.max(1) as usize;
+        let top_k = (query_request.top_k.max(1) as usize).min(MAX_TOP_K);
         let question = query_request.question.clone();
+        let filter = SearchFilter::new(query_request.repos, query_request.languages);
+
+        let (references, mut answer) = query_engine
+            .query_stream(&question, top_k, &filter)
+            .await
+            .map_err(|e| Status::internal(format!("Query failed: {}", e)))?;
 
         // Create a channel for streaming responses
         let (tx, rx) = tokio::sync::mpsc::channel(32);
         
         tokio::spawn(async move {
-            match query_engine.query(&question, top_k, true).await {
-                Ok(response) => {
-                    // Send the main response
-                    let proto_references: Vec<CodeReference> = response
-                        .references
-                        .into_iter()
-                        .map(|r| CodeReference {
-                            file_path: r.file_path,
-                            line_start: r.line_start as i32,
-                            line_end: r.line_end as i32,
-                            snippet: r.snippet,
-                            relevance_score: r.relevance_score,
-                        })
-                        .collect();
-
-                    let _ = tx
-                        .send(Ok(QueryResponse {
-                            chunk: response.answer,
-                            references: proto_references,
-                        }))
-                        .await;
-                }
-                Err(e) => {
-                    let _ = tx
-                        .send(Err(Status::internal(format!("Query failed: {}", e))))
-                        .await;
+            // The first message carries the references, the rest one delta each.
+            let references = references
+                .into_iter()
+                .map(|r| CodeReference {
+                    file_path: r.file_path,
+                    line_start: r.line_start as i32,
+                    line_end: r.line_end as i32,
+                    snippet: r.snippet,
+                    relevance_score: r.relevance_score,
+                    commit: r.commit.unwrap_or_default(),
+                })
+                .collect();
+            let first = QueryResponse {
+                chunk: String::new(),
+                references,
+            };
+            if tx.send(Ok(first)).await.is_err() {
+                return;
+            }
+
+            while let Some(event) = answer.next().await {
+                let message = match event {
+                    Ok(ChatEvent::Delta(chunk)) => Ok(QueryResponse {
+                        chunk,
+                        references: vec![],
+                    }),
+                    Ok(ChatEvent::Usage(_)) => continue,
+                    Err(e) => Err(Status::internal(format!("Query failed: {}", e))),
+                };
+                if tx.send(message).await.is_err() {
+                    // The client went away.
+                    return;
                 }
             }
         });
@@ -145,44 +125,146 @@ impl BuildliService for BuildliGrpcService {
         &self,
         request: Request<BugSolveRequest>,
     ) -> Result<Response<Self::BugSolveStream>, Status> {
+        if request.get_ref().apply {
+            require_grpc_scope(&request, Scope::Admin)?;
+        }
         let bug_request = request.into_inner();
+        let query_engine = self.query_engine().await?;
+        let workspace_root = workspace_root(&self.context.config()).map_err(Status::failed_precondition)?;
+        let solver = BugSolver::new(query_engine, workspace_root);
+        let context = self.context.clone();
         
-        // For now, return a simple response indicating the feature is coming soon
-        let (tx, rx) = tokio::sync::mpsc::channel(1);
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
         
         tokio::spawn(async move {
-            let _ = tx
-                .send(Ok(BugSolveResponse {
-                    chunk: format!(
-                        "Bug solver mode for '{}' is coming soon. This feature will analyze your bug description and suggest patches.",
-                        bug_request.description
-                    ),
-                    patch: String::new(),
-                    affected_files: vec![],
-                }))
+            let delta_tx = tx.clone();
+            let result = solver
+                .solve_streaming(&bug_request.description, |delta| {
+                    let _ = delta_tx.send(Ok(BugSolveResponse {
+                        chunk: delta.to_string(),
+                        patch: String::new(),
+                        affected_files: vec![],
+                    }));
+                })
                 .await;
+            
+            match result {
+                Ok(solution) => {
+                    let chunk = match (&solution.validation_error, &solution.patch) {
+                        (Some(error), _) => format!("\n\nWarning: patch does not apply cleanly: {}", error),
+                        (None, Some(patch)) if bug_request.apply => {
+                            match apply_patch(patch, solver.workspace_root()) {
+                                Ok(entry) => {
+                                    let reindexed = reindex_patched_files(&context, &entry).await;
+                                    // Let queries see whatever made it into the index.
+                                    context.reload().await;
+                                    match reindexed {
+                                        Ok(()) => format!("\n\nApplied patch {}", entry.id),
+                                        Err(e) => format!(
+                                            "\n\nApplied patch {}, but reindexing the patched files failed: {}",
+                                            entry.id, e
+                                        ),
+                                    }
+                                }
+                                Err(e) => format!("\n\nFailed to apply patch: {}", e),
+                            }
+                        }
+                        _ => String::new(),
+                    };
+                    
+                    let _ = tx.send(Ok(BugSolveResponse {
+                        chunk,
+                        patch: solution.patch.unwrap_or_default(),
+                        affected_files: solution.affected_files,
+                    }));
+                }
+                Err(e) => {
+                    let _ = tx.send(Err(Status::internal(format!("Bug solving failed: {}", e))));
+                }
+            }
         });
 
-        let stream = tokio_stream::wrappers::ReceiverStream::new(rx);
+        let stream = tokio_stream::wrappers::UnboundedReceiverStream::new(rx);
         Ok(Response::new(Box::pin(stream)))
     }
 
     async fn index_status(
         &self,
-        _request: Request<IndexStatusRequest>,
+        request: Request<IndexStatusRequest>,
     ) -> Result<Response<IndexStatusResponse>, Status> {
-        let stats = self.stats.read().await;
+        let metadata = IndexMetadata::load(&self.context.config().vector.index_key())
+            .await
+            .map_err(|e| Status::internal(format!("Failed to load index metadata: {}", e)))?;
+        let paths: Vec<PathBuf> = request.into_inner().paths.iter().map(PathBuf::from).collect();
+        let status = metadata.status(&paths);
         
         Ok(Response::new(IndexStatusResponse {
-            total_files: stats.total_files,
-            indexed_files: stats.indexed_files,
-            total_chunks: stats.total_chunks,
-            last_updated: stats.last_updated.clone(),
+            total_files: status.total_files as i64,
+            indexed_files: status.indexed_files as i64,
+            total_chunks: status.total_chunks as i64,
+            last_updated: status.last_updated,
+            failed_files: status.failed_files as i64,
+            roots: status
+                .roots
+                .into_iter()
+                .map(|root| RootStatus {
+                    path: root.path.display().to_string(),
+                    total_files: root.total_files as i64,
+                    indexed_files: root.indexed_files as i64,
+                    failed_files: root.failed_files as i64,
+                    total_chunks: root.total_chunks as i64,
+                    last_indexed: root.last_indexed as i64,
+                    commit: root.commit.unwrap_or_default(),
+                    embedding_model: root.embedding_model,
+                })
+                .collect(),
         }))
     }
 }
 
-pub fn create_grpc_service(config_manager: ConfigManager) -> BuildliServiceServer<BuildliGrpcService> {
-    let service = BuildliGrpcService::new(config_manager);
-    BuildliServiceServer::new(service)
+/// The indexed root patches are validated and applied against. With more
+/// than one configured root it is unclear which one a patch belongs to.
+fn workspace_root(config: &Config) -> Result<PathBuf, String> {
+    let root = match config.paths.index_root.as_slice() {
+        [root] => root,
+        [] => return Err("No index root configured in paths.index_root".to_string()),
+        _ => return Err("Several index roots are configured in paths.index_root; patches need exactly one".to_string()),
+    };
+    root.canonicalize()
+        .map_err(|e| format!("Index root {} is unusable: {}", root.display(), e))
+}
+
+/// Applies a validated patch through the journal.
+fn apply_patch(patch: &str, workspace_root: &Path) -> crate::Result<JournalEntry> {
+    PatchJournal::new()?.apply(&Patch::parse(patch)?, workspace_root)
+}
+
+/// Replaces the chunks of the files touched by an applied patch, writing
+/// through the store the context's query engine already has open.
+async fn reindex_patched_files(context: &AppContext, entry: &JournalEntry) -> crate::Result<()> {
+    let config = context.config();
+    let vector_store = context.query_engine().await?.vector_store();
+    let embedder = EmbeddingProviderType::from_config(&config);
+    let mut indexer = Indexer::new(embedder, vector_store).with_collection(config.vector.index_key());
+    indexer.load_manifest(&entry.workspace_root).await?;
+    indexer.load_lexical_index().await?;
+    let mut stats = crate::indexer::IndexStats::default();
+
+    for file in &entry.files {
+        indexer.delete_file_chunks(&file.path).await?;
+        if file.path.exists() {
+            indexer.index_file(&file.path, &mut stats).await?;
+        }
+    }
+    indexer.save_state().await?;
+
+    Ok(())
+}
+
+pub fn create_grpc_service(
+    context: Arc<AppContext>,
+    auth: Authenticator,
+) -> InterceptedService<BuildliServiceServer<BuildliGrpcService>, GrpcAuth> {
+    let service = BuildliGrpcService::new(context);
+    BuildliServiceServer::with_interceptor(service, GrpcAuth { auth })
 }
\ No newline at end of file
@@ -1,33 +1,62 @@
+pub mod auth;
+mod chat;
+pub mod context;
+pub mod error;
 pub mod grpc;
 
-use crate::config::ConfigManager;
+use crate::{
+    config::ConfigManager,
+    indexer::{metadata::IndexMetadata, progress::Progress},
+    query::{factory::BuildliQueryEngine, ChatEvent, QueryResponse},
+    vector::SearchFilter,
+};
 use anyhow::Result;
+use auth::{require_scope, Authenticator, RequireScope, Scope};
+use context::AppContext;
 use axum::{
-    extract::State,
+    extract::{rejection::JsonRejection, Query, State},
     http::StatusCode,
-    response::Json,
+    middleware,
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        Json,
+    },
     routing::{get, post},
     Router,
 };
+use serde::Deserialize;
 use serde_json::json;
+use std::convert::Infallible;
+use std::path::PathBuf;
 use std::sync::Arc;
+use tokio::sync::mpsc;
+use tokio_stream::{wrappers::UnboundedReceiverStream, Stream, StreamExt};
 use tower_http::cors::CorsLayer;
+use error::ApiError;
 use tonic::transport::Server;
 
+/// Largest `top_k` a query may ask for.
+const MAX_TOP_K: usize = 100;
+
 pub async fn run_server(
     port: u16,
     token: Option<String>,
     config_manager: ConfigManager,
 ) -> Result<()> {
-    // Clone config_manager for both servers
-    let http_config = config_manager.clone();
-    let grpc_config = config_manager.clone();
+    // Both servers share one context, rebuilt when the config or index changes
+    let context = AppContext::load(config_manager).await?;
+    let _watcher = context.watch()?;
+    
+    let auth = Authenticator::new(token, &context.config().server)?;
+    if !auth.is_enabled() {
+        tracing::warn!("No API tokens configured; the API is open to anyone who can reach it");
+    }
     
     // Start gRPC server on port + 1
     let grpc_port = port + 1;
     let grpc_addr = format!("0.0.0.0:{}", grpc_port).parse()?;
     
-    let grpc_service = grpc::create_grpc_service(grpc_config);
+    let grpc_service = grpc::create_grpc_service(context.clone(), auth.clone());
     
     let grpc_handle = tokio::spawn(async move {
         tracing::info!("gRPC server listening on {}", grpc_addr);
@@ -38,17 +67,9 @@ pub async fn run_server(
     });
 
     // Start HTTP server
-    let app_state = Arc::new(AppState {
-        config_manager: http_config,
-        auth_token: token,
-    });
+    let app_state = Arc::new(AppState { context, auth });
 
-    let app = Router::new()
-        .route("/health", get(health_check))
-        .route("/v1/query", post(query_handler))
-        .route("/v1/index/status", get(index_status_handler))
-        .layer(CorsLayer::permissive())
-        .with_state(app_state);
+    let app = router(app_state);
 
     let addr = format!("0.0.0.0:{}", port);
     let listener = tokio::net::TcpListener::bind(&addr).await?;
@@ -68,10 +89,29 @@ pub async fn run_server(
     Ok(())
 }
 
+fn router(app_state: Arc<AppState>) -> Router {
+    let read = RequireScope {
+        auth: app_state.auth.clone(),
+        scope: Scope::Read,
+    };
+    let api = Router::new()
+        .route("/v1/query", post(query_handler))
+        .route("/v1/query/stream", post(query_stream_handler))
+        .route("/v1/chat", get(chat::chat_handler))
+        .route("/v1/index/status", get(index_status_handler))
+        .route_layer(middleware::from_fn_with_state(read, require_scope));
+    
+    Router::new()
+        .route("/health", get(health_check))
+        .merge(api)
+        .layer(CorsLayer::permissive())
+        .with_state(app_state)
+}
+
 #[derive(Clone)]
 struct AppState {
-    config_manager: ConfigManager,
-    auth_token: Option<String>,
+    context: Arc<AppContext>,
+    auth: Authenticator,
 }
 
 async fn health_check() -> Json<serde_json::Value> {
@@ -82,24 +122,201 @@ async fn health_check() -> Json<serde_json::Value> {
     }))
 }
 
+#[derive(Debug, Deserialize)]
+struct QueryRequest {
+    question: String,
+    #[serde(default = "default_top_k")]
+    top_k: usize,
+    #[serde(default)]
+    repos: Vec<String>,
+    #[serde(default)]
+    languages: Vec<String>,
+}
+
+fn default_top_k() -> usize {
+    10
+}
+
+impl QueryRequest {
+    fn validate(&self) -> Result<(), ApiError> {
+        if self.question.trim().is_empty() {
+            return Err(ApiError::bad_request("question must not be empty"));
+        }
+        if !(1..=MAX_TOP_K).contains(&self.top_k) {
+            return Err(ApiError::bad_request(format!("top_k must be between 1 and {}", MAX_TOP_K)));
+        }
+        Ok(())
+    }
+}
+
 async fn query_handler(
     State(state): State<Arc<AppState>>,
-    Json(payload): Json<serde_json::Value>,
-) -> Result<Json<serde_json::Value>, StatusCode> {
-    Ok(Json(json!({
-        "message": "Query endpoint not yet implemented",
-        "query": payload
-    })))
+    payload: Result<Json<QueryRequest>, JsonRejection>,
+) -> Result<Json<QueryResponse>, ApiError> {
+    let Json(request) = payload?;
+    request.validate()?;
+    
+    let engine = query_engine(&state).await?;
+    let filter = SearchFilter::new(request.repos, request.languages);
+    let response = engine.query(&request.question, request.top_k, &filter).await?;
+    
+    Ok(Json(response))
+}
+
+/// Streams a query as Server-Sent Events: one `references` event, a `token`
+/// event per answer delta, then `done` with the token usage, or `error`.
+async fn query_stream_handler(
+    State(state): State<Arc<AppState>>,
+    payload: Result<Json<QueryRequest>, JsonRejection>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, ApiError> {
+    let Json(request) = payload?;
+    request.validate()?;
+    
+    let engine = query_engine(&state).await?;
+    let filter = SearchFilter::new(request.repos, request.languages);
+    let (references, mut answer) = engine.query_stream(&request.question, request.top_k, &filter).await?;
+    let (tx, rx) = mpsc::unbounded_channel();
+    
+    tokio::spawn(async move {
+        let event = |name: &str, data: serde_json::Value| {
+            Event::default().event(name).json_data(data).unwrap_or_default()
+        };
+        let _ = tx.send(event("references", json!(references)));
+        
+        let mut usage = None;
+        while let Some(chat_event) = answer.next().await {
+            match chat_event {
+                Ok(ChatEvent::Delta(delta)) => {
+                    if tx.send(event("token", json!({ "delta": delta }))).is_err() {
+                        // The client went away.
+                        return;
+                    }
+                }
+                Ok(ChatEvent::Usage(reported)) => usage = Some(reported),
+                Err(e) => {
+                    let _ = tx.send(event("error", json!({ "error": e.to_string() })));
+                    return;
+                }
+            }
+        }
+        let _ = tx.send(event("done", json!({ "usage": usage })));
+    });
+    
+    let stream = UnboundedReceiverStream::new(rx).map(Ok);
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+async fn query_engine(state: &AppState) -> Result<Arc<BuildliQueryEngine>, ApiError> {
+    Ok(state.context.query_engine().await?)
+}
+
+#[derive(Deserialize)]
+struct IndexStatusParams {
+    /// Comma-separated roots to report on; all roots when absent.
+    paths: Option<String>,
 }
 
 async fn index_status_handler(
     State(state): State<Arc<AppState>>,
+    Query(params): Query<IndexStatusParams>,
 ) -> Result<Json<serde_json::Value>, StatusCode> {
+    let collection = state.context.config().vector.index_key();
+    let metadata = IndexMetadata::load(&collection)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let progress = Progress::load(&collection)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .filter(|progress| progress.running);
+    
+    let paths: Vec<PathBuf> = params
+        .paths
+        .iter()
+        .flat_map(|paths| paths.split(','))
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from)
+        .collect();
+    let status = metadata.status(&paths);
+    
     Ok(Json(json!({
-        "status": "ok",
-        "total_files": 0,
-        "indexed_files": 0,
-        "total_chunks": 0,
-        "last_updated": "never"
+        "status": if progress.is_some() { "indexing" } else { "ok" },
+        "total_files": status.total_files,
+        "indexed_files": status.indexed_files,
+        "failed_files": status.failed_files,
+        "total_chunks": status.total_chunks,
+        "last_updated": status.last_updated,
+        "roots": status.roots,
+        "progress": progress,
     })))
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::Request;
+    use tower::ServiceExt;
+
+    async fn post_query(body: &str) -> (StatusCode, serde_json::Value) {
+        let dir = tempfile::tempdir().unwrap();
+        let state = Arc::new(AppState {
+            context: AppContext::load(ConfigManager::at(dir.path().join("config.toml"))).await.unwrap(),
+            auth: Authenticator::new(Some("secret".to_string()), &Default::default()).unwrap(),
+        });
+        let request = Request::post("/v1/query")
+            .header("content-type", "application/json")
+            .header("authorization", "Bearer secret")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+
+        let response = router(state).oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_chat_accepts_token_as_websocket_protocol() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = Arc::new(AppState {
+            context: AppContext::load(ConfigManager::at(dir.path().join("config.toml"))).await.unwrap(),
+            auth: Authenticator::new(Some("secret".to_string()), &Default::default()).unwrap(),
+        });
+        let upgrade = |protocols: &str| {
+            Request::get("/v1/chat")
+                .header("connection", "upgrade")
+                .header("upgrade", "websocket")
+                .header("sec-websocket-version", "13")
+                .header("sec-websocket-key", "dGhlIHNhbXBsZSBub25jZQ==")
+                .header("sec-websocket-protocol", protocols)
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let response = router(state.clone()).oneshot(upgrade("buildli.chat")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        // Past authentication; this in-process request cannot be upgraded.
+        let response = router(state).oneshot(upgrade("buildli.chat, bearer.secret")).await.unwrap();
+        assert_ne!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_query_rejects_bad_requests_with_json_errors() {
+        let (status, body) = post_query(r#"{"question": "  "}"#).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"], "question must not be empty");
+
+        let (status, _) = post_query(r#"{"question": "where is auth?", "top_k": 0}"#).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (status, body) = post_query(r#"{"top_k": 5}"#).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(body["error"].as_str().unwrap().contains("question"));
+
+        // The default config has no API key to answer with.
+        let (status, body) = post_query(r#"{"question": "where is auth?"}"#).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert!(body["error"].as_str().unwrap().contains("llm.api_key"));
+    }
+}
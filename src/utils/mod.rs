@@ -1,6 +1,8 @@
+pub mod tokenizer;
+
 use colored::Colorize;
 use indicatif::{ProgressBar, ProgressStyle};
-use std::time::Duration;
+use std::time::{Duration, UNIX_EPOCH};
 
 pub fn create_progress_bar(total: u64, message: &str) -> ProgressBar {
     let pb = ProgressBar::new(total);
@@ -50,6 +52,14 @@ pub fn format_code_snippet(content: &str, file_path: &str, line_start: usize) ->
     result
 }
 
+/// Formats a Unix time as an HTTP date, or "never" for 0.
+pub fn http_date(unix_secs: u64) -> String {
+    match unix_secs {
+        0 => "never".to_string(),
+        secs => httpdate::fmt_http_date(UNIX_EPOCH + Duration::from_secs(secs)),
+    }
+}
+
 pub fn truncate_string(s: &str, max_len: usize) -> String {
     if s.len() <= max_len {
         s.to_string()
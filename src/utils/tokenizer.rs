@@ -0,0 +1,84 @@
+//! Identifier-aware tokenization shared by the local embedding model and
+//! lexical search. Source code is split on punctuation and whitespace, then
+//! every identifier is broken into its `snake_case` / `camelCase` parts so a
+//! query for "parse config" matches `parseConfig` and `PARSE_CONFIG`.
+
+/// Lowercased terms of `text`. Compound identifiers yield their parts
+/// followed by the whole identifier, e.g. `HttpServer` gives `http`,
+/// `server` and `httpserver`.
+pub fn tokenize(text: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+
+    for identifier in identifiers(text) {
+        let parts = split_identifier(identifier);
+        if parts.len() > 1 {
+            let whole: String = parts.concat();
+            tokens.extend(parts);
+            tokens.push(whole);
+        } else {
+            tokens.extend(parts);
+        }
+    }
+
+    tokens
+}
+
+/// Runs of alphanumeric characters and underscores.
+pub fn identifiers(text: &str) -> impl Iterator<Item = &str> {
+    text.split(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .filter(|word| word.chars().any(|c| c.is_alphanumeric()))
+}
+
+/// Splits an identifier on underscores, lower-to-upper case changes,
+/// acronym boundaries (`HTTPServer` -> `http`, `server`) and letter/digit
+/// boundaries. Parts are lowercased.
+pub fn split_identifier(identifier: &str) -> Vec<String> {
+    let chars: Vec<char> = identifier.chars().collect();
+    let mut parts = Vec::new();
+    let mut current = String::new();
+
+    for (i, &c) in chars.iter().enumerate() {
+        if c == '_' {
+            push_part(&mut parts, &mut current);
+            continue;
+        }
+
+        if let Some(&prev) = i.checked_sub(1).and_then(|p| chars.get(p)) {
+            let next = chars.get(i + 1).copied();
+            let boundary = (prev.is_lowercase() && c.is_uppercase())
+                || (prev.is_uppercase() && c.is_uppercase() && next.is_some_and(|n| n.is_lowercase()))
+                || (prev.is_alphabetic() && c.is_numeric())
+                || (prev.is_numeric() && c.is_alphabetic());
+            if boundary {
+                push_part(&mut parts, &mut current);
+            }
+        }
+
+        current.extend(c.to_lowercase());
+    }
+    push_part(&mut parts, &mut current);
+
+    parts
+}
+
+fn push_part(parts: &mut Vec<String>, current: &mut String) {
+    if !current.is_empty() {
+        parts.push(std::mem::take(current));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_identifier() {
+        assert_eq!(split_identifier("parseConfig"), vec!["parse", "config"]);
+        assert_eq!(split_identifier("HTTPServer2Handler"), vec!["http", "server", "2", "handler"]);
+        assert_eq!(split_identifier("__MAX_RETRY_count"), vec!["max", "retry", "count"]);
+        assert_eq!(
+            tokenize("fn load_file(path)"),
+            vec!["fn", "load", "file", "loadfile", "path"]
+        );
+    }
+}
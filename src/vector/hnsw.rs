@@ -0,0 +1,593 @@
+//! Hierarchical navigable small world graph (Malkov & Yashunin, 2016) for
+//! approximate nearest-neighbor search by cosine similarity.
+//!
+//! Nodes are identified by dense `u32` IDs (vector slots in the local store)
+//! and the graph does not own any vectors: they are looked up through a
+//! [`VectorSource`] on every distance computation, which may score quantized
+//! codes instead of full-precision vectors.
+
+use super::{dot, inverse_norm};
+use crate::config::HnswConfig;
+use anyhow::{bail, Context, Result};
+use std::borrow::Cow;
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+const MAGIC: &[u8; 4] = b"HNSW";
+const FORMAT_VERSION: u32 = 1;
+const NO_ENTRY: u32 = u32::MAX;
+
+pub trait VectorSource {
+    /// A query vector prepared for scoring against many stored vectors.
+    type Query;
+
+    fn prepare(&self, query: &[f32]) -> Self::Query;
+
+    /// Dot product of a prepared query with the vector stored under `id`.
+    fn dot(&self, query: &Self::Query, id: u32) -> Option<f32>;
+
+    /// The vector stored under `id`, decoded if it is stored quantized.
+    fn vector(&self, id: u32) -> Option<Cow<'_, [f32]>>;
+}
+
+impl VectorSource for [Vec<f32>] {
+    type Query = Vec<f32>;
+
+    fn prepare(&self, query: &[f32]) -> Vec<f32> {
+        query.to_vec()
+    }
+
+    fn dot(&self, query: &Vec<f32>, id: u32) -> Option<f32> {
+        Some(dot(query, self.get(id as usize)?))
+    }
+
+    fn vector(&self, id: u32) -> Option<Cow<'_, [f32]>> {
+        self.get(id as usize).map(|vector| Cow::Borrowed(vector.as_slice()))
+    }
+}
+
+pub struct Hnsw {
+    m: usize,
+    ef_construction: usize,
+    /// Per-node neighbor lists, one per level the node appears on.
+    nodes: Vec<Vec<Vec<u32>>>,
+    /// Reciprocal of each node's vector norm, so a cosine distance costs a
+    /// single dot product.
+    inverse_norms: Vec<f32>,
+    entry_point: Option<u32>,
+    /// State of the xorshift generator used to draw node levels, persisted so
+    /// a reloaded graph keeps growing the same way.
+    rng: u64,
+}
+
+impl Hnsw {
+    pub fn new(config: &HnswConfig) -> Self {
+        Self {
+            m: config.m.max(2),
+            ef_construction: config.ef_construction.max(1),
+            nodes: Vec::new(),
+            inverse_norms: Vec::new(),
+            entry_point: None,
+            rng: 0x2545_f491_4f6c_dd1d,
+        }
+    }
+
+    /// Number of nodes inserted so far; the next insert must use this ID.
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn m(&self) -> usize {
+        self.m
+    }
+
+    /// Adds node `id`, which must equal [`len`](Self::len).
+    pub fn insert<S: VectorSource + ?Sized>(&mut self, id: u32, vectors: &S) -> Result<()> {
+        if id as usize != self.nodes.len() {
+            bail!("HNSW nodes must be inserted in order (expected {}, got {})", self.nodes.len(), id);
+        }
+        let vector = vectors.vector(id).context("Missing vector for HNSW node")?;
+        let query = Query::new(&vector, vectors);
+
+        let level = self.random_level();
+        self.nodes.push(vec![Vec::new(); level + 1]);
+        self.inverse_norms.push(query.inverse_norm);
+
+        let Some(entry_point) = self.entry_point else {
+            self.entry_point = Some(id);
+            return Ok(());
+        };
+
+        let top_level = self.nodes[entry_point as usize].len() - 1;
+        let mut current = entry_point;
+        for layer in (level + 1..=top_level).rev() {
+            current = self.greedy_closest(&query, current, layer, vectors);
+        }
+
+        for layer in (0..=level.min(top_level)).rev() {
+            let candidates = self.search_layer(&query, &[current], self.ef_construction, layer, vectors);
+            let neighbors = self.select_neighbors(&candidates, self.max_neighbors(layer), vectors);
+
+            for &neighbor in &neighbors {
+                self.connect(neighbor, id, layer, vectors);
+            }
+            self.nodes[id as usize][layer] = neighbors;
+
+            if let Some(closest) = candidates.first() {
+                current = closest.id;
+            }
+        }
+
+        if level > top_level {
+            self.entry_point = Some(id);
+        }
+
+        Ok(())
+    }
+
+    /// Up to `top_k` nodes closest to `query` among those `accept` returns
+    /// true for, best first, as `(id, cosine similarity)` pairs. Rejected
+    /// nodes are still used to navigate the graph.
+    pub fn search<S, F>(&self, query: &[f32], top_k: usize, ef: usize, vectors: &S, accept: F) -> Vec<(u32, f32)>
+    where
+        S: VectorSource + ?Sized,
+        F: Fn(u32) -> bool,
+    {
+        let Some(entry_point) = self.entry_point else {
+            return vec![];
+        };
+        let query = Query::new(query, vectors);
+
+        let mut current = entry_point;
+        for layer in (1..self.nodes[entry_point as usize].len()).rev() {
+            current = self.greedy_closest(&query, current, layer, vectors);
+        }
+
+        self.search_layer(&query, &[current], ef.max(top_k), 0, vectors)
+            .into_iter()
+            .filter(|candidate| accept(candidate.id))
+            .take(top_k)
+            .map(|candidate| (candidate.id, 1.0 - candidate.distance))
+            .collect()
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let mut data = Vec::new();
+        data.extend_from_slice(MAGIC);
+        for value in [
+            FORMAT_VERSION,
+            self.m as u32,
+            self.ef_construction as u32,
+            self.entry_point.unwrap_or(NO_ENTRY),
+            self.nodes.len() as u32,
+        ] {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        data.extend_from_slice(&self.rng.to_le_bytes());
+
+        for (levels, inverse_norm) in self.nodes.iter().zip(&self.inverse_norms) {
+            data.extend_from_slice(&inverse_norm.to_le_bytes());
+            data.push(levels.len() as u8);
+            for neighbors in levels {
+                data.extend_from_slice(&(neighbors.len() as u32).to_le_bytes());
+                for neighbor in neighbors {
+                    data.extend_from_slice(&neighbor.to_le_bytes());
+                }
+            }
+        }
+
+        let tmp_path = path.with_extension("tmp");
+        let mut file = fs::File::create(&tmp_path)?;
+        file.write_all(&data)?;
+        file.sync_data()?;
+        fs::rename(&tmp_path, path)?;
+
+        Ok(())
+    }
+
+    /// Loads a graph saved with [`save`](Self::save). Returns `None` if the
+    /// file is missing, unreadable, corrupt or was built with a different
+    /// `m`, in which case the caller should rebuild it. The configured
+    /// `ef_construction` replaces the saved one, since it only affects
+    /// future inserts.
+    pub fn load(path: &Path, config: &HnswConfig) -> Option<Self> {
+        let data = fs::read(path).ok()?;
+        let mut reader = Reader { data: &data, pos: 0 };
+
+        if reader.take(4)? != MAGIC || reader.u32()? != FORMAT_VERSION {
+            return None;
+        }
+        let m = reader.u32()? as usize;
+        // Saved for reference; the configured value applies from here on.
+        let _ef_construction = reader.u32()?;
+        let entry_point = reader.u32()?;
+        let node_count = reader.u32()? as usize;
+        let rng = u64::from_le_bytes(reader.take(8)?.try_into().ok()?);
+
+        if m != config.m.max(2) {
+            return None;
+        }
+
+        // Every node takes at least 9 bytes, which bounds what a corrupt count can allocate.
+        let mut nodes = Vec::with_capacity(node_count.min(data.len() / 9));
+        let mut inverse_norms = Vec::with_capacity(node_count.min(data.len() / 9));
+        for _ in 0..node_count {
+            inverse_norms.push(f32::from_le_bytes(reader.take(4)?.try_into().ok()?));
+            let level_count = reader.take(1)?[0] as usize;
+            let mut levels = Vec::with_capacity(level_count);
+            for _ in 0..level_count {
+                let count = reader.u32()? as usize;
+                let neighbors = (0..count).map(|_| reader.u32()).collect::<Option<Vec<u32>>>()?;
+                levels.push(neighbors);
+            }
+            nodes.push(levels);
+        }
+
+        // Search indexes straight into `nodes`, so links must stay inside the graph.
+        let valid_entry = match entry_point {
+            NO_ENTRY => node_count == 0,
+            entry_point => (entry_point as usize) < node_count,
+        };
+        let valid_links = nodes.iter().all(|levels| {
+            !levels.is_empty()
+                && levels.iter().enumerate().all(|(layer, neighbors)| {
+                    neighbors
+                        .iter()
+                        .all(|&neighbor| nodes.get(neighbor as usize).is_some_and(|levels| levels.len() > layer))
+                })
+        });
+        if !valid_entry || !valid_links {
+            return None;
+        }
+
+        Some(Self {
+            m,
+            ef_construction: config.ef_construction.max(1),
+            nodes,
+            inverse_norms,
+            entry_point: (entry_point != NO_ENTRY).then_some(entry_point),
+            rng,
+        })
+    }
+
+    fn max_neighbors(&self, layer: usize) -> usize {
+        if layer == 0 {
+            self.m * 2
+        } else {
+            self.m
+        }
+    }
+
+    fn random_level(&mut self) -> usize {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+
+        // Uniform in (0, 1]; level = floor(-ln(u) / ln(m)).
+        let uniform = ((self.rng >> 11) as f64 + 1.0) / (1u64 << 53) as f64;
+        ((-uniform.ln()) / (self.m as f64).ln()).floor().min(32.0) as usize
+    }
+
+    fn distance<S: VectorSource + ?Sized>(&self, query: &Query<S::Query>, id: u32, vectors: &S) -> f32 {
+        match vectors.dot(&query.prepared, id) {
+            Some(dot) => 1.0 - dot * query.inverse_norm * self.inverse_norms[id as usize],
+            None => f32::MAX,
+        }
+    }
+
+    /// Distance between a stored vector and node `id`. Used while linking
+    /// nodes, where preparing every vector as a query would cost more than
+    /// decoding the other one.
+    fn node_distance<S: VectorSource + ?Sized>(&self, vector: &[f32], inverse_norm: f32, id: u32, vectors: &S) -> f32 {
+        match vectors.vector(id) {
+            Some(other) => 1.0 - dot(vector, &other) * inverse_norm * self.inverse_norms[id as usize],
+            None => f32::MAX,
+        }
+    }
+
+    fn greedy_closest<S: VectorSource + ?Sized>(&self, query: &Query<S::Query>, start: u32, layer: usize, vectors: &S) -> u32 {
+        let mut current = start;
+        let mut current_distance = self.distance(query, current, vectors);
+
+        loop {
+            let mut improved = false;
+            for &neighbor in &self.nodes[current as usize][layer] {
+                let distance = self.distance(query, neighbor, vectors);
+                if distance < current_distance {
+                    current = neighbor;
+                    current_distance = distance;
+                    improved = true;
+                }
+            }
+            if !improved {
+                return current;
+            }
+        }
+    }
+
+    /// The `ef` closest nodes reachable from `entry_points` on `layer`, closest first.
+    fn search_layer<S: VectorSource + ?Sized>(
+        &self,
+        query: &Query<S::Query>,
+        entry_points: &[u32],
+        ef: usize,
+        layer: usize,
+        vectors: &S,
+    ) -> Vec<Candidate> {
+        let mut visited = VisitedSet::new(self.nodes.len());
+        for &id in entry_points {
+            visited.insert(id);
+        }
+        let mut candidates: BinaryHeap<Reverse<Candidate>> = BinaryHeap::new();
+        let mut results: BinaryHeap<Candidate> = BinaryHeap::new();
+
+        for &id in entry_points {
+            let candidate = Candidate {
+                id,
+                distance: self.distance(query, id, vectors),
+            };
+            candidates.push(Reverse(candidate));
+            results.push(candidate);
+        }
+
+        while let Some(Reverse(candidate)) = candidates.pop() {
+            let furthest = results.peek().map(|c| c.distance).unwrap_or(f32::MAX);
+            if candidate.distance > furthest && results.len() >= ef {
+                break;
+            }
+
+            let Some(neighbors) = self.nodes[candidate.id as usize].get(layer) else {
+                continue;
+            };
+            for &neighbor in neighbors {
+                if !visited.insert(neighbor) {
+                    continue;
+                }
+
+                let distance = self.distance(query, neighbor, vectors);
+                let furthest = results.peek().map(|c| c.distance).unwrap_or(f32::MAX);
+                if results.len() < ef || distance < furthest {
+                    let next = Candidate { id: neighbor, distance };
+                    candidates.push(Reverse(next));
+                    results.push(next);
+                    if results.len() > ef {
+                        results.pop();
+                    }
+                }
+            }
+        }
+
+        results.into_sorted_vec()
+    }
+
+    /// Neighbor selection heuristic from the paper: a candidate is kept only
+    /// if it is closer to the new node than to every neighbor kept so far,
+    /// which favours links in different directions over clusters.
+    fn select_neighbors<S: VectorSource + ?Sized>(&self, candidates: &[Candidate], max: usize, vectors: &S) -> Vec<u32> {
+        let mut selected: Vec<Candidate> = Vec::with_capacity(max);
+        let mut pruned = Vec::new();
+
+        for &candidate in candidates {
+            if selected.len() >= max {
+                break;
+            }
+            let Some(vector) = vectors.vector(candidate.id) else {
+                continue;
+            };
+            let inverse_norm = self.inverse_norms[candidate.id as usize];
+
+            let diverse = selected
+                .iter()
+                .all(|kept| self.node_distance(&vector, inverse_norm, kept.id, vectors) > candidate.distance);
+            if diverse {
+                selected.push(candidate);
+            } else {
+                pruned.push(candidate);
+            }
+        }
+
+        // Fill up with the closest pruned candidates to keep the graph well connected.
+        for candidate in pruned {
+            if selected.len() >= max {
+                break;
+            }
+            selected.push(candidate);
+        }
+
+        selected.into_iter().map(|c| c.id).collect()
+    }
+
+    fn connect<S: VectorSource + ?Sized>(&mut self, node: u32, neighbor: u32, layer: usize, vectors: &S) {
+        let max = self.max_neighbors(layer);
+        let links = &mut self.nodes[node as usize][layer];
+        links.push(neighbor);
+        if links.len() <= max {
+            return;
+        }
+
+        let Some(vector) = vectors.vector(node) else {
+            return;
+        };
+        let inverse_norm = self.inverse_norms[node as usize];
+        let mut candidates: Vec<Candidate> = self.nodes[node as usize][layer]
+            .iter()
+            .map(|&id| Candidate {
+                id,
+                distance: self.node_distance(&vector, inverse_norm, id, vectors),
+            })
+            .collect();
+        candidates.sort();
+
+        let selected = self.select_neighbors(&candidates, max, vectors);
+        self.nodes[node as usize][layer] = selected;
+    }
+}
+
+/// Bitset of visited nodes; much cheaper than hashing for dense IDs.
+struct VisitedSet {
+    words: Vec<u64>,
+}
+
+impl VisitedSet {
+    fn new(len: usize) -> Self {
+        Self {
+            words: vec![0; len.div_ceil(64)],
+        }
+    }
+
+    /// Marks `id` as visited, returning whether it was not visited before.
+    fn insert(&mut self, id: u32) -> bool {
+        let (word, bit) = (id as usize / 64, id % 64);
+        let Some(word) = self.words.get_mut(word) else {
+            return false;
+        };
+        let was_set = *word & (1 << bit) != 0;
+        *word |= 1 << bit;
+        !was_set
+    }
+}
+
+struct Query<Q> {
+    prepared: Q,
+    inverse_norm: f32,
+}
+
+impl<Q> Query<Q> {
+    fn new<S: VectorSource<Query = Q> + ?Sized>(vector: &[f32], vectors: &S) -> Self {
+        Self {
+            prepared: vectors.prepare(vector),
+            inverse_norm: inverse_norm(vector),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Candidate {
+    id: u32,
+    distance: f32,
+}
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.distance
+            .total_cmp(&other.distance)
+            .then_with(|| self.id.cmp(&other.id))
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, len: usize) -> Option<&[u8]> {
+        let bytes = self.data.get(self.pos..self.pos.checked_add(len)?)?;
+        self.pos += len;
+        Some(bytes)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.take(4)?.try_into().ok()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_finds_exact_match_and_round_trips() {
+        let vectors: Vec<Vec<f32>> = (0..500)
+            .map(|i| {
+                let angle = i as f32 * 0.0125;
+                vec![angle.cos(), angle.sin(), (i % 7) as f32 * 0.01]
+            })
+            .collect();
+
+        let config = HnswConfig::default();
+        let mut graph = Hnsw::new(&config);
+        for id in 0..vectors.len() as u32 {
+            graph.insert(id, vectors.as_slice()).unwrap();
+        }
+
+        let results = graph.search(&vectors[123], 5, 32, vectors.as_slice(), |_| true);
+        assert_eq!(results[0].0, 123);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("graph.bin");
+        graph.save(&path).unwrap();
+        let loaded = Hnsw::load(&path, &config).unwrap();
+        assert_eq!(loaded.len(), 500);
+
+        let results = loaded.search(&vectors[321], 5, 32, vectors.as_slice(), |id| id != 321);
+        assert!(results.iter().all(|(id, _)| *id != 321));
+        assert_eq!(results.len(), 5);
+
+        let tuned = HnswConfig {
+            ef_construction: config.ef_construction * 2,
+            ..config.clone()
+        };
+        assert_eq!(Hnsw::load(&path, &tuned).unwrap().ef_construction, tuned.ef_construction);
+    }
+
+    #[test]
+    fn test_rejects_corrupt_graph() {
+        let vectors: Vec<Vec<f32>> = (0..50).map(|i| vec![1.0, i as f32]).collect();
+        let config = HnswConfig::default();
+        let mut graph = Hnsw::new(&config);
+        for id in 0..vectors.len() as u32 {
+            graph.insert(id, vectors.as_slice()).unwrap();
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("graph.bin");
+        graph.save(&path).unwrap();
+        let data = fs::read(&path).unwrap();
+        let load = |data: &[u8]| {
+            fs::write(&path, data).unwrap();
+            Hnsw::load(&path, &config)
+        };
+        assert!(load(&data).is_some());
+
+        // Header, then node 0: inverse norm, level count, neighbor count, first neighbor.
+        let level_count = 32 + 4;
+        let first_neighbor = level_count + 1 + 4;
+        assert!(u32::from_le_bytes(data[level_count + 1..first_neighbor].try_into().unwrap()) > 0);
+
+        let mut corrupt = data.clone();
+        corrupt[first_neighbor..first_neighbor + 4].copy_from_slice(&50u32.to_le_bytes());
+        assert!(load(&corrupt).is_none());
+
+        let mut corrupt = data.clone();
+        corrupt[level_count] = 0;
+        assert!(load(&corrupt).is_none());
+
+        let mut corrupt = data.clone();
+        corrupt[16..20].copy_from_slice(&50u32.to_le_bytes());
+        assert!(load(&corrupt).is_none());
+
+        assert!(load(&data[..data.len() - 3]).is_none());
+    }
+}
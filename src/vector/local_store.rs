@@ -1,14 +1,55 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
 use std::collections::HashMap;
+use std::fs::{self, File, TryLockError};
 use std::path::{Path, PathBuf};
-use tokio::fs;
-use tokio::sync::RwLock;
+use tokio::sync::{RwLock, RwLockWriteGuard};
+use tracing::{info, warn};
 
-use super::{cosine_similarity, Document, SearchResult, VectorStore};
+use super::hnsw::{Hnsw, VectorSource};
+use super::quantization::{QuantizationMethod, QuantizedQuery, Quantizer, MAX_TRAINING_VECTORS};
+use super::segment::{self, PutRecord, Record, SegmentLog, SlotFile, VectorFile};
+use super::{
+    chunk_id, chunk_metadata, cosine_similarity, dot, CollectionSchema, Document, SearchFilter, SearchResult,
+    VectorStore,
+};
+use crate::config::{HnswConfig, QuantizationConfig, VectorConfig};
 use crate::indexer::parser::CodeChunk;
 
+const STORE_VERSION: u32 = 1;
+const META_FILE: &str = "meta.json";
+
+/// Held locked by the one process allowed to write the store.
+const LOCK_FILE: &str = "write.lock";
+
+/// Maps collection names to the directories holding them, once a migration
+/// has moved a collection to a new directory.
+const ALIASES_FILE: &str = "aliases.json";
+
+/// Compaction runs once at least this many vector slots are unreferenced and
+/// they outnumber the live ones.
+const COMPACTION_MIN_DEAD_SLOTS: u32 = 1024;
+
+/// Up to this many documents an exact scan is as fast as the HNSW graph.
+const BRUTE_FORCE_MAX_DOCUMENTS: usize = 2048;
+
+/// The HNSW graph is checkpointed after this many inserts, or after a tenth
+/// of its size when that is larger.
+const HNSW_CHECKPOINT_INTERVAL: usize = 10_000;
+
+/// With quantization configured, vectors are stored in full precision until
+/// there are enough of them to train the quantizer on.
+const QUANTIZATION_MIN_VECTORS: usize = 2048;
+
+/// Quantized scores are approximate, so this many candidates per result are
+/// re-ranked exactly when full-precision vectors are kept.
+const RERANK_CANDIDATES_PER_RESULT: usize = 4;
+
+/// Single-file JSON format used before the binary store.
+const LEGACY_STORE_FILE: &str = "local_vector_store.json";
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct StoredDocument {
     id: String,
@@ -16,128 +57,1041 @@ struct StoredDocument {
     metadata: HashMap<String, serde_json::Value>,
 }
 
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct StoreMeta {
+    version: u32,
+    generation: u64,
+    /// Set by the first upsert; every vector in the store has this length.
+    dimension: Option<usize>,
+    /// Embedding model the vectors come from, recorded by `initialize`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    model: Option<String>,
+    /// Encoding of the current generation's codes file, if any.
+    #[serde(default)]
+    quantization: QuantizationMethod,
+    /// Set when full-precision vectors were dropped after quantizing.
+    #[serde(default)]
+    quantized_only: bool,
+}
+
+/// Disk-backed vector store for use without a Qdrant server. See
+/// [`segment`](super::segment) for the file format. Writes append to the
+/// segment log and vector file, so indexing is linear in the number of chunks,
+/// and searches go through an HNSW graph once the store is large enough.
+/// Vectors can be stored quantized; see [`quantization`](super::quantization).
+///
+/// One process at a time may write a store. Stores opened read-only take
+/// the writer lock on their first write instead.
 pub struct PersistentLocalVectorStore {
-    documents: RwLock<Vec<StoredDocument>>,
-    store_path: PathBuf,
+    inner: RwLock<StoreInner>,
+}
+
+struct StoreInner {
+    dir: PathBuf,
+    /// The locked writer lock file, or `None` while the store is read-only.
+    lock: Option<File>,
+    meta: StoreMeta,
+    log: SegmentLog,
+    storage: Option<VectorStorage>,
+    documents: HashMap<String, PutRecord>,
+    /// ID of the live document stored in each slot.
+    slot_ids: HashMap<u32, String>,
+    hnsw: Hnsw,
+    hnsw_config: HnswConfig,
+    /// Graph nodes added since the last checkpoint.
+    hnsw_unsaved: usize,
+    quantization: QuantizationConfig,
+    quantization_method: QuantizationMethod,
+}
+
+/// Vector files of the current generation. At least one of `raw` and
+/// `quantized` is present, and both hold the same slots.
+struct VectorStorage {
+    raw: Option<VectorFile>,
+    quantized: Option<QuantizedVectors>,
+}
+
+struct QuantizedVectors {
+    quantizer: Quantizer,
+    codes: SlotFile,
+}
+
+enum StorageQuery {
+    Raw(Vec<f32>),
+    Quantized(QuantizedQuery),
 }
 
 impl PersistentLocalVectorStore {
-    pub async fn new() -> Result<Self> {
-        let project_dirs = directories::ProjectDirs::from("", "", "buildli")
-            .ok_or_else(|| anyhow::anyhow!("Failed to determine project directories"))?;
-        
-        let data_dir = project_dirs.data_dir();
-        let store_path = data_dir.join("local_vector_store.json");
-        
-        // Create directory if it doesn't exist
-        if let Some(parent) = store_path.parent() {
-            fs::create_dir_all(parent).await?;
-        }
-        
-        let mut store = Self {
-            documents: RwLock::new(Vec::new()),
-            store_path,
-        };
-        
-        // Load existing data if available
-        store.load().await?;
-        
+    /// Opens the store for the configured collection in the data directory,
+    /// importing the legacy JSON store on first use.
+    pub async fn new(config: &VectorConfig) -> Result<Self> {
+        let data_dir = crate::config::data_dir()?;
+        let dir = collection_dir(&data_dir.join("local_store"), &config.collection_name)?;
+        let is_new = !dir.join(META_FILE).exists();
+
+        let store = Self::open(dir, config).await?;
+
+        let legacy_path = data_dir.join(LEGACY_STORE_FILE);
+        if is_new && legacy_path.exists() {
+            store.import_legacy(&legacy_path).await?;
+        }
+
         Ok(store)
     }
-    
-    async fn load(&mut self) -> Result<()> {
-        if self.store_path.exists() {
-            let data = fs::read_to_string(&self.store_path).await?;
-            let documents: Vec<StoredDocument> = serde_json::from_str(&data)?;
-            *self.documents.write().await = documents;
+
+    /// Opens the store for the configured collection without taking the
+    /// writer lock, for processes that mostly search it while another one
+    /// indexes.
+    pub async fn new_read_only(config: &VectorConfig) -> Result<Self> {
+        let dir = collection_dir(&crate::config::data_dir()?.join("local_store"), &config.collection_name)?;
+        Self::open_read_only(dir, config).await
+    }
+
+    /// Opens (or creates) a store in `dir` for writing. Fails if another
+    /// process is writing it. Only the HNSW and quantization settings of
+    /// `config` are used.
+    pub async fn open(dir: PathBuf, config: &VectorConfig) -> Result<Self> {
+        fs::create_dir_all(&dir)?;
+        let lock = lock_writer(&dir)?;
+        Ok(Self {
+            inner: RwLock::new(StoreInner::open(dir, &config.hnsw, &config.quantization, Some(lock))?),
+        })
+    }
+
+    /// Opens a store in `dir` without the writer lock. A write takes the lock
+    /// and reloads the store from disk first.
+    pub async fn open_read_only(dir: PathBuf, config: &VectorConfig) -> Result<Self> {
+        Ok(Self {
+            inner: RwLock::new(StoreInner::open(dir, &config.hnsw, &config.quantization, None)?),
+        })
+    }
+
+    pub async fn len(&self) -> usize {
+        self.inner.read().await.documents.len()
+    }
+
+    pub async fn is_empty(&self) -> bool {
+        self.len().await == 0
+    }
+
+    /// Rewrites the store without deleted and replaced vectors.
+    pub async fn compact(&self) -> Result<()> {
+        self.write().await?.compact()
+    }
+
+    /// Re-encodes the stored vectors with `quantization` in place. An empty
+    /// store is quantized once enough vectors have been added.
+    pub async fn convert(&self, quantization: &QuantizationConfig) -> Result<()> {
+        let method = QuantizationMethod::parse(&quantization.method)?;
+
+        let mut inner = self.write().await?;
+        inner.quantization = quantization.clone();
+        inner.quantization_method = method;
+        if inner.documents.is_empty() {
+            return Ok(());
+        }
+        inner.quantize()
+    }
+
+    /// Bytes of disk space one vector takes up, or zero for an empty store.
+    pub async fn bytes_per_vector(&self) -> usize {
+        self.inner
+            .read()
+            .await
+            .storage
+            .as_ref()
+            .map(VectorStorage::bytes_per_vector)
+            .unwrap_or(0)
+    }
+
+    /// Points `collection` at the directory of collection `target` and
+    /// deletes the directory it used before. Neither store may be open.
+    pub fn promote(collection: &str, target: &str) -> Result<()> {
+        let root = crate::config::data_dir()?.join("local_store");
+        let previous = collection_dir(&root, collection)?;
+        let target_dir = collection_dir(&root, target)?;
+        if previous == target_dir {
+            return Ok(());
+        }
+
+        let mut aliases = read_aliases(&root)?;
+        aliases.insert(collection.to_string(), sanitize(target));
+        let tmp_path = root.join(format!("{}.tmp", ALIASES_FILE));
+        fs::write(&tmp_path, serde_json::to_vec_pretty(&aliases)?)?;
+        fs::rename(&tmp_path, root.join(ALIASES_FILE))?;
+
+        if previous.exists() {
+            fs::remove_dir_all(&previous)?;
         }
         Ok(())
     }
-    
-    async fn save(&self) -> Result<()> {
-        let documents = self.documents.read().await;
-        let data = serde_json::to_string_pretty(&*documents)?;
-        fs::write(&self.store_path, data).await?;
+
+    /// Locks the store for writing, first taking the writer lock if it was
+    /// opened read-only. Other writers may have changed the store since, so it
+    /// is reloaded then.
+    async fn write(&self) -> Result<RwLockWriteGuard<'_, StoreInner>> {
+        let mut inner = self.inner.write().await;
+        if inner.lock.is_none() {
+            let lock = lock_writer(&inner.dir)?;
+            let reopened = StoreInner::open(inner.dir.clone(), &inner.hnsw_config, &inner.quantization, Some(lock))?;
+            *inner = reopened;
+        }
+        Ok(inner)
+    }
+
+    async fn import_legacy(&self, legacy_path: &Path) -> Result<()> {
+        let documents: Vec<StoredDocument> = serde_json::from_slice(&fs::read(legacy_path)?)
+            .context("Failed to parse legacy local vector store")?;
+        info!("Importing {} documents from {}", documents.len(), legacy_path.display());
+
+        self.upsert_documents(
+            documents
+                .into_iter()
+                .map(|doc| Document {
+                    id: doc.id,
+                    embedding: doc.embedding,
+                    metadata: doc.metadata,
+                })
+                .collect(),
+        )
+        .await?;
+
+        fs::rename(legacy_path, legacy_path.with_extension("json.migrated"))?;
         Ok(())
     }
 }
 
-#[async_trait]
-impl VectorStore for PersistentLocalVectorStore {
-    async fn initialize(&self, _collection_name: &str, _vector_size: usize) -> Result<()> {
+impl Drop for PersistentLocalVectorStore {
+    fn drop(&mut self) {
+        let inner = self.inner.get_mut();
+        if inner.lock.is_some() && inner.hnsw_unsaved > 0 {
+            if let Err(e) = inner.save_hnsw() {
+                warn!("Failed to save HNSW index: {}", e);
+            }
+        }
+    }
+}
+
+impl StoreInner {
+    /// Loads the store in `dir`. With `lock`, the store is writable and
+    /// repaired after a crash; without, nothing on disk is changed.
+    fn open(dir: PathBuf, hnsw_config: &HnswConfig, quantization: &QuantizationConfig, lock: Option<File>) -> Result<Self> {
+        let quantization_method = QuantizationMethod::parse(&quantization.method)?;
+        let read_only = lock.is_none();
+
+        let meta_path = dir.join(META_FILE);
+        let meta = if meta_path.exists() {
+            let meta: StoreMeta = serde_json::from_slice(&fs::read(&meta_path)?)
+                .context("Failed to parse local store metadata")?;
+            if meta.version != STORE_VERSION {
+                anyhow::bail!("Unsupported local store version {} in {}", meta.version, dir.display());
+            }
+            meta
+        } else {
+            // Creating an empty store cannot clash with a writer.
+            fs::create_dir_all(&dir)?;
+            let meta = StoreMeta {
+                version: STORE_VERSION,
+                ..Default::default()
+            };
+            write_meta(&dir, &meta)?;
+            File::options().create(true).append(true).open(segment::log_path(&dir, meta.generation))?;
+            meta
+        };
+
+        let (log, records) = SegmentLog::open(&segment::log_path(&dir, meta.generation), read_only)?;
+        let storage = match meta.dimension {
+            Some(dimension) => {
+                let quantizer = match meta.quantization {
+                    QuantizationMethod::None => None,
+                    _ => Some(Quantizer::load(&segment::quantizer_path(&dir, meta.generation))?),
+                };
+                let keep_raw = !meta.quantized_only;
+                Some(VectorStorage::open(&dir, meta.generation, dimension, quantizer, keep_raw, read_only)?)
+            }
+            None => None,
+        };
+        let slots = storage.as_ref().map(VectorStorage::slots).unwrap_or(0);
+
+        if meta.quantization != QuantizationMethod::None && meta.quantization != quantization_method {
+            warn!(
+                "Local store in {} is quantized with {:?} but {:?} is configured; run `buildli convert-store` to convert it",
+                dir.display(),
+                meta.quantization,
+                quantization_method
+            );
+        }
+
+        let mut documents = HashMap::new();
+        for record in records {
+            match record {
+                // A record whose vector never made it to disk is dropped.
+                Record::Put(put) if put.slot < slots => {
+                    documents.insert(put.id.clone(), put);
+                }
+                Record::Put(_) => {}
+                Record::Delete(id) => {
+                    documents.remove(&id);
+                }
+            }
+        }
+        if !read_only {
+            segment::remove_stale_generations(&dir, meta.generation)?;
+        }
+
+        let slot_ids = documents.values().map(|record| (record.slot, record.id.clone())).collect();
+        // A checkpoint covering vectors that were lost in a crash is unusable.
+        let hnsw = Hnsw::load(&segment::hnsw_path(&dir, meta.generation), hnsw_config)
+            .filter(|graph| graph.len() <= slots as usize)
+            .unwrap_or_else(|| Hnsw::new(hnsw_config));
+
+        let mut inner = StoreInner {
+            dir,
+            lock,
+            meta,
+            log,
+            storage,
+            documents,
+            slot_ids,
+            hnsw,
+            hnsw_config: hnsw_config.clone(),
+            hnsw_unsaved: 0,
+            quantization: quantization.clone(),
+            quantization_method,
+        };
+        if slots as usize > inner.hnsw.len() + HNSW_CHECKPOINT_INTERVAL {
+            info!("Building HNSW index for {} vectors", slots as usize - inner.hnsw.len());
+        }
+        inner.index_new_slots()?;
+
+        Ok(inner)
+    }
+
+    /// Adds every slot the graph does not cover yet.
+    fn index_new_slots(&mut self) -> Result<()> {
+        let Some(storage) = &self.storage else {
+            return Ok(());
+        };
+
+        let start = self.hnsw.len() as u32;
+        for slot in start..storage.slots() {
+            self.hnsw.insert(slot, storage)?;
+        }
+        self.hnsw_unsaved += (storage.slots() - start) as usize;
+
+        if self.lock.is_some() && self.hnsw_unsaved >= HNSW_CHECKPOINT_INTERVAL.max(self.hnsw.len() / 10) {
+            self.save_hnsw()?;
+        }
         Ok(())
     }
 
-    async fn upsert_documents(&self, documents: Vec<Document>) -> Result<()> {
-        let mut store = self.documents.write().await;
-        
-        for doc in documents {
-            // Remove existing document with same ID if any
-            store.retain(|d| d.id != doc.id);
-            
-            // Add new document
-            store.push(StoredDocument {
-                id: doc.id,
-                embedding: doc.embedding,
-                metadata: doc.metadata,
-            });
-        }
-        
-        drop(store); // Release the lock before saving
-        self.save().await?;
+    fn save_hnsw(&mut self) -> Result<()> {
+        self.hnsw.save(&segment::hnsw_path(&self.dir, self.meta.generation))?;
+        self.hnsw_unsaved = 0;
         Ok(())
     }
 
-    async fn search(&self, query_vector: Vec<f32>, top_k: usize) -> Result<Vec<SearchResult>> {
-        let store = self.documents.read().await;
-        let mut results: Vec<(f32, &StoredDocument)> = store
-            .iter()
-            .map(|doc| {
-                let score = cosine_similarity(&query_vector, &doc.embedding);
-                (score, doc)
+    fn search_graph(&self, storage: &VectorStorage, query: &[f32], top_k: usize, filter: &SearchFilter) -> Vec<(f32, &PutRecord)> {
+        // Rejected nodes still count against `ef`, so widen it for filtered searches.
+        let ef = if filter.is_empty() {
+            self.hnsw_config.ef_search
+        } else {
+            self.hnsw_config.ef_search * 4
+        };
+        let record = |slot: u32| self.slot_ids.get(&slot).and_then(|id| self.documents.get(id));
+
+        self.hnsw
+            .search(query, top_k, ef, storage, |slot| {
+                record(slot).is_some_and(|r| filter.accepts(r.repo.as_deref(), Some(&r.language)))
             })
+            .into_iter()
+            .filter_map(|(slot, score)| Some((score, record(slot)?)))
+            .collect()
+    }
+
+    /// Exact (or, for quantized vectors, approximate) scan of every document.
+    fn scan(&self, storage: &VectorStorage, query: &[f32], top_k: usize, filter: &SearchFilter) -> Vec<(f32, &PutRecord)> {
+        let query = storage.prepare(query);
+        let mut results: Vec<(f32, &PutRecord)> = self
+            .documents
+            .values()
+            .filter(|record| filter.accepts(record.repo.as_deref(), Some(&record.language)))
+            .filter_map(|record| Some((storage.similarity(&query, record.slot)?, record)))
             .collect();
-        
-        results.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap());
-        
-        Ok(results
+
+        results.sort_by(|a, b| b.0.total_cmp(&a.0));
+        results.truncate(top_k);
+        results
+    }
+
+    fn dead_slots(&self) -> u32 {
+        let slots = self.storage.as_ref().map(VectorStorage::slots).unwrap_or(0);
+        slots.saturating_sub(self.documents.len() as u32)
+    }
+
+    fn maybe_compact(&mut self) -> Result<()> {
+        let dead = self.dead_slots();
+        if dead >= COMPACTION_MIN_DEAD_SLOTS && dead as usize > self.documents.len() {
+            return self.compact();
+        }
+
+        let unquantized = self.meta.quantization == QuantizationMethod::None;
+        if unquantized
+            && self.quantization_method != QuantizationMethod::None
+            && self.documents.len() >= QUANTIZATION_MIN_VECTORS
+        {
+            return self.quantize();
+        }
+        Ok(())
+    }
+
+    fn compact(&mut self) -> Result<()> {
+        info!("Compacting local vector store ({} dead vectors)", self.dead_slots());
+        let quantizer = self
+            .storage
+            .as_ref()
+            .and_then(|storage| storage.quantized.as_ref())
+            .map(|quantized| quantized.quantizer.clone());
+        self.rewrite(quantizer, !self.meta.quantized_only)
+    }
+
+    /// Rewrites the store with a quantizer freshly trained for the configured method.
+    fn quantize(&mut self) -> Result<()> {
+        let method = self.quantization_method;
+        if method == QuantizationMethod::None {
+            info!("Storing local vectors in full precision");
+            return self.rewrite(None, true);
+        }
+
+        let storage = self.storage.as_ref().context("Local store has no vectors")?;
+        let mut slots: Vec<u32> = self.documents.values().map(|record| record.slot).collect();
+        slots.sort_unstable();
+        let step = slots.len().div_ceil(MAX_TRAINING_VECTORS).max(1);
+        let samples: Vec<Cow<[f32]>> = slots.iter().step_by(step).filter_map(|&slot| storage.vector(slot)).collect();
+        let samples: Vec<&[f32]> = samples.iter().map(|sample| sample.as_ref()).collect();
+
+        info!("Training {:?} quantizer on {} vectors", method, samples.len());
+        let quantizer = Quantizer::train(method, self.quantization.pq_subvectors, &samples)?;
+        self.rewrite(Some(quantizer), self.quantization.rerank)
+    }
+
+    /// Writes the live documents into the next generation, encoded with
+    /// `quantizer` and, if `keep_raw` is set, in full precision as well.
+    fn rewrite(&mut self, quantizer: Option<Quantizer>, keep_raw: bool) -> Result<()> {
+        let Some(dimension) = self.meta.dimension else {
+            return Ok(());
+        };
+        let old = self.storage.as_ref().context("Local store has no vectors")?;
+        let generation = self.meta.generation + 1;
+
+        // Leftovers from an interrupted rewrite.
+        segment::remove_generation(&self.dir, generation)?;
+
+        let keep_raw = keep_raw || quantizer.is_none();
+        if keep_raw && old.raw.is_none() {
+            warn!("Restoring full-precision vectors from quantized ones; re-index for exact vectors");
+        }
+        let quantization = quantizer.as_ref().map(Quantizer::method).unwrap_or_default();
+        let copy_codes = match (&quantizer, &old.quantized) {
+            (Some(new), Some(old)) => *new == old.quantizer,
+            _ => false,
+        };
+
+        let mut storage = VectorStorage::create(&self.dir, generation, dimension, quantizer, keep_raw)?;
+        let (mut log, _) = SegmentLog::open(&segment::log_path(&self.dir, generation), false)?;
+        let mut documents = HashMap::with_capacity(self.documents.len());
+
+        let mut live: Vec<&PutRecord> = self.documents.values().collect();
+        live.sort_by_key(|record| record.slot);
+
+        for record in live {
+            let slot = storage.copy_from(old, record.slot, copy_codes)?;
+            let metadata = self.log.read_metadata(record)?;
+            let put = log.put(
+                &record.id,
+                slot,
+                &record.file_path,
+                record.repo.as_deref(),
+                &record.language,
+                &metadata,
+            )?;
+            documents.insert(put.id.clone(), put);
+        }
+
+        storage.sync()?;
+        log.sync()?;
+        storage.remap()?;
+
+        self.meta.generation = generation;
+        self.meta.quantization = quantization;
+        self.meta.quantized_only = !keep_raw;
+        write_meta(&self.dir, &self.meta)?;
+
+        self.storage = Some(storage);
+        self.log = log;
+        self.slot_ids = documents.values().map(|record| (record.slot, record.id.clone())).collect();
+        self.documents = documents;
+
+        // Slots were renumbered, so the graph is rebuilt from scratch.
+        self.hnsw = Hnsw::new(&self.hnsw_config);
+        self.index_new_slots()?;
+        self.save_hnsw()?;
+        segment::remove_stale_generations(&self.dir, generation)?;
+
+        Ok(())
+    }
+
+    fn to_result(&self, record: &PutRecord, score: f32) -> Result<SearchResult> {
+        let metadata = serde_json::from_slice(&self.log.read_metadata(record)?)
+            .context("Corrupt metadata in local vector store")?;
+
+        Ok(SearchResult {
+            id: record.id.clone(),
+            score,
+            metadata,
+        })
+    }
+}
+
+impl VectorStorage {
+    fn open(
+        dir: &Path,
+        generation: u64,
+        dimension: usize,
+        quantizer: Option<Quantizer>,
+        keep_raw: bool,
+        read_only: bool,
+    ) -> Result<Self> {
+        let raw = if keep_raw || quantizer.is_none() {
+            Some(VectorFile::open(&segment::vectors_path(dir, generation), dimension, read_only)?)
+        } else {
+            None
+        };
+        let quantized = match quantizer {
+            Some(quantizer) => Some(QuantizedVectors {
+                codes: SlotFile::open(&segment::codes_path(dir, generation), quantizer.code_size(), read_only)?,
+                quantizer,
+            }),
+            None => None,
+        };
+
+        // Searches index the codebook by the stored codes, so a corrupt one
+        // must not get that far.
+        if let Some(quantized) = &quantized {
+            let is_valid = |slot| quantized.codes.get(slot).is_some_and(|code| quantized.quantizer.is_valid_code(code));
+            if let Some(slot) = (0..quantized.codes.slots()).find(|&slot| !is_valid(slot)) {
+                let path = segment::codes_path(dir, generation);
+                anyhow::bail!("Corrupt quantized vector in slot {} of {}", slot, path.display());
+            }
+        }
+
+        // A crash between the two appends can leave one file ahead. Readers
+        // only use the slots both files have.
+        let mut storage = Self { raw, quantized };
+        if read_only {
+            return Ok(storage);
+        }
+        let slots = storage.slots();
+        if let Some(raw) = &mut storage.raw {
+            raw.truncate(slots)?;
+        }
+        if let Some(quantized) = &mut storage.quantized {
+            quantized.codes.truncate(slots)?;
+        }
+
+        Ok(storage)
+    }
+
+    /// Creates the files of a new generation.
+    fn create(dir: &Path, generation: u64, dimension: usize, quantizer: Option<Quantizer>, keep_raw: bool) -> Result<Self> {
+        if let Some(quantizer) = &quantizer {
+            quantizer.save(&segment::quantizer_path(dir, generation))?;
+        }
+        Self::open(dir, generation, dimension, quantizer, keep_raw, false)
+    }
+
+    fn slots(&self) -> u32 {
+        let raw = self.raw.as_ref().map(VectorFile::slots);
+        let codes = self.quantized.as_ref().map(|quantized| quantized.codes.slots());
+        raw.into_iter().chain(codes).min().unwrap_or(0)
+    }
+
+    fn dimension(&self) -> usize {
+        match (&self.raw, &self.quantized) {
+            (Some(raw), _) => raw.dimension(),
+            (None, Some(quantized)) => quantized.quantizer.dimension(),
+            (None, None) => 0,
+        }
+    }
+
+    fn bytes_per_vector(&self) -> usize {
+        let raw = self.raw.as_ref().map(|raw| raw.dimension() * 4).unwrap_or(0);
+        let codes = self.quantized.as_ref().map(|quantized| quantized.codes.slot_size()).unwrap_or(0);
+        raw + codes
+    }
+
+    /// Appends `vectors` and returns the slot of the first one.
+    fn append(&mut self, vectors: &[&[f32]]) -> Result<u32> {
+        let dimension = self.dimension();
+        for vector in vectors {
+            segment::check_dimension(vector, dimension)?;
+        }
+
+        let first = self.slots();
+        if let Some(raw) = &mut self.raw {
+            raw.append(vectors)?;
+        }
+        if let Some(quantized) = &mut self.quantized {
+            let codes: Vec<u8> = vectors.iter().flat_map(|vector| quantized.quantizer.encode(vector)).collect();
+            quantized.codes.append(&codes)?;
+        }
+        Ok(first)
+    }
+
+    /// Appends the vector in `slot` of `old`. With `copy_codes`, both use the
+    /// same quantizer and its code is copied instead of re-encoded.
+    fn copy_from(&mut self, old: &VectorStorage, slot: u32, copy_codes: bool) -> Result<u32> {
+        let vector = || old.vector(slot).context("Missing vector in local store");
+
+        let new_slot = self.slots();
+        if let Some(raw) = &mut self.raw {
+            raw.append(&[&vector()?])?;
+        }
+        if let Some(quantized) = &mut self.quantized {
+            let old_code = old
+                .quantized
+                .as_ref()
+                .filter(|_| copy_codes)
+                .and_then(|old| old.codes.get(slot));
+            match old_code {
+                Some(code) => quantized.codes.append(code)?,
+                None => quantized.codes.append(&quantized.quantizer.encode(&vector()?))?,
+            };
+        }
+        Ok(new_slot)
+    }
+
+    fn sync(&self) -> Result<()> {
+        if let Some(raw) = &self.raw {
+            raw.sync()?;
+        }
+        if let Some(quantized) = &self.quantized {
+            quantized.codes.sync()?;
+        }
+        Ok(())
+    }
+
+    fn remap(&mut self) -> Result<()> {
+        if let Some(raw) = &mut self.raw {
+            raw.remap()?;
+        }
+        if let Some(quantized) = &mut self.quantized {
+            quantized.codes.remap()?;
+        }
+        Ok(())
+    }
+
+    /// Cosine similarity of a prepared query with the vector in `slot`.
+    fn similarity(&self, query: &StorageQuery, slot: u32) -> Option<f32> {
+        match (query, &self.raw, &self.quantized) {
+            (StorageQuery::Quantized(query), _, Some(quantized)) => Some(query.similarity(quantized.codes.get(slot)?)),
+            (StorageQuery::Raw(query), Some(raw), _) => Some(cosine_similarity(query, raw.get(slot)?)),
+            _ => None,
+        }
+    }
+
+    /// Replaces approximate scores with exact ones when full-precision
+    /// vectors are available, and keeps the best `top_k`.
+    fn rerank<'a>(&self, query: &[f32], mut results: Vec<(f32, &'a PutRecord)>, top_k: usize) -> Vec<(f32, &'a PutRecord)> {
+        if let (Some(raw), Some(_)) = (&self.raw, &self.quantized) {
+            for (score, record) in &mut results {
+                if let Some(vector) = raw.get(record.slot) {
+                    *score = cosine_similarity(query, vector);
+                }
+            }
+            results.sort_by(|a, b| b.0.total_cmp(&a.0));
+        }
+        results.truncate(top_k);
+        results
+    }
+}
+
+impl VectorSource for VectorStorage {
+    type Query = StorageQuery;
+
+    /// Quantized codes are preferred when present: they are smaller to scan,
+    /// and results can be re-ranked with the raw vectors afterwards.
+    fn prepare(&self, query: &[f32]) -> StorageQuery {
+        match &self.quantized {
+            Some(quantized) => StorageQuery::Quantized(quantized.quantizer.prepare(query)),
+            None => StorageQuery::Raw(query.to_vec()),
+        }
+    }
+
+    fn dot(&self, query: &StorageQuery, id: u32) -> Option<f32> {
+        match (query, &self.raw, &self.quantized) {
+            (StorageQuery::Quantized(query), _, Some(quantized)) => Some(query.dot(quantized.codes.get(id)?)),
+            (StorageQuery::Raw(query), Some(raw), _) => Some(dot(query, raw.get(id)?)),
+            _ => None,
+        }
+    }
+
+    fn vector(&self, id: u32) -> Option<Cow<'_, [f32]>> {
+        if let Some(raw) = &self.raw {
+            return raw.get(id).map(Cow::Borrowed);
+        }
+        let quantized = self.quantized.as_ref()?;
+        Some(Cow::Owned(quantized.quantizer.decode(quantized.codes.get(id)?)))
+    }
+}
+
+#[async_trait]
+impl VectorStore for PersistentLocalVectorStore {
+    async fn initialize(&self, schema: &CollectionSchema) -> Result<()> {
+        let mut inner = self.write().await?;
+        let inner = &mut *inner;
+
+        if let Some(dimension) = inner.meta.dimension {
+            // Stores from before models were recorded are only checked by size.
+            let stored = CollectionSchema {
+                model: inner.meta.model.clone().unwrap_or_else(|| "an unrecorded model".to_string()),
+                dimension,
+            };
+            if inner.meta.model.is_some() || dimension != schema.dimension {
+                let collection = inner.dir.file_name().unwrap_or_default().to_string_lossy();
+                return stored.ensure_matches(schema, &collection);
+            }
+        }
+
+        inner.meta.model = Some(schema.model.clone());
+        inner.meta.dimension = Some(schema.dimension);
+        write_meta(&inner.dir, &inner.meta)?;
+        if inner.storage.is_none() {
+            inner.storage = Some(VectorStorage::open(&inner.dir, inner.meta.generation, schema.dimension, None, true, false)?);
+        }
+
+        Ok(())
+    }
+
+    async fn upsert_documents(&self, documents: Vec<Document>) -> Result<()> {
+        let Some(first) = documents.first() else {
+            return Ok(());
+        };
+
+        let mut inner = self.write().await?;
+        let inner = &mut *inner;
+
+        if inner.storage.is_none() {
+            let dimension = inner.meta.dimension.unwrap_or(first.embedding.len());
+            inner.storage = Some(VectorStorage::open(&inner.dir, inner.meta.generation, dimension, None, true, false)?);
+            inner.meta.dimension = Some(dimension);
+            write_meta(&inner.dir, &inner.meta)?;
+        }
+        let storage = inner.storage.as_mut().unwrap();
+
+        let embeddings: Vec<&[f32]> = documents.iter().map(|doc| doc.embedding.as_slice()).collect();
+        let first_slot = storage.append(&embeddings)?;
+        storage.remap()?;
+
+        for (offset, doc) in documents.iter().enumerate() {
+            let field = |key: &str| doc.metadata.get(key).and_then(|v| v.as_str());
+            let put = inner.log.put(
+                &doc.id,
+                first_slot + offset as u32,
+                field("file_path").unwrap_or_default(),
+                field("repo"),
+                field("language").unwrap_or_default(),
+                &serde_json::to_vec(&doc.metadata)?,
+            )?;
+            inner.slot_ids.insert(put.slot, put.id.clone());
+            // Replacing a document leaves its old slot for compaction to reclaim.
+            if let Some(previous) = inner.documents.insert(doc.id.clone(), put) {
+                inner.slot_ids.remove(&previous.slot);
+            }
+        }
+        inner.log.flush()?;
+        inner.index_new_slots()?;
+
+        inner.maybe_compact()
+    }
+
+    async fn search(&self, query_vector: Vec<f32>, top_k: usize, filter: &SearchFilter) -> Result<Vec<SearchResult>> {
+        let inner = self.inner.read().await;
+        let Some(storage) = &inner.storage else {
+            return Ok(vec![]);
+        };
+        let candidates = if storage.raw.is_some() && storage.quantized.is_some() {
+            top_k * RERANK_CANDIDATES_PER_RESULT
+        } else {
+            top_k
+        };
+
+        let mut results = vec![];
+        if inner.documents.len() > BRUTE_FORCE_MAX_DOCUMENTS {
+            results = inner.search_graph(storage, &query_vector, candidates, filter);
+        }
+        // With a very selective filter the graph may not reach enough
+        // matches; fall back to a full scan then.
+        if results.len() < top_k {
+            results = inner.scan(storage, &query_vector, candidates, filter);
+        }
+
+        storage
+            .rerank(&query_vector, results, top_k)
             .into_iter()
-            .take(top_k)
-            .map(|(score, doc)| SearchResult {
-                score,
-                metadata: doc.metadata.clone(),
-            })
-            .collect())
+            .map(|(score, record)| inner.to_result(record, score))
+            .collect()
     }
 
     async fn delete_by_file(&self, file_path: &Path) -> Result<()> {
-        let mut store = self.documents.write().await;
         let path_str = file_path.display().to_string();
-        store.retain(|doc| {
-            doc.metadata
-                .get("file_path")
-                .and_then(|v| v.as_str())
-                .map(|p| p != path_str)
-                .unwrap_or(true)
-        });
-        
-        drop(store); // Release the lock before saving
-        self.save().await?;
-        Ok(())
+        let ids: Vec<String> = self
+            .inner
+            .read()
+            .await
+            .documents
+            .values()
+            .filter(|record| record.file_path == path_str)
+            .map(|record| record.id.clone())
+            .collect();
+
+        self.delete_documents(ids).await
+    }
+
+    async fn delete_documents(&self, ids: Vec<String>) -> Result<()> {
+        if ids.is_empty() {
+            return Ok(());
+        }
+
+        let mut inner = self.write().await?;
+        for id in ids {
+            if let Some(record) = inner.documents.remove(&id) {
+                inner.slot_ids.remove(&record.slot);
+                inner.log.delete(&id)?;
+            }
+        }
+        inner.log.flush()?;
+
+        inner.maybe_compact()
+    }
+
+    async fn get_documents(&self, ids: &[String]) -> Result<Vec<SearchResult>> {
+        let inner = self.inner.read().await;
+
+        ids.iter()
+            .filter_map(|id| inner.documents.get(id))
+            .map(|record| inner.to_result(record, 0.0))
+            .collect()
+    }
+
+    /// Pages are ordered by ID; the token is the last ID returned.
+    async fn scroll(&self, offset: Option<String>, limit: usize) -> Result<(Vec<SearchResult>, Option<String>)> {
+        let inner = self.inner.read().await;
+
+        let mut ids: Vec<&String> = inner
+            .documents
+            .keys()
+            .filter(|id| offset.as_ref().is_none_or(|offset| *id > offset))
+            .collect();
+        let more = ids.len() > limit;
+        if more {
+            ids.select_nth_unstable(limit);
+            ids.truncate(limit);
+        }
+        ids.sort_unstable();
+
+        let next = if more { ids.last().map(|id| id.to_string()) } else { None };
+        let results = ids
+            .into_iter()
+            .map(|id| inner.to_result(&inner.documents[id], 0.0))
+            .collect::<Result<_>>()?;
+        Ok((results, next))
+    }
+
+    async fn schema(&self) -> Result<Option<CollectionSchema>> {
+        let meta = &self.inner.read().await.meta;
+        Ok(meta.model.clone().zip(meta.dimension).map(|(model, dimension)| CollectionSchema { model, dimension }))
     }
 
     fn create_document(&self, chunk: CodeChunk, embedding: Vec<f32>) -> Document {
         Document {
-            id: uuid::Uuid::new_v4().to_string(),
+            id: chunk_id(&chunk),
             embedding,
-            metadata: HashMap::from([
-                ("file_path".to_string(), serde_json::json!(chunk.file_path)),
-                ("content".to_string(), serde_json::json!(chunk.content)),
-                ("line_start".to_string(), serde_json::json!(chunk.line_start)),
-                ("line_end".to_string(), serde_json::json!(chunk.line_end)),
-                ("chunk_type".to_string(), serde_json::json!(format!("{:?}", chunk.chunk_type))),
-                ("language".to_string(), serde_json::json!(chunk.language)),
-            ]),
+            metadata: chunk_metadata(chunk),
         }
     }
-}
\ No newline at end of file
+}
+
+/// Takes the writer lock of the store in `dir`, which is released when the
+/// returned file is closed.
+fn lock_writer(dir: &Path) -> Result<File> {
+    let file = File::options().create(true).write(true).truncate(false).open(dir.join(LOCK_FILE))?;
+    match file.try_lock() {
+        Ok(()) => Ok(file),
+        Err(TryLockError::WouldBlock) => anyhow::bail!(
+            "Local store in {} is being written by another process, such as a running `buildli index`",
+            dir.display()
+        ),
+        Err(TryLockError::Error(e)) => Err(e.into()),
+    }
+}
+
+fn write_meta(dir: &Path, meta: &StoreMeta) -> Result<()> {
+    let tmp_path = dir.join(format!("{}.tmp", META_FILE));
+    fs::write(&tmp_path, serde_json::to_vec_pretty(meta)?)?;
+    fs::rename(&tmp_path, dir.join(META_FILE))?;
+    Ok(())
+}
+
+/// Directory holding `collection` under `root`.
+fn collection_dir(root: &Path, collection: &str) -> Result<PathBuf> {
+    let aliases = read_aliases(root)?;
+    let name = aliases.get(collection).cloned().unwrap_or_else(|| sanitize(collection));
+    Ok(root.join(name))
+}
+
+fn read_aliases(root: &Path) -> Result<HashMap<String, String>> {
+    let path = root.join(ALIASES_FILE);
+    if !path.exists() {
+        return Ok(HashMap::new());
+    }
+    serde_json::from_slice(&fs::read(&path)?).context("Failed to parse local store aliases")
+}
+
+/// Collection names become directory names.
+fn sanitize(collection: &str) -> String {
+    collection
+        .chars()
+        .map(|c| if c.is_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn document(id: &str, file_path: &str, embedding: Vec<f32>) -> Document {
+        let metadata = HashMap::from([
+            ("file_path".to_string(), json!(file_path)),
+            ("language".to_string(), json!("rs")),
+        ]);
+        Document {
+            id: id.to_string(),
+            embedding,
+            metadata,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_persists_and_compacts() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = PersistentLocalVectorStore::open(dir.path().to_path_buf(), &VectorConfig::default()).await.unwrap();
+
+        store
+            .upsert_documents(vec![
+                document("a", "a.rs", vec![1.0, 0.0]),
+                document("b", "b.rs", vec![0.0, 1.0]),
+            ])
+            .await
+            .unwrap();
+        store.upsert_documents(vec![document("a", "a.rs", vec![0.6, 0.8])]).await.unwrap();
+        store.delete_by_file(Path::new("b.rs")).await.unwrap();
+        drop(store);
+
+        let store = PersistentLocalVectorStore::open(dir.path().to_path_buf(), &VectorConfig::default()).await.unwrap();
+        let results = store.search(vec![0.6, 0.8], 10, &SearchFilter::default()).await.unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].id, "a");
+        assert_eq!(results[0].metadata["file_path"], json!("a.rs"));
+
+        store.compact().await.unwrap();
+        assert_eq!(store.inner.read().await.dead_slots(), 0);
+        let results = store.get_documents(&["a".to_string()]).await.unwrap();
+        assert_eq!(results[0].metadata["file_path"], json!("a.rs"));
+    }
+
+    #[tokio::test]
+    async fn test_refuses_other_embedding_model() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = PersistentLocalVectorStore::open(dir.path().to_path_buf(), &VectorConfig::default()).await.unwrap();
+        let schema = CollectionSchema {
+            model: "local/hashed-tfidf".to_string(),
+            dimension: 384,
+        };
+        store.initialize(&schema).await.unwrap();
+        drop(store);
+
+        let store = PersistentLocalVectorStore::open(dir.path().to_path_buf(), &VectorConfig::default()).await.unwrap();
+        store.initialize(&schema).await.unwrap();
+        let other = CollectionSchema {
+            model: "openai/text-embedding-3-small".to_string(),
+            dimension: 1536,
+        };
+        let err = store.initialize(&other).await.unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(crate::BuildliError::VectorStore(_))));
+    }
+
+    #[tokio::test]
+    async fn test_converts_between_quantizations() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = PersistentLocalVectorStore::open(dir.path().to_path_buf(), &VectorConfig::default()).await.unwrap();
+
+        let documents: Vec<Document> = (0..300)
+            .map(|i| {
+                let embedding = (0..16).map(|d| ((i * 7919 + d * 104_729) % 1000) as f32 / 1000.0 - 0.5).collect();
+                document(&format!("d{}", i), &format!("{}.rs", i), embedding)
+            })
+            .collect();
+        let query = documents[17].embedding.clone();
+        store.upsert_documents(documents).await.unwrap();
+
+        for (method, rerank) in [("int8", true), ("pq", false), ("none", true)] {
+            let quantization = QuantizationConfig {
+                method: method.to_string(),
+                pq_subvectors: 0,
+                rerank,
+            };
+            store.convert(&quantization).await.unwrap();
+
+            let results = store.search(query.clone(), 3, &SearchFilter::default()).await.unwrap();
+            assert!(results.iter().any(|result| result.id == "d17"), "{}", method);
+            if method == "pq" {
+                // Two 8-dimensional subvectors plus the norm, without raw vectors.
+                assert_eq!(store.bytes_per_vector().await, 6);
+            }
+        }
+
+        drop(store);
+        let store = PersistentLocalVectorStore::open(dir.path().to_path_buf(), &VectorConfig::default()).await.unwrap();
+        assert_eq!(store.len().await, 300);
+        assert_eq!(store.bytes_per_vector().await, 64);
+    }
+
+    #[tokio::test]
+    async fn test_readers_share_the_store_with_one_writer() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = VectorConfig::default();
+        let writer = PersistentLocalVectorStore::open(dir.path().to_path_buf(), &config).await.unwrap();
+        writer.upsert_documents(vec![document("a", "a.rs", vec![1.0, 0.0])]).await.unwrap();
+        assert!(PersistentLocalVectorStore::open(dir.path().to_path_buf(), &config).await.is_err());
+
+        // A record the writer is still appending looks torn to a reader.
+        let log_path = segment::log_path(dir.path(), 0);
+        let mut log = fs::OpenOptions::new().append(true).open(&log_path).unwrap();
+        std::io::Write::write_all(&mut log, &[64, 0, 0, 0, 1]).unwrap();
+        let log_len = fs::metadata(&log_path).unwrap().len();
+
+        let reader = PersistentLocalVectorStore::open_read_only(dir.path().to_path_buf(), &config).await.unwrap();
+        assert_eq!(reader.len().await, 1);
+        assert_eq!(fs::metadata(&log_path).unwrap().len(), log_len);
+        assert!(reader.upsert_documents(vec![document("b", "b.rs", vec![0.0, 1.0])]).await.is_err());
+
+        drop(writer);
+
+        // Once the writer is gone, the reader's first write takes over.
+        reader.upsert_documents(vec![document("b", "b.rs", vec![0.0, 1.0])]).await.unwrap();
+        assert_eq!(reader.len().await, 2);
+        assert!(PersistentLocalVectorStore::open(dir.path().to_path_buf(), &config).await.is_err());
+    }
+}
@@ -1,31 +1,118 @@
+pub mod hnsw;
 pub mod local_store;
+pub mod quantization;
+pub mod segment;
 
 use anyhow::Result;
 use async_trait::async_trait;
 use qdrant_client::{
-    prelude::*,
     qdrant::{
-        vectors_config::Config, CreateCollection, Distance, PointStruct, SearchPoints,
-        VectorParams, VectorsConfig,
+        alias_operations::Action, collections_client::CollectionsClient, vectors_config::Config, AliasOperations,
+        ChangeAliases, CreateAlias, CreateCollectionBuilder, DeleteAlias, DeletePointsBuilder, Distance,
+        GetPointsBuilder, PointStruct, ScrollPointsBuilder, SearchPointsBuilder, UpsertPointsBuilder,
+        VectorParamsBuilder,
     },
+    Qdrant,
 };
+use serde::{Deserialize, Serialize};
 use serde_json::json;
+use sha2::{Digest, Sha256};
 use std::collections::HashMap;
 use std::path::Path;
+use std::sync::Arc;
 use uuid::Uuid;
 
-use crate::indexer::parser::CodeChunk;
+use crate::indexer::parser::{language_codes, CodeChunk};
+use crate::BuildliError;
 pub use local_store::PersistentLocalVectorStore;
 
 #[async_trait]
 pub trait VectorStore: Send + Sync {
-    async fn initialize(&self, collection_name: &str, vector_size: usize) -> Result<()>;
+    /// Creates the collection for `schema` if needed. Fails with
+    /// [`BuildliError::VectorStore`] if it was created for another model.
+    async fn initialize(&self, schema: &CollectionSchema) -> Result<()>;
     async fn upsert_documents(&self, documents: Vec<Document>) -> Result<()>;
-    async fn search(&self, query_vector: Vec<f32>, top_k: usize) -> Result<Vec<SearchResult>>;
+    async fn search(&self, query_vector: Vec<f32>, top_k: usize, filter: &SearchFilter) -> Result<Vec<SearchResult>>;
     async fn delete_by_file(&self, file_path: &Path) -> Result<()>;
+    async fn delete_documents(&self, ids: Vec<String>) -> Result<()>;
+    /// Fetches stored chunks by ID. Unknown IDs are skipped and every result
+    /// has a score of zero.
+    async fn get_documents(&self, ids: &[String]) -> Result<Vec<SearchResult>>;
+    /// Pages through every stored chunk in a stable order. Pass the returned
+    /// token to fetch the next page; `None` means there are no more. Results
+    /// have a score of zero.
+    async fn scroll(&self, offset: Option<String>, limit: usize) -> Result<(Vec<SearchResult>, Option<String>)>;
+    /// The schema recorded by [`initialize`](Self::initialize), if any.
+    async fn schema(&self) -> Result<Option<CollectionSchema>>;
     fn create_document(&self, chunk: CodeChunk, embedding: Vec<f32>) -> Document;
 }
 
+/// Lets one store be shared, e.g. by a query engine and an indexer.
+#[async_trait]
+impl<V: VectorStore + ?Sized> VectorStore for Arc<V> {
+    async fn initialize(&self, schema: &CollectionSchema) -> Result<()> {
+        (**self).initialize(schema).await
+    }
+
+    async fn upsert_documents(&self, documents: Vec<Document>) -> Result<()> {
+        (**self).upsert_documents(documents).await
+    }
+
+    async fn search(&self, query_vector: Vec<f32>, top_k: usize, filter: &SearchFilter) -> Result<Vec<SearchResult>> {
+        (**self).search(query_vector, top_k, filter).await
+    }
+
+    async fn delete_by_file(&self, file_path: &Path) -> Result<()> {
+        (**self).delete_by_file(file_path).await
+    }
+
+    async fn delete_documents(&self, ids: Vec<String>) -> Result<()> {
+        (**self).delete_documents(ids).await
+    }
+
+    async fn get_documents(&self, ids: &[String]) -> Result<Vec<SearchResult>> {
+        (**self).get_documents(ids).await
+    }
+
+    async fn scroll(&self, offset: Option<String>, limit: usize) -> Result<(Vec<SearchResult>, Option<String>)> {
+        (**self).scroll(offset, limit).await
+    }
+
+    async fn schema(&self) -> Result<Option<CollectionSchema>> {
+        (**self).schema().await
+    }
+
+    fn create_document(&self, chunk: CodeChunk, embedding: Vec<f32>) -> Document {
+        (**self).create_document(chunk, embedding)
+    }
+}
+
+/// Embedding model and vector size a collection was created with. Vectors
+/// from different models are not comparable, so writes from any other model
+/// are refused.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CollectionSchema {
+    pub model: String,
+    pub dimension: usize,
+}
+
+impl CollectionSchema {
+    /// Checks that `other` matches this schema, recorded for `collection`.
+    pub fn ensure_matches(&self, other: &CollectionSchema, collection: &str) -> Result<()> {
+        if self == other {
+            return Ok(());
+        }
+
+        Err(BuildliError::VectorStore(format!(
+            "Collection '{}' holds {}-dimensional vectors from {}, but the configured embedder is {} ({} dimensions). \
+             Vectors from different models cannot be mixed: re-embed the collection with \
+             `buildli reindex --migrate-embeddings`, or switch back to {}.",
+            collection, self.dimension, self.model, other.model, other.dimension, self.model
+        ))
+        .into())
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Document {
     pub id: String,
@@ -35,54 +122,312 @@ pub struct Document {
 
 #[derive(Debug, Clone)]
 pub struct SearchResult {
+    pub id: String,
     pub score: f32,
     pub metadata: HashMap<String, serde_json::Value>,
 }
 
+/// Restricts a search to chunks from the given repositories and languages.
+/// An empty list places no restriction on that field.
+#[derive(Debug, Clone, Default)]
+pub struct SearchFilter {
+    pub repos: Vec<String>,
+    pub languages: Vec<String>,
+}
+
+impl SearchFilter {
+    /// Builds a filter from user input, expanding language names such as
+    /// `rust` or `typescript` into the language codes stored on chunks.
+    pub fn new(repos: Vec<String>, languages: Vec<String>) -> Self {
+        let mut language_codes: Vec<String> = languages
+            .iter()
+            .flat_map(|language| language_codes(language))
+            .collect();
+        language_codes.sort();
+        language_codes.dedup();
+        
+        Self {
+            repos: repos.into_iter().filter(|r| !r.is_empty()).collect(),
+            languages: language_codes,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.repos.is_empty() && self.languages.is_empty()
+    }
+
+    pub fn matches(&self, metadata: &HashMap<String, serde_json::Value>) -> bool {
+        let field = |key: &str| metadata.get(key).and_then(|v| v.as_str());
+        self.accepts(field("repo"), field("language"))
+    }
+
+    pub fn accepts(&self, repo: Option<&str>, language: Option<&str>) -> bool {
+        let allows = |allowed: &[String], value: Option<&str>| {
+            allowed.is_empty() || value.map(|v| allowed.iter().any(|a| a == v)).unwrap_or(false)
+        };
+        
+        allows(&self.repos, repo) && allows(&self.languages, language)
+    }
+
+    fn to_qdrant_filter(&self) -> Option<qdrant_client::qdrant::Filter> {
+        if self.is_empty() {
+            return None;
+        }
+        
+        let mut conditions = Vec::new();
+        if !self.repos.is_empty() {
+            conditions.push(qdrant_client::qdrant::Condition::matches("repo", self.repos.clone()));
+        }
+        if !self.languages.is_empty() {
+            conditions.push(qdrant_client::qdrant::Condition::matches("language", self.languages.clone()));
+        }
+        
+        Some(qdrant_client::qdrant::Filter::must(conditions))
+    }
+}
+
+/// Namespace for chunk IDs, so they cannot collide with other UUIDv5 users.
+const CHUNK_ID_NAMESPACE: Uuid = Uuid::from_u128(0x6f1c_2b0e_9a4d_4c37_8e55_b1d0_c7a3_f912);
+
+/// Stable ID for a chunk: a UUIDv5 over its repository, repo-relative path,
+/// symbol path, line span and content hash. Symbols are not unique (nested
+/// closures share their parent's), so the span is always part of it.
+/// Re-indexing unchanged code therefore produces the same IDs in every backend.
+pub fn chunk_id(chunk: &CodeChunk) -> String {
+    let path = chunk.relative_path.as_deref().unwrap_or(&chunk.file_path);
+    let span = format!("{}-{}", chunk.line_start, chunk.line_end);
+    let content_hash = Sha256::digest(chunk.content.as_bytes());
+    
+    let mut name = Vec::new();
+    for part in [chunk.repo.as_deref().unwrap_or_default(), path, chunk.symbol.as_deref().unwrap_or_default(), &span] {
+        name.extend_from_slice(part.as_bytes());
+        name.push(0);
+    }
+    name.extend_from_slice(&content_hash);
+    
+    Uuid::new_v5(&CHUNK_ID_NAMESPACE, &name).to_string()
+}
+
+/// Payload stored alongside every chunk embedding, shared by all backends.
+pub(crate) fn chunk_metadata(chunk: CodeChunk) -> HashMap<String, serde_json::Value> {
+    let mut metadata = HashMap::from([
+        ("file_path".to_string(), json!(chunk.file_path)),
+        ("content".to_string(), json!(chunk.content)),
+        ("line_start".to_string(), json!(chunk.line_start)),
+        ("line_end".to_string(), json!(chunk.line_end)),
+        ("chunk_type".to_string(), json!(format!("{:?}", chunk.chunk_type))),
+        ("language".to_string(), json!(chunk.language)),
+    ]);
+    
+    if let Some(repo) = chunk.repo {
+        metadata.insert("repo".to_string(), json!(repo));
+    }
+    
+    if let Some(commit) = chunk.commit {
+        metadata.insert("commit".to_string(), json!(commit));
+    }
+    
+    if let Some(relative_path) = chunk.relative_path {
+        metadata.insert("relative_path".to_string(), json!(relative_path));
+    }
+    
+    if let Some(symbol) = chunk.symbol {
+        metadata.insert("symbol".to_string(), json!(symbol));
+    }
+    
+    metadata
+}
+
+/// Suffix of the companion collection holding a collection's schema, since
+/// Qdrant collections have no metadata of their own.
+const SCHEMA_COLLECTION_SUFFIX: &str = "__buildli_meta";
+const SCHEMA_POINT_ID: u64 = 0;
+
+/// Suffix of the alias that `buildli reindex --migrate-embeddings` points at
+/// the collection serving a configured name. An alias cannot take the name of
+/// an existing collection, so the configured name keeps working until the
+/// alias is in place.
+const CURRENT_ALIAS_SUFFIX: &str = "__current";
+
 pub struct QdrantStore {
-    client: QdrantClient,
+    client: Qdrant,
+    url: String,
     collection_name: String,
+    /// Collection that `collection_name` refers to: the target of its
+    /// migration alias, if any. Resolved on creation and by `promote`.
+    collection: std::sync::RwLock<String>,
 }
 
 impl QdrantStore {
     pub async fn new(url: &str, collection_name: &str) -> Result<Self> {
-        let client = QdrantClient::from_url(url).build()
+        // The compatibility check prints to stdout, which would corrupt `--json` output.
+        let client = Qdrant::from_url(url).skip_compatibility_check().build()
             .map_err(|e| anyhow::anyhow!("Failed to create Qdrant client: {}", e))?;
         
-        Ok(Self {
+        let store = Self {
             client,
+            url: url.to_string(),
             collection_name: collection_name.to_string(),
-        })
+            collection: std::sync::RwLock::new(collection_name.to_string()),
+        };
+        if let Some(collection) = store.alias_target().await? {
+            *store.collection.write().unwrap() = collection;
+        }
+        Ok(store)
+    }
+
+    /// Name of the collection that `collection_name` refers to, which differs
+    /// once `buildli reindex --migrate-embeddings` has aliased it.
+    fn physical_collection(&self) -> String {
+        self.collection.read().unwrap().clone()
+    }
+
+    /// The collection the migration alias for `collection_name` points at,
+    /// if embeddings were ever migrated.
+    async fn alias_target(&self) -> Result<Option<String>> {
+        let alias = self.current_alias();
+        let aliases = self.client.list_aliases().await?.aliases;
+        Ok(aliases
+            .into_iter()
+            .find(|description| description.alias_name == alias)
+            .map(|description| description.collection_name))
+    }
+
+    fn current_alias(&self) -> String {
+        format!("{}{}", self.collection_name, CURRENT_ALIAS_SUFFIX)
+    }
+
+    fn schema_collection(collection: &str) -> String {
+        format!("{}{}", collection, SCHEMA_COLLECTION_SUFFIX)
+    }
+
+    async fn create_collection(&self, collection_name: String, vector_size: usize) -> Result<()> {
+        self.client
+            .create_collection(
+                CreateCollectionBuilder::new(collection_name)
+                    .vectors_config(VectorParamsBuilder::new(vector_size as u64, Distance::Cosine)),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn read_schema(&self, collection: &str) -> Result<Option<CollectionSchema>> {
+        let schema_collection = Self::schema_collection(collection);
+        if !self.client.collection_exists(&schema_collection).await? {
+            return Ok(None);
+        }
+
+        let response = self
+            .client
+            .get_points(GetPointsBuilder::new(schema_collection, vec![SCHEMA_POINT_ID.into()]).with_payload(true))
+            .await?;
+        let Some(point) = response.result.into_iter().next() else {
+            return Ok(None);
+        };
+
+        let payload: serde_json::Map<String, serde_json::Value> =
+            point.payload.into_iter().map(|(k, v)| (k, v.into())).collect();
+        Ok(serde_json::from_value(payload.into()).ok())
+    }
+
+    async fn write_schema(&self, collection: &str, schema: &CollectionSchema) -> Result<()> {
+        let schema_collection = Self::schema_collection(collection);
+        if !self.client.collection_exists(&schema_collection).await? {
+            self.create_collection(schema_collection.clone(), 1).await?;
+        }
+
+        let payload: HashMap<String, qdrant_client::qdrant::Value> = [
+            ("model".to_string(), json!(schema.model).into()),
+            ("dimension".to_string(), json!(schema.dimension).into()),
+        ]
+        .into_iter()
+        .collect();
+        self.client
+            .upsert_points(UpsertPointsBuilder::new(
+                schema_collection,
+                vec![PointStruct::new(SCHEMA_POINT_ID, vec![1.0], payload)],
+            ))
+            .await?;
+        Ok(())
+    }
+
+    /// Vector size of `collection`, if it uses a single unnamed vector.
+    async fn vector_size(&self, collection: &str) -> Result<Option<usize>> {
+        let info = self.client.collection_info(collection).await?;
+        let size = info
+            .result
+            .and_then(|info| info.config)
+            .and_then(|config| config.params)
+            .and_then(|params| params.vectors_config)
+            .and_then(|vectors| vectors.config)
+            .and_then(|config| match config {
+                Config::Params(params) => Some(params.size as usize),
+                _ => None,
+            });
+        Ok(size)
+    }
+
+    /// Points `collection_name` at `target` and deletes the collection it
+    /// referred to before, along with its schema. Readers are switched over
+    /// by a single alias update; the old collection is only deleted once
+    /// that has succeeded.
+    pub async fn promote(&self, target: &str) -> Result<()> {
+        let alias = self.current_alias();
+        let previous = self.alias_target().await?;
+        let previous_collection = previous.clone().unwrap_or_else(|| self.collection_name.clone());
+        if previous_collection == target {
+            return Ok(());
+        }
+
+        let mut actions = Vec::new();
+        if previous.is_some() {
+            actions.push(AliasOperations {
+                action: Some(Action::DeleteAlias(DeleteAlias {
+                    alias_name: alias.clone(),
+                })),
+            });
+        }
+        actions.push(AliasOperations {
+            action: Some(Action::CreateAlias(CreateAlias {
+                collection_name: target.to_string(),
+                alias_name: alias,
+            })),
+        });
+        // The client only sends one alias action per request, so the swap
+        // goes through the gRPC service directly to stay atomic.
+        CollectionsClient::connect(self.url.clone())
+            .await?
+            .update_aliases(ChangeAliases { actions, timeout: None })
+            .await?;
+        *self.collection.write().unwrap() = target.to_string();
+
+        for collection in [previous_collection.clone(), Self::schema_collection(&previous_collection)] {
+            if self.client.collection_exists(&collection).await? {
+                self.client.delete_collection(&collection).await?;
+            }
+        }
+        Ok(())
     }
 }
 
 #[async_trait]
 impl VectorStore for QdrantStore {
-    async fn initialize(&self, collection_name: &str, vector_size: usize) -> Result<()> {
-        let collections = self.client.list_collections().await?;
-        
-        let exists = collections
-            .collections
-            .iter()
-            .any(|c| c.name == collection_name);
-        
-        if !exists {
-            self.client
-                .create_collection(&CreateCollection {
-                    collection_name: collection_name.to_string(),
-                    vectors_config: Some(VectorsConfig {
-                        config: Some(Config::Params(VectorParams {
-                            size: vector_size as u64,
-                            distance: Distance::Cosine.into(),
-                            ..Default::default()
-                        })),
-                    }),
-                    ..Default::default()
-                })
-                .await?;
+    async fn initialize(&self, schema: &CollectionSchema) -> Result<()> {
+        let collection = self.physical_collection();
+        if !self.client.collection_exists(&collection).await? {
+            self.create_collection(collection.clone(), schema.dimension).await?;
+        } else if let Some(stored) = self.read_schema(&collection).await? {
+            return stored.ensure_matches(schema, &self.collection_name);
+        } else if let Some(size) = self.vector_size(&collection).await?.filter(|&size| size != schema.dimension) {
+            // Created before schemas were recorded, so only the size is known.
+            let stored = CollectionSchema {
+                model: "an unrecorded model".to_string(),
+                dimension: size,
+            };
+            return stored.ensure_matches(schema, &self.collection_name);
         }
         
-        Ok(())
+        self.write_schema(&collection, schema).await
     }
 
     async fn upsert_documents(&self, documents: Vec<Document>) -> Result<()> {
@@ -95,34 +440,30 @@ impl VectorStore for QdrantStore {
                     doc.metadata
                         .into_iter()
                         .map(|(k, v)| (k, v.into()))
-                        .collect::<std::collections::HashMap<_, _>>(),
+                        .collect::<std::collections::HashMap<_, qdrant_client::qdrant::Value>>(),
                 )
             })
             .collect();
         
         self.client
-            .upsert_points(&self.collection_name, None, points, None)
+            .upsert_points(UpsertPointsBuilder::new(self.physical_collection(), points))
             .await?;
         
         Ok(())
     }
 
-    async fn search(&self, query_vector: Vec<f32>, top_k: usize) -> Result<Vec<SearchResult>> {
-        let search_result = self
-            .client
-            .search_points(&SearchPoints {
-                collection_name: self.collection_name.clone(),
-                vector: query_vector,
-                limit: top_k as u64,
-                with_payload: Some(true.into()),
-                ..Default::default()
-            })
-            .await?;
+    async fn search(&self, query_vector: Vec<f32>, top_k: usize, filter: &SearchFilter) -> Result<Vec<SearchResult>> {
+        let mut request = SearchPointsBuilder::new(self.physical_collection(), query_vector, top_k as u64).with_payload(true);
+        if let Some(filter) = filter.to_qdrant_filter() {
+            request = request.filter(filter);
+        }
+        let search_result = self.client.search_points(request).await?;
         
         let results = search_result
             .result
             .into_iter()
             .map(|point| SearchResult {
+                id: point_id_to_string(point.id),
                 score: point.score,
                 metadata: point
                     .payload
@@ -136,46 +477,86 @@ impl VectorStore for QdrantStore {
     }
 
     async fn delete_by_file(&self, file_path: &Path) -> Result<()> {
-        let filter = qdrant_client::qdrant::Filter {
-            must: vec![qdrant_client::qdrant::Condition {
-                condition_one_of: Some(
-                    qdrant_client::qdrant::condition::ConditionOneOf::Field(
-                        qdrant_client::qdrant::FieldCondition {
-                            key: "file_path".to_string(),
-                            r#match: Some(qdrant_client::qdrant::Match {
-                                match_value: Some(
-                                    qdrant_client::qdrant::r#match::MatchValue::Text(
-                                        file_path.display().to_string(),
-                                    ),
-                                ),
-                            }),
-                            ..Default::default()
-                        },
-                    ),
-                ),
-            }],
-            ..Default::default()
-        };
+        let filter = qdrant_client::qdrant::Filter::must([qdrant_client::qdrant::Condition::matches(
+            "file_path",
+            file_path.display().to_string(),
+        )]);
+        
+        self.client
+            .delete_points(DeletePointsBuilder::new(self.physical_collection()).points(filter))
+            .await?;
+        
+        Ok(())
+    }
+
+    async fn delete_documents(&self, ids: Vec<String>) -> Result<()> {
+        if ids.is_empty() {
+            return Ok(());
+        }
+        
+        let points: Vec<qdrant_client::qdrant::PointId> = ids.into_iter().map(Into::into).collect();
         
         self.client
-            .delete_points(&self.collection_name, None, &filter.into(), None)
+            .delete_points(DeletePointsBuilder::new(self.physical_collection()).points(points))
             .await?;
         
         Ok(())
     }
 
+    async fn get_documents(&self, ids: &[String]) -> Result<Vec<SearchResult>> {
+        if ids.is_empty() {
+            return Ok(vec![]);
+        }
+        
+        let points: Vec<qdrant_client::qdrant::PointId> = ids.iter().map(|id| id.as_str().into()).collect();
+        let response = self
+            .client
+            .get_points(GetPointsBuilder::new(self.physical_collection(), points).with_payload(true))
+            .await?;
+        
+        Ok(response
+            .result
+            .into_iter()
+            .map(|point| SearchResult {
+                id: point_id_to_string(point.id),
+                score: 0.0,
+                metadata: point.payload.into_iter().map(|(k, v)| (k, v.into())).collect(),
+            })
+            .collect())
+    }
+
+    async fn scroll(&self, offset: Option<String>, limit: usize) -> Result<(Vec<SearchResult>, Option<String>)> {
+        let mut request = ScrollPointsBuilder::new(self.physical_collection())
+            .limit(limit as u32)
+            .with_payload(true)
+            .with_vectors(false);
+        if let Some(id) = offset {
+            request = request.offset(id);
+        }
+        let response = self.client.scroll(request).await?;
+
+        let results = response
+            .result
+            .into_iter()
+            .map(|point| SearchResult {
+                id: point_id_to_string(point.id),
+                score: 0.0,
+                metadata: point.payload.into_iter().map(|(k, v)| (k, v.into())).collect(),
+            })
+            .collect();
+        let next = response.next_page_offset.map(|id| point_id_to_string(Some(id)));
+        Ok((results, next))
+    }
+
+    async fn schema(&self) -> Result<Option<CollectionSchema>> {
+        self.read_schema(&self.physical_collection()).await
+    }
+
     fn create_document(&self, chunk: CodeChunk, embedding: Vec<f32>) -> Document {
         Document {
-            id: Uuid::new_v4().to_string(),
+            id: chunk_id(&chunk),
             embedding,
-            metadata: HashMap::from([
-                ("file_path".to_string(), json!(chunk.file_path)),
-                ("content".to_string(), json!(chunk.content)),
-                ("line_start".to_string(), json!(chunk.line_start)),
-                ("line_end".to_string(), json!(chunk.line_end)),
-                ("chunk_type".to_string(), json!(format!("{:?}", chunk.chunk_type))),
-                ("language".to_string(), json!(chunk.language)),
-            ]),
+            metadata: chunk_metadata(chunk),
         }
     }
 }
@@ -194,20 +575,23 @@ impl LocalVectorStore {
 
 #[async_trait]
 impl VectorStore for LocalVectorStore {
-    async fn initialize(&self, _collection_name: &str, _vector_size: usize) -> Result<()> {
+    async fn initialize(&self, _schema: &CollectionSchema) -> Result<()> {
         Ok(())
     }
 
     async fn upsert_documents(&self, documents: Vec<Document>) -> Result<()> {
+        let ids: std::collections::HashSet<&str> = documents.iter().map(|d| d.id.as_str()).collect();
         let mut store = self.documents.write().unwrap();
+        store.retain(|doc| !ids.contains(doc.id.as_str()));
         store.extend(documents);
         Ok(())
     }
 
-    async fn search(&self, query_vector: Vec<f32>, top_k: usize) -> Result<Vec<SearchResult>> {
+    async fn search(&self, query_vector: Vec<f32>, top_k: usize, filter: &SearchFilter) -> Result<Vec<SearchResult>> {
         let store = self.documents.read().unwrap();
         let mut results: Vec<(f32, &Document)> = store
             .iter()
+            .filter(|doc| filter.matches(&doc.metadata))
             .map(|doc| {
                 let score = cosine_similarity(&query_vector, &doc.embedding);
                 (score, doc)
@@ -220,6 +604,7 @@ impl VectorStore for LocalVectorStore {
             .into_iter()
             .take(top_k)
             .map(|(score, doc)| SearchResult {
+                id: doc.id.clone(),
                 score,
                 metadata: doc.metadata.clone(),
             })
@@ -239,30 +624,208 @@ impl VectorStore for LocalVectorStore {
         Ok(())
     }
 
+    async fn delete_documents(&self, ids: Vec<String>) -> Result<()> {
+        let ids: std::collections::HashSet<String> = ids.into_iter().collect();
+        let mut store = self.documents.write().unwrap();
+        store.retain(|doc| !ids.contains(&doc.id));
+        Ok(())
+    }
+
+    async fn get_documents(&self, ids: &[String]) -> Result<Vec<SearchResult>> {
+        let ids: std::collections::HashSet<&str> = ids.iter().map(String::as_str).collect();
+        let store = self.documents.read().unwrap();
+        
+        Ok(store
+            .iter()
+            .filter(|doc| ids.contains(doc.id.as_str()))
+            .map(|doc| SearchResult {
+                id: doc.id.clone(),
+                score: 0.0,
+                metadata: doc.metadata.clone(),
+            })
+            .collect())
+    }
+
+    /// Pages are positions in insertion order, so concurrent writes may shift them.
+    async fn scroll(&self, offset: Option<String>, limit: usize) -> Result<(Vec<SearchResult>, Option<String>)> {
+        let start: usize = offset.map(|offset| offset.parse()).transpose()?.unwrap_or(0);
+        let store = self.documents.read().unwrap();
+
+        let results = store
+            .iter()
+            .skip(start)
+            .take(limit)
+            .map(|doc| SearchResult {
+                id: doc.id.clone(),
+                score: 0.0,
+                metadata: doc.metadata.clone(),
+            })
+            .collect();
+        let end = start + limit;
+        Ok((results, (end < store.len()).then(|| end.to_string())))
+    }
+
+    async fn schema(&self) -> Result<Option<CollectionSchema>> {
+        Ok(None)
+    }
+
     fn create_document(&self, chunk: CodeChunk, embedding: Vec<f32>) -> Document {
         Document {
-            id: Uuid::new_v4().to_string(),
+            id: chunk_id(&chunk),
             embedding,
-            metadata: HashMap::from([
-                ("file_path".to_string(), json!(chunk.file_path)),
-                ("content".to_string(), json!(chunk.content)),
-                ("line_start".to_string(), json!(chunk.line_start)),
-                ("line_end".to_string(), json!(chunk.line_end)),
-                ("chunk_type".to_string(), json!(format!("{:?}", chunk.chunk_type))),
-                ("language".to_string(), json!(chunk.language)),
-            ]),
+            metadata: chunk_metadata(chunk),
         }
     }
 }
 
+fn point_id_to_string(id: Option<qdrant_client::qdrant::PointId>) -> String {
+    use qdrant_client::qdrant::point_id::PointIdOptions;
+    
+    match id.and_then(|id| id.point_id_options) {
+        Some(PointIdOptions::Uuid(uuid)) => uuid,
+        Some(PointIdOptions::Num(num)) => num.to_string(),
+        None => String::new(),
+    }
+}
+
 pub(crate) fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
-    let dot_product: f32 = a.iter().zip(b.iter()).map(|(x, y)| x * y).sum();
-    let norm_a: f32 = a.iter().map(|x| x * x).sum::<f32>().sqrt();
-    let norm_b: f32 = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let dot_product = dot(a, b);
+    let norm_a = dot(a, a).sqrt();
+    let norm_b = dot(b, b).sqrt();
     
     if norm_a == 0.0 || norm_b == 0.0 {
         0.0
     } else {
         dot_product / (norm_a * norm_b)
     }
-}
\ No newline at end of file
+}
+
+/// Dot product with eight independent accumulators, which lets the compiler
+/// vectorize the loop.
+pub(crate) fn dot(a: &[f32], b: &[f32]) -> f32 {
+    let mut lanes = [0.0f32; 8];
+    let (a_chunks, b_chunks) = (a.chunks_exact(8), b.chunks_exact(8));
+    let tail: f32 = a_chunks
+        .remainder()
+        .iter()
+        .zip(b_chunks.remainder())
+        .map(|(x, y)| x * y)
+        .sum();
+
+    for (x, y) in a_chunks.zip(b_chunks) {
+        for i in 0..8 {
+            lanes[i] += x[i] * y[i];
+        }
+    }
+
+    lanes.iter().sum::<f32>() + tail
+}
+
+/// Reciprocal of the norm of `vector`, or zero for the zero vector.
+pub(crate) fn inverse_norm(vector: &[f32]) -> f32 {
+    let norm = dot(vector, vector).sqrt();
+    if norm > 0.0 {
+        1.0 / norm
+    } else {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use qdrant_client::qdrant::{condition::ConditionOneOf, r#match::MatchValue};
+
+    fn chunk(repo: &str, relative_path: &str, file_path: &str) -> CodeChunk {
+        CodeChunk {
+            file_path: file_path.to_string(),
+            content: "fn handler() {}".to_string(),
+            line_start: 1,
+            line_end: 1,
+            chunk_type: crate::indexer::parser::ChunkType::Function,
+            language: "rs".to_string(),
+            repo: Some(repo.to_string()),
+            commit: None,
+            relative_path: Some(relative_path.to_string()),
+            symbol: Some("handler".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_chunk_ids_are_stable_and_distinct() {
+        let id = chunk_id(&chunk("api", "src/lib.rs", "./src/lib.rs"));
+        // Pinned so that a change to the ID scheme, which orphans every stored chunk, is deliberate.
+        assert_eq!(id, "ce6a9266-0eb6-5543-a36f-5a8a63b6d1b3");
+        // Where the repository was checked out or how the root was spelled does not matter.
+        assert_eq!(chunk_id(&chunk("api", "src/lib.rs", "/home/me/api/src/lib.rs")), id);
+
+        // Nested chunks can share the symbol and context window of their parent.
+        let mut nested = chunk("api", "src/lib.rs", "./src/lib.rs");
+        nested.line_start = 2;
+        nested.line_end = 2;
+        assert_ne!(chunk_id(&nested), id);
+
+        assert_ne!(chunk_id(&chunk("web", "src/lib.rs", "./src/lib.rs")), id);
+        assert_ne!(chunk_id(&chunk("api", "src/main.rs", "./src/main.rs")), id);
+        let mut edited = chunk("api", "src/lib.rs", "./src/lib.rs");
+        edited.content.push('\n');
+        assert_ne!(chunk_id(&edited), id);
+        let mut renamed = chunk("api", "src/lib.rs", "./src/lib.rs");
+        renamed.symbol = Some("other".to_string());
+        assert_ne!(chunk_id(&renamed), id);
+    }
+
+    #[test]
+    fn test_nested_chunks_get_distinct_ids() {
+        let content = "function f() {\n a.map(x => x);\n b.map(x => x);\n}";
+        let chunks = crate::indexer::parser::LanguageParser::new()
+            .parse_content(Path::new("f.js"), content)
+            .unwrap();
+        let ids: std::collections::HashSet<String> = chunks.iter().map(chunk_id).collect();
+        assert!(chunks.len() > 1);
+        assert_eq!(ids.len(), chunks.len());
+    }
+
+    #[test]
+    fn test_search_filter_matches() {
+        let metadata = |repo: &str, language: &str| {
+            HashMap::from([("repo".to_string(), json!(repo)), ("language".to_string(), json!(language))])
+        };
+        let filter = SearchFilter::new(vec!["api".to_string(), String::new()], vec!["javascript".to_string()]);
+
+        assert!(filter.matches(&metadata("api", "jsx")));
+        assert!(filter.matches(&metadata("api", "mjs")));
+        assert!(!filter.matches(&metadata("web", "js")));
+        assert!(!filter.matches(&metadata("api", "rs")));
+        assert!(!filter.matches(&HashMap::new()));
+        assert!(SearchFilter::default().matches(&HashMap::new()));
+    }
+
+    #[test]
+    fn test_search_filter_to_qdrant_filter() {
+        assert!(SearchFilter::default().to_qdrant_filter().is_none());
+
+        let filter = SearchFilter::new(vec!["api".to_string()], vec!["typescript".to_string()]);
+        let conditions: Vec<(String, Vec<String>)> = filter
+            .to_qdrant_filter()
+            .unwrap()
+            .must
+            .into_iter()
+            .map(|condition| match condition.condition_one_of {
+                Some(ConditionOneOf::Field(field)) => match field.r#match.and_then(|m| m.match_value) {
+                    Some(MatchValue::Keywords(keywords)) => (field.key, keywords.strings),
+                    other => panic!("unexpected match {:?}", other),
+                },
+                other => panic!("unexpected condition {:?}", other),
+            })
+            .collect();
+
+        assert_eq!(
+            conditions,
+            vec![
+                ("repo".to_string(), vec!["api".to_string()]),
+                ("language".to_string(), vec!["ts".to_string(), "tsx".to_string()]),
+            ]
+        );
+    }
+}
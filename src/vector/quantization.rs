@@ -0,0 +1,492 @@
+//! Compressed embeddings for the local vector store.
+//!
+//! - `int8` (scalar quantization) maps each dimension linearly onto a byte,
+//!   using the range seen while training. Vectors shrink 4x.
+//! - `pq` (product quantization) splits vectors into subvectors and stores,
+//!   for each one, the index of the closest of 256 centroids learned with
+//!   k-means. With the default of 8 dimensions per subvector vectors shrink
+//!   32x.
+//!
+//! Searches use asymmetric distance computation: the query is kept in full
+//! precision and scored against the codes directly, through per-dimension
+//! weights for `int8` and a table of query-centroid dot products for `pq`.
+//! Every code starts with the reciprocal norm of the original vector, so
+//! cosine similarities need no decoding either.
+
+use super::segment::Cursor;
+use super::{dot, inverse_norm};
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::Write;
+use std::ops::Range;
+use std::path::Path;
+
+const MAGIC: &[u8; 4] = b"BLQZ";
+const FORMAT_VERSION: u32 = 1;
+const SCALAR: u8 = 1;
+const PRODUCT: u8 = 2;
+
+/// Bytes at the start of every code holding the reciprocal norm.
+const NORM_BYTES: usize = 4;
+const CENTROIDS: usize = 256;
+const KMEANS_ITERATIONS: usize = 8;
+/// Dimensions per subvector when the number of subvectors is not configured.
+const DEFAULT_SUBVECTOR_DIMENSIONS: usize = 8;
+
+/// Quantizers are trained on at most this many vectors.
+pub const MAX_TRAINING_VECTORS: usize = 4096;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QuantizationMethod {
+    #[default]
+    None,
+    Int8,
+    Pq,
+}
+
+impl QuantizationMethod {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value {
+            "none" => Ok(Self::None),
+            "int8" => Ok(Self::Int8),
+            "pq" => Ok(Self::Pq),
+            other => bail!("Unknown quantization method '{}' (expected none, int8 or pq)", other),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Quantizer {
+    Scalar(ScalarQuantizer),
+    Product(ProductQuantizer),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScalarQuantizer {
+    min: Vec<f32>,
+    /// Width of one code step per dimension.
+    scale: Vec<f32>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProductQuantizer {
+    dimension: usize,
+    subvectors: usize,
+    /// Centroids per subvector; fewer than 256 when trained on few vectors.
+    centroids: usize,
+    /// `subvectors * centroids` centroids of `dimension / subvectors` values.
+    codebook: Vec<f32>,
+}
+
+/// A query prepared for scoring codes.
+pub struct QuantizedQuery {
+    inverse_norm: f32,
+    kind: PreparedQuery,
+}
+
+enum PreparedQuery {
+    /// `dot(query, decode(code)) = offset + sum(weights[i] * code[i])`.
+    Scalar { offset: f32, weights: Vec<f32> },
+    /// Dot product of each query subvector with each of its centroids.
+    Product { table: Vec<f32>, centroids: usize },
+}
+
+impl Quantizer {
+    /// Trains a quantizer for `method` on a sample of the stored vectors.
+    /// `pq_subvectors` of zero picks one subvector per 8 dimensions.
+    pub fn train(method: QuantizationMethod, pq_subvectors: usize, samples: &[&[f32]]) -> Result<Self> {
+        let Some(dimension) = samples.first().map(|sample| sample.len()) else {
+            bail!("Cannot train a quantizer without vectors");
+        };
+
+        match method {
+            QuantizationMethod::None => bail!("No quantization method selected"),
+            QuantizationMethod::Int8 => Ok(Self::Scalar(ScalarQuantizer::train(dimension, samples))),
+            QuantizationMethod::Pq => {
+                let subvectors = if pq_subvectors == 0 {
+                    // The most subvectors with at least the default width.
+                    (1..=dimension / DEFAULT_SUBVECTOR_DIMENSIONS)
+                        .rev()
+                        .find(|n| dimension.is_multiple_of(*n))
+                        .unwrap_or(1)
+                } else {
+                    pq_subvectors
+                };
+                if subvectors > dimension || !dimension.is_multiple_of(subvectors) {
+                    bail!(
+                        "Cannot split {}-dimensional vectors into {} subvectors; pick a divisor of the dimension",
+                        dimension,
+                        subvectors
+                    );
+                }
+                Ok(Self::Product(ProductQuantizer::train(dimension, subvectors, samples)))
+            }
+        }
+    }
+
+    pub fn method(&self) -> QuantizationMethod {
+        match self {
+            Self::Scalar(_) => QuantizationMethod::Int8,
+            Self::Product(_) => QuantizationMethod::Pq,
+        }
+    }
+
+    pub fn dimension(&self) -> usize {
+        match self {
+            Self::Scalar(scalar) => scalar.min.len(),
+            Self::Product(product) => product.dimension,
+        }
+    }
+
+    /// Size in bytes of one code.
+    pub fn code_size(&self) -> usize {
+        NORM_BYTES
+            + match self {
+                Self::Scalar(scalar) => scalar.min.len(),
+                Self::Product(product) => product.subvectors,
+            }
+    }
+
+    /// Whether `code` could have come from this quantizer: it has the right
+    /// size and, for PQ, names only centroids the codebook has.
+    pub fn is_valid_code(&self, code: &[u8]) -> bool {
+        if code.len() != self.code_size() {
+            return false;
+        }
+        match self {
+            Self::Scalar(_) => true,
+            Self::Product(product) => code[NORM_BYTES..].iter().all(|&centroid| (centroid as usize) < product.centroids),
+        }
+    }
+
+    pub fn encode(&self, vector: &[f32]) -> Vec<u8> {
+        let mut code = Vec::with_capacity(self.code_size());
+        code.extend_from_slice(&inverse_norm(vector).to_le_bytes());
+
+        match self {
+            Self::Scalar(scalar) => {
+                for ((value, min), scale) in vector.iter().zip(&scalar.min).zip(&scalar.scale) {
+                    let step = if *scale > 0.0 { (value - min) / scale } else { 0.0 };
+                    code.push(step.round().clamp(0.0, 255.0) as u8);
+                }
+            }
+            Self::Product(product) => {
+                let sub_dimension = product.sub_dimension();
+                for (subvector, value) in vector.chunks_exact(sub_dimension).enumerate() {
+                    code.push(nearest_centroid(product.codebook(subvector), value) as u8);
+                }
+            }
+        }
+
+        code
+    }
+
+    pub fn decode(&self, code: &[u8]) -> Vec<f32> {
+        let code = &code[NORM_BYTES..];
+        match self {
+            Self::Scalar(scalar) => code
+                .iter()
+                .zip(&scalar.min)
+                .zip(&scalar.scale)
+                .map(|((&step, min), scale)| min + scale * step as f32)
+                .collect(),
+            Self::Product(product) => {
+                let sub_dimension = product.sub_dimension();
+                let mut vector = Vec::with_capacity(product.dimension);
+                for (subvector, &centroid) in code.iter().enumerate() {
+                    let start = centroid as usize * sub_dimension;
+                    vector.extend_from_slice(&product.codebook(subvector)[start..start + sub_dimension]);
+                }
+                vector
+            }
+        }
+    }
+
+    pub fn prepare(&self, query: &[f32]) -> QuantizedQuery {
+        let kind = match self {
+            Self::Scalar(scalar) => PreparedQuery::Scalar {
+                offset: dot(query, &scalar.min),
+                weights: query.iter().zip(&scalar.scale).map(|(q, scale)| q * scale).collect(),
+            },
+            Self::Product(product) => {
+                let sub_dimension = product.sub_dimension();
+                let mut table = Vec::with_capacity(product.subvectors * product.centroids);
+                for (subvector, query) in query.chunks_exact(sub_dimension).enumerate() {
+                    for centroid in product.codebook(subvector).chunks_exact(sub_dimension) {
+                        table.push(dot(query, centroid));
+                    }
+                }
+                PreparedQuery::Product {
+                    table,
+                    centroids: product.centroids,
+                }
+            }
+        };
+
+        QuantizedQuery {
+            inverse_norm: inverse_norm(query),
+            kind,
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let mut data = Vec::new();
+        data.extend_from_slice(MAGIC);
+        data.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+
+        let floats: Vec<&[f32]> = match self {
+            Self::Scalar(scalar) => {
+                data.push(SCALAR);
+                data.extend_from_slice(&(scalar.min.len() as u32).to_le_bytes());
+                vec![&scalar.min, &scalar.scale]
+            }
+            Self::Product(product) => {
+                data.push(PRODUCT);
+                for value in [product.dimension, product.subvectors, product.centroids] {
+                    data.extend_from_slice(&(value as u32).to_le_bytes());
+                }
+                vec![&product.codebook]
+            }
+        };
+        for value in floats.into_iter().flatten() {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+
+        let tmp_path = path.with_extension("tmp");
+        let mut file = fs::File::create(&tmp_path)?;
+        file.write_all(&data)?;
+        file.sync_data()?;
+        fs::rename(&tmp_path, path)?;
+
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let data = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        Self::decode_file(&data).with_context(|| format!("Corrupt quantizer in {}", path.display()))
+    }
+
+    fn decode_file(data: &[u8]) -> Option<Self> {
+        let mut cursor = Cursor::new(data);
+        if cursor.take(4)? != MAGIC || cursor.u32()? != FORMAT_VERSION {
+            return None;
+        }
+
+        match cursor.u8()? {
+            SCALAR => {
+                let dimension = cursor.u32()? as usize;
+                Some(Self::Scalar(ScalarQuantizer {
+                    min: cursor.f32s(dimension)?,
+                    scale: cursor.f32s(dimension)?,
+                }))
+            }
+            PRODUCT => {
+                let dimension = cursor.u32()? as usize;
+                let subvectors = cursor.u32()? as usize;
+                let centroids = cursor.u32()? as usize;
+                if subvectors == 0 || !dimension.is_multiple_of(subvectors) || centroids == 0 || centroids > CENTROIDS {
+                    return None;
+                }
+                Some(Self::Product(ProductQuantizer {
+                    dimension,
+                    subvectors,
+                    centroids,
+                    codebook: cursor.f32s(centroids * dimension)?,
+                }))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl QuantizedQuery {
+    /// Approximate dot product of the query with the vector behind `code`.
+    pub fn dot(&self, code: &[u8]) -> f32 {
+        let steps = &code[NORM_BYTES..];
+        match &self.kind {
+            PreparedQuery::Scalar { offset, weights } => {
+                let mut lanes = [0.0f32; 8];
+                let (weight_chunks, step_chunks) = (weights.chunks_exact(8), steps.chunks_exact(8));
+                let tail: f32 = weight_chunks
+                    .remainder()
+                    .iter()
+                    .zip(step_chunks.remainder())
+                    .map(|(w, &s)| w * s as f32)
+                    .sum();
+                for (w, s) in weight_chunks.zip(step_chunks) {
+                    for i in 0..8 {
+                        lanes[i] += w[i] * s[i] as f32;
+                    }
+                }
+                offset + lanes.iter().sum::<f32>() + tail
+            }
+            PreparedQuery::Product { table, centroids } => steps
+                .iter()
+                .enumerate()
+                .map(|(subvector, &centroid)| table[subvector * centroids + centroid as usize])
+                .sum(),
+        }
+    }
+
+    /// Approximate cosine similarity of the query with the vector behind `code`.
+    pub fn similarity(&self, code: &[u8]) -> f32 {
+        let code_inverse_norm = f32::from_le_bytes(code[..NORM_BYTES].try_into().unwrap());
+        self.dot(code) * self.inverse_norm * code_inverse_norm
+    }
+}
+
+impl ScalarQuantizer {
+    fn train(dimension: usize, samples: &[&[f32]]) -> Self {
+        let mut min = vec![f32::MAX; dimension];
+        let mut max = vec![f32::MIN; dimension];
+        for sample in samples {
+            for (i, &value) in sample.iter().enumerate().take(dimension) {
+                min[i] = min[i].min(value);
+                max[i] = max[i].max(value);
+            }
+        }
+
+        let scale = min.iter().zip(&max).map(|(min, max)| (max - min) / 255.0).collect();
+        Self { min, scale }
+    }
+}
+
+impl ProductQuantizer {
+    fn train(dimension: usize, subvectors: usize, samples: &[&[f32]]) -> Self {
+        let sub_dimension = dimension / subvectors;
+        let centroids = CENTROIDS.min(samples.len());
+        let mut codebook = vec![0.0; subvectors * centroids * sub_dimension];
+
+        // Subvectors are clustered independently, so spread them over threads.
+        let threads = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+        let per_thread = subvectors.div_ceil(threads);
+        let stride = centroids * sub_dimension;
+        std::thread::scope(|scope| {
+            for (batch, codebooks) in codebook.chunks_mut(per_thread * stride).enumerate() {
+                scope.spawn(move || {
+                    for (offset, codebook) in codebooks.chunks_mut(stride).enumerate() {
+                        let start = (batch * per_thread + offset) * sub_dimension;
+                        kmeans(samples, start..start + sub_dimension, codebook);
+                    }
+                });
+            }
+        });
+
+        Self {
+            dimension,
+            subvectors,
+            centroids,
+            codebook,
+        }
+    }
+
+    fn sub_dimension(&self) -> usize {
+        self.dimension / self.subvectors
+    }
+
+    /// Centroids of one subvector, back to back.
+    fn codebook(&self, subvector: usize) -> &[f32] {
+        let stride = self.centroids * self.sub_dimension();
+        &self.codebook[subvector * stride..(subvector + 1) * stride]
+    }
+}
+
+/// Lloyd's k-means over `range` of each sample, writing the centroids into
+/// `codebook`. Centroids start at evenly spaced samples so training is
+/// deterministic.
+fn kmeans(samples: &[&[f32]], range: Range<usize>, codebook: &mut [f32]) {
+    let dimension = range.len();
+    let centroids = codebook.len() / dimension;
+    for (i, centroid) in codebook.chunks_exact_mut(dimension).enumerate() {
+        centroid.copy_from_slice(&samples[i * samples.len() / centroids][range.clone()]);
+    }
+
+    let mut sums = vec![0.0f32; codebook.len()];
+    let mut counts = vec![0usize; centroids];
+    for _ in 0..KMEANS_ITERATIONS {
+        sums.fill(0.0);
+        counts.fill(0);
+        for sample in samples {
+            let value = &sample[range.clone()];
+            let nearest = nearest_centroid(codebook, value);
+            counts[nearest] += 1;
+            for (sum, v) in sums[nearest * dimension..(nearest + 1) * dimension].iter_mut().zip(value) {
+                *sum += v;
+            }
+        }
+
+        // Empty clusters keep their previous centroid.
+        for (i, centroid) in codebook.chunks_exact_mut(dimension).enumerate() {
+            if counts[i] > 0 {
+                for (value, sum) in centroid.iter_mut().zip(&sums[i * dimension..(i + 1) * dimension]) {
+                    *value = sum / counts[i] as f32;
+                }
+            }
+        }
+    }
+}
+
+/// Index of the centroid in `codebook` closest to `value` by Euclidean distance.
+fn nearest_centroid(codebook: &[f32], value: &[f32]) -> usize {
+    codebook
+        .chunks_exact(value.len())
+        .map(|centroid| centroid.iter().zip(value).map(|(c, v)| (c - v) * (c - v)).sum::<f32>())
+        .enumerate()
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(i, _)| i)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vector::cosine_similarity;
+
+    #[test]
+    fn test_adc_approximates_cosine() {
+        let vectors: Vec<Vec<f32>> = (0..600)
+            .map(|i| (0..32).map(|d| ((i * 31 + d * 7) % 97) as f32 / 97.0 - 0.5).collect())
+            .collect();
+        let samples: Vec<&[f32]> = vectors.iter().map(Vec::as_slice).collect();
+        let query = &vectors[42];
+
+        let dir = tempfile::tempdir().unwrap();
+        for (method, tolerance) in [(QuantizationMethod::Int8, 0.01), (QuantizationMethod::Pq, 0.1)] {
+            let quantizer = Quantizer::train(method, 0, &samples).unwrap();
+            let path = dir.path().join("quantizer.bin");
+            quantizer.save(&path).unwrap();
+            let quantizer = Quantizer::load(&path).unwrap();
+
+            let prepared = quantizer.prepare(query);
+            for vector in vectors.iter().step_by(50) {
+                let code = quantizer.encode(vector);
+                assert_eq!(code.len(), quantizer.code_size());
+                let exact = cosine_similarity(query, vector);
+                assert!((prepared.similarity(&code) - exact).abs() < tolerance, "{:?}", method);
+                assert!((cosine_similarity(&quantizer.decode(&code), vector) - 1.0).abs() < tolerance);
+            }
+        }
+    }
+
+    #[test]
+    fn test_rejects_corrupt_product_quantizers() {
+        let mut data = MAGIC.to_vec();
+        data.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        data.push(PRODUCT);
+        for value in [8u32, 2, 0] {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        assert!(Quantizer::decode_file(&data).is_none());
+
+        let samples: Vec<Vec<f32>> = (0..4).map(|i| (0..8).map(|d| (i * 8 + d) as f32).collect()).collect();
+        let samples: Vec<&[f32]> = samples.iter().map(Vec::as_slice).collect();
+        let quantizer = Quantizer::train(QuantizationMethod::Pq, 2, &samples).unwrap();
+        let mut code = quantizer.encode(samples[0]);
+        assert!(quantizer.is_valid_code(&code));
+        code[NORM_BYTES] = 255;
+        assert!(!quantizer.is_valid_code(&code));
+        assert!(!quantizer.is_valid_code(&code[1..]));
+    }
+}
@@ -0,0 +1,568 @@
+//! On-disk format of the persistent local vector store.
+//!
+//! A store generation consists of these files:
+//!
+//! - `vectors.<gen>.bin`: embeddings as raw little-endian `f32`s, one
+//!   fixed-size slot per document, memory-mapped for search. Left out when the
+//!   store keeps quantized vectors only.
+//! - `codes.<gen>.bin` and `quantizer.<gen>.bin`: quantized embeddings in the
+//!   same slots, and the quantizer that encodes them; see
+//!   [`quantization`](super::quantization).
+//! - `segments.<gen>.log`: a log of length-prefixed `Put`/`Delete` records.
+//!   A `Put` carries the document's slot, the fields needed for filtering and
+//!   deletion, and its JSON metadata, which is only read when a result is
+//!   returned.
+//! - `hnsw.<gen>.bin`: a checkpoint of the HNSW graph over the vector slots.
+//!   Slots added after the checkpoint are inserted again when the store opens.
+//!
+//! Compaction writes the live documents into the next generation and then
+//! switches `meta.json` over to it, so a crash never leaves a mixed state.
+//!
+//! Only one process writes a store at a time. Others open its files read-only
+//! and leave a torn tail alone, since it may be a record still being written.
+
+use anyhow::{bail, Context, Result};
+use memmap2::Mmap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+const PUT: u8 = 1;
+const DELETE: u8 = 2;
+
+/// Fields of a `Put` record kept in memory for filtering and deletion.
+#[derive(Debug, Clone)]
+pub struct PutRecord {
+    pub id: String,
+    pub slot: u32,
+    pub file_path: String,
+    pub repo: Option<String>,
+    pub language: String,
+    /// Location of the JSON metadata within the log.
+    pub metadata_offset: u64,
+    pub metadata_len: u32,
+}
+
+#[derive(Debug, Clone)]
+pub enum Record {
+    Put(PutRecord),
+    Delete(String),
+}
+
+pub fn vectors_path(dir: &Path, generation: u64) -> PathBuf {
+    dir.join(format!("vectors.{}.bin", generation))
+}
+
+pub fn log_path(dir: &Path, generation: u64) -> PathBuf {
+    dir.join(format!("segments.{}.log", generation))
+}
+
+pub fn hnsw_path(dir: &Path, generation: u64) -> PathBuf {
+    dir.join(format!("hnsw.{}.bin", generation))
+}
+
+pub fn codes_path(dir: &Path, generation: u64) -> PathBuf {
+    dir.join(format!("codes.{}.bin", generation))
+}
+
+pub fn quantizer_path(dir: &Path, generation: u64) -> PathBuf {
+    dir.join(format!("quantizer.{}.bin", generation))
+}
+
+/// Every file of a generation, as produced by the functions above.
+fn generation_paths(dir: &Path, generation: u64) -> [PathBuf; 5] {
+    [
+        vectors_path(dir, generation),
+        log_path(dir, generation),
+        hnsw_path(dir, generation),
+        codes_path(dir, generation),
+        quantizer_path(dir, generation),
+    ]
+}
+
+/// Append-only record log.
+pub struct SegmentLog {
+    writer: BufWriter<File>,
+    reader: Mutex<File>,
+    len: u64,
+}
+
+impl SegmentLog {
+    /// Opens (or creates) the log at `path` and replays it. A record torn by
+    /// a crash at the end of the log is discarded, and cut off unless
+    /// `read_only` is set. Records are streamed from disk and their metadata
+    /// skipped, so replay never holds the whole log.
+    pub fn open(path: &Path, read_only: bool) -> Result<(Self, Vec<Record>)> {
+        let file = OpenOptions::new()
+            .create(!read_only)
+            .read(true)
+            .append(!read_only)
+            .open(path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+
+        let file_len = file.metadata()?.len();
+        let mut reader = BufReader::new(&file);
+        let mut records = Vec::new();
+        let mut offset = 0;
+        while let Some((record, next)) = read_record(&mut reader, offset, file_len)? {
+            records.push(record);
+            offset = next;
+        }
+
+        if offset < file_len && !read_only {
+            tracing::warn!("Discarding {} bytes of torn records in {}", file_len - offset, path.display());
+            file.set_len(offset)?;
+        }
+
+        let reader = Mutex::new(File::open(path)?);
+        let log = Self {
+            writer: BufWriter::new(file),
+            reader,
+            len: offset,
+        };
+
+        Ok((log, records))
+    }
+
+    /// Appends a `Put` record and returns it with the metadata location filled in.
+    pub fn put(
+        &mut self,
+        id: &str,
+        slot: u32,
+        file_path: &str,
+        repo: Option<&str>,
+        language: &str,
+        metadata: &[u8],
+    ) -> Result<PutRecord> {
+        let mut body = vec![PUT];
+        body.extend_from_slice(&slot.to_le_bytes());
+        for field in [id, file_path, repo.unwrap_or(""), language] {
+            write_str(&mut body, field);
+        }
+        body.extend_from_slice(&(metadata.len() as u32).to_le_bytes());
+        let metadata_start = body.len();
+        body.extend_from_slice(metadata);
+
+        let record_offset = self.append(&body)?;
+
+        Ok(PutRecord {
+            id: id.to_string(),
+            slot,
+            file_path: file_path.to_string(),
+            repo: repo.map(String::from),
+            language: language.to_string(),
+            metadata_offset: record_offset + 4 + metadata_start as u64,
+            metadata_len: metadata.len() as u32,
+        })
+    }
+
+    pub fn delete(&mut self, id: &str) -> Result<()> {
+        let mut body = vec![DELETE];
+        write_str(&mut body, id);
+        self.append(&body)?;
+        Ok(())
+    }
+
+    /// Makes appended records visible to readers.
+    pub fn flush(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    pub fn sync(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        self.writer.get_ref().sync_data()?;
+        Ok(())
+    }
+
+    /// Reads the JSON metadata of a record appended before the last flush.
+    pub fn read_metadata(&self, record: &PutRecord) -> Result<Vec<u8>> {
+        let mut buffer = vec![0; record.metadata_len as usize];
+        let mut reader = self.reader.lock().unwrap();
+        reader.seek(SeekFrom::Start(record.metadata_offset))?;
+        reader.read_exact(&mut buffer)?;
+        Ok(buffer)
+    }
+
+    fn append(&mut self, body: &[u8]) -> Result<u64> {
+        let offset = self.len;
+        self.writer.write_all(&(body.len() as u32).to_le_bytes())?;
+        self.writer.write_all(body)?;
+        self.len += 4 + body.len() as u64;
+        Ok(offset)
+    }
+}
+
+/// Append-only file of fixed-size binary slots, memory-mapped for reading.
+pub struct SlotFile {
+    file: File,
+    mmap: Option<Mmap>,
+    slot_size: usize,
+    slots: u32,
+}
+
+impl SlotFile {
+    /// Opens (or creates) the file at `path`. A partially written trailing
+    /// slot is ignored, and cut off unless `read_only` is set.
+    pub fn open(path: &Path, slot_size: usize, read_only: bool) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(!read_only)
+            .read(true)
+            .append(!read_only)
+            .open(path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+
+        let len = file.metadata()?.len();
+        if len % slot_size as u64 != 0 && !read_only {
+            file.set_len(len - len % slot_size as u64)?;
+        }
+
+        let mut slots = Self {
+            file,
+            mmap: None,
+            slot_size,
+            slots: (len / slot_size as u64) as u32,
+        };
+        slots.remap()?;
+
+        Ok(slots)
+    }
+
+    pub fn slot_size(&self) -> usize {
+        self.slot_size
+    }
+
+    /// Number of slots in the file, including ones no longer referenced.
+    pub fn slots(&self) -> u32 {
+        self.slots
+    }
+
+    /// Appends whole slots and returns the index of the first one. Call
+    /// [`remap`](Self::remap) before reading them back.
+    pub fn append(&mut self, bytes: &[u8]) -> Result<u32> {
+        if !bytes.len().is_multiple_of(self.slot_size) {
+            bail!("Slot data of {} bytes is not a multiple of the slot size {}", bytes.len(), self.slot_size);
+        }
+
+        let first = self.slots;
+        self.file.write_all(bytes)?;
+        self.slots += (bytes.len() / self.slot_size) as u32;
+        Ok(first)
+    }
+
+    /// Drops every slot from `slots` on, e.g. ones a crash left without a
+    /// counterpart in another file.
+    pub fn truncate(&mut self, slots: u32) -> Result<()> {
+        if slots >= self.slots {
+            return Ok(());
+        }
+        self.mmap = None;
+        self.file.set_len(slots as u64 * self.slot_size as u64)?;
+        self.slots = slots;
+        self.remap()
+    }
+
+    pub fn sync(&self) -> Result<()> {
+        self.file.sync_data()?;
+        Ok(())
+    }
+
+    pub fn remap(&mut self) -> Result<()> {
+        self.mmap = if self.slots == 0 {
+            None
+        } else {
+            // Safety: the file is only ever appended to by the writer and
+            // replaced wholesale on compaction. It is only truncated through
+            // `truncate`, which drops the mapping first, or by a writer
+            // repairing it after a crash, which only cuts slots past the ones
+            // readers use.
+            Some(unsafe { Mmap::map(&self.file)? })
+        };
+        Ok(())
+    }
+
+    pub fn get(&self, slot: u32) -> Option<&[u8]> {
+        let start = slot as usize * self.slot_size;
+        self.mmap.as_ref()?.get(start..start + self.slot_size)
+    }
+}
+
+/// Slot file of `f32` vectors of a fixed dimension.
+pub struct VectorFile {
+    slots: SlotFile,
+    dimension: usize,
+}
+
+impl VectorFile {
+    pub fn open(path: &Path, dimension: usize, read_only: bool) -> Result<Self> {
+        Ok(Self {
+            slots: SlotFile::open(path, dimension * 4, read_only)?,
+            dimension,
+        })
+    }
+
+    pub fn dimension(&self) -> usize {
+        self.dimension
+    }
+
+    /// Number of slots in the file, including ones no longer referenced.
+    pub fn slots(&self) -> u32 {
+        self.slots.slots()
+    }
+
+    /// Appends `vectors` and returns the slot of the first one. Call
+    /// [`remap`](Self::remap) before reading them back.
+    pub fn append(&mut self, vectors: &[&[f32]]) -> Result<u32> {
+        let mut bytes = Vec::with_capacity(vectors.len() * self.dimension * 4);
+        for vector in vectors {
+            check_dimension(vector, self.dimension)?;
+            for value in *vector {
+                bytes.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+
+        self.slots.append(&bytes)
+    }
+
+    pub fn truncate(&mut self, slots: u32) -> Result<()> {
+        self.slots.truncate(slots)
+    }
+
+    pub fn sync(&self) -> Result<()> {
+        self.slots.sync()
+    }
+
+    pub fn remap(&mut self) -> Result<()> {
+        self.slots.remap()
+    }
+
+    /// The vector stored in `slot`.
+    pub fn get(&self, slot: u32) -> Option<&[f32]> {
+        let bytes = self.slots.get(slot)?;
+
+        // Safety: any bit pattern is a valid f32. The mapping is page aligned
+        // and slots are a multiple of 4 bytes, so the prefix is always empty.
+        let (prefix, floats, _) = unsafe { bytes.align_to::<f32>() };
+        if !prefix.is_empty() || !cfg!(target_endian = "little") {
+            return None;
+        }
+        Some(floats)
+    }
+}
+
+pub fn check_dimension(vector: &[f32], dimension: usize) -> Result<()> {
+    if vector.len() != dimension {
+        bail!(
+            "Embedding has {} dimensions but the local store holds {}-dimensional vectors; re-index into a new collection",
+            vector.len(),
+            dimension
+        );
+    }
+    Ok(())
+}
+
+/// Removes any files of `generation`, e.g. left over from an interrupted compaction.
+pub fn remove_generation(dir: &Path, generation: u64) -> Result<()> {
+    for path in generation_paths(dir, generation) {
+        if path.exists() {
+            fs::remove_file(&path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Removes the files of every generation other than `keep`.
+pub fn remove_stale_generations(dir: &Path, keep: u64) -> Result<()> {
+    let current = generation_paths(dir, keep);
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+        let is_generation_file = [
+            ("vectors.", ".bin"),
+            ("segments.", ".log"),
+            ("hnsw.", ".bin"),
+            ("codes.", ".bin"),
+            ("quantizer.", ".bin"),
+        ]
+        .iter()
+        .any(|(prefix, suffix)| name.starts_with(prefix) && name.ends_with(suffix));
+        if is_generation_file && !current.contains(&path) {
+            fs::remove_file(&path)?;
+        }
+    }
+    Ok(())
+}
+
+fn write_str(buffer: &mut Vec<u8>, value: &str) {
+    buffer.extend_from_slice(&(value.len() as u32).to_le_bytes());
+    buffer.extend_from_slice(value.as_bytes());
+}
+
+/// Reads the record starting at `offset`, where `reader` is positioned, and
+/// leaves `reader` at the next one. The metadata of a `Put` is skipped over.
+/// Returns `None` at the end of the log or on a torn record.
+fn read_record<R: Read + Seek>(reader: &mut R, offset: u64, log_len: u64) -> Result<Option<(Record, u64)>> {
+    if log_len - offset < 4 {
+        return Ok(None);
+    }
+    let mut len = [0; 4];
+    reader.read_exact(&mut len)?;
+    let end = offset + 4 + u32::from_le_bytes(len) as u64;
+    if end > log_len {
+        return Ok(None);
+    }
+
+    let mut body = RecordReader {
+        inner: reader,
+        remaining: end - offset - 4,
+    };
+    let Some(kind) = body.u8()? else {
+        return Ok(None);
+    };
+    let record = match kind {
+        PUT => {
+            let Some(slot) = body.u32()? else { return Ok(None) };
+            let Some(id) = body.string()? else { return Ok(None) };
+            let Some(file_path) = body.string()? else { return Ok(None) };
+            let Some(repo) = body.string()? else { return Ok(None) };
+            let Some(language) = body.string()? else { return Ok(None) };
+            let Some(metadata_len) = body.u32()? else { return Ok(None) };
+            if body.remaining != metadata_len as u64 {
+                return Ok(None);
+            }
+
+            Record::Put(PutRecord {
+                id,
+                slot,
+                file_path,
+                repo: (!repo.is_empty()).then_some(repo),
+                language,
+                metadata_offset: end - metadata_len as u64,
+                metadata_len,
+            })
+        }
+        DELETE => match body.string()? {
+            Some(id) => Record::Delete(id),
+            None => return Ok(None),
+        },
+        _ => return Ok(None),
+    };
+
+    // Leave the metadata (or anything else past the decoded fields) on disk.
+    let skip = body.remaining;
+    reader.seek_relative(skip as i64)?;
+    Ok(Some((record, end)))
+}
+
+/// Reads the fields of one record body, returning `None` for a field that
+/// would run past the end of the body.
+struct RecordReader<'a, R> {
+    inner: &'a mut R,
+    remaining: u64,
+}
+
+impl<R: Read> RecordReader<'_, R> {
+    fn bytes(&mut self, len: u64) -> Result<Option<Vec<u8>>> {
+        if len > self.remaining {
+            return Ok(None);
+        }
+        let mut buffer = vec![0; len as usize];
+        self.inner.read_exact(&mut buffer)?;
+        self.remaining -= len;
+        Ok(Some(buffer))
+    }
+
+    fn u8(&mut self) -> Result<Option<u8>> {
+        Ok(self.bytes(1)?.map(|bytes| bytes[0]))
+    }
+
+    fn u32(&mut self) -> Result<Option<u32>> {
+        Ok(self.bytes(4)?.map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap())))
+    }
+
+    fn string(&mut self) -> Result<Option<String>> {
+        let Some(len) = self.u32()? else {
+            return Ok(None);
+        };
+        Ok(self.bytes(len as u64)?.and_then(|bytes| String::from_utf8(bytes).ok()))
+    }
+}
+
+/// Reads little-endian values from a byte buffer, returning `None` once it
+/// runs out.
+pub struct Cursor<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    pub fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.data.get(self.pos..self.pos.checked_add(len)?)?;
+        self.pos += len;
+        Some(bytes)
+    }
+
+    pub fn u8(&mut self) -> Option<u8> {
+        Some(self.take(1)?[0])
+    }
+
+    pub fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    pub fn f32s(&mut self, count: usize) -> Option<Vec<f32>> {
+        let bytes = self.take(count.checked_mul(4)?)?;
+        Some(
+            bytes
+                .chunks_exact(4)
+                .map(|chunk| f32::from_le_bytes(chunk.try_into().unwrap()))
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replays_log_and_drops_torn_tail() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = log_path(dir.path(), 0);
+
+        let (mut log, records) = SegmentLog::open(&path, false).unwrap();
+        assert!(records.is_empty());
+        log.put("a", 0, "a.rs", Some("api"), "rs", br#"{"content":"fn a() {}"}"#).unwrap();
+        log.put("b", 1, "b.rs", None, "rs", b"{}").unwrap();
+        log.delete("a").unwrap();
+        log.sync().unwrap();
+        drop(log);
+        let intact = fs::metadata(&path).unwrap().len();
+
+        // A crash halfway through appending a record.
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&[64, 0, 0, 0, PUT, 2]).unwrap();
+        drop(file);
+
+        // A reader leaves the tail for the writer, which cuts it off.
+        let (_, records) = SegmentLog::open(&path, true).unwrap();
+        assert_eq!(records.len(), 3);
+        assert_eq!(fs::metadata(&path).unwrap().len(), intact + 6);
+
+        let (log, records) = SegmentLog::open(&path, false).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), intact);
+        assert_eq!(records.len(), 3);
+        let Record::Put(put) = &records[0] else {
+            panic!("expected a put, got {:?}", records[0]);
+        };
+        assert_eq!((put.id.as_str(), put.slot, put.repo.as_deref()), ("a", 0, Some("api")));
+        assert_eq!(log.read_metadata(put).unwrap(), br#"{"content":"fn a() {}"}"#);
+        assert!(matches!(&records[1], Record::Put(put) if put.id == "b" && put.repo.is_none()));
+        assert!(matches!(&records[2], Record::Delete(id) if id == "a"));
+    }
+}